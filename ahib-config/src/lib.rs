@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 use amd_apcb::Apcb;
 use serde::Deserialize;
+use zerocopy::IntoBytes;
 
 use amd_efs::flash::Location;
 use amd_efs::{
@@ -19,6 +20,20 @@ use amd_efs::{
     ValueOrLocation,
 };
 
+mod source_span;
+pub use source_span::{SourceSpan, render_json5_location};
+
+pub mod raw_entry;
+
+mod policy;
+pub use policy::{SerdePolicy, SerdePolicyProfile};
+
+mod soft_fuse_chain;
+pub use soft_fuse_chain::{
+    SerdePspSoftFuseChain, SoftFuseField, invalid_soft_fuse_fields,
+    soft_fuse_fields,
+};
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum Error {
@@ -32,6 +47,10 @@ pub enum Error {
     ImageTooBig,
     #[error("psp entry source {0} unknown")]
     PspEntrySourceUnknown(PspDirectoryEntryType),
+    #[error("soft fuse bits {0:#x} are named, but given as unknown_bits")]
+    SoftFuseBitsNotUnknown(u64),
+    #[error("entry attribute {0} is out of range: {1}")]
+    EntryAttributeOutOfRange(&'static str, u8),
 }
 
 impl From<amd_efs::Error> for Error {
@@ -52,11 +71,123 @@ pub struct SerdePspDirectoryEntryBlob {
     pub size: Option<u32>, // FIXME u64
 }
 
+/// Deserializes either a known entry type T (by name) or an unknown entry
+/// type (by number).
+struct EntryTypeVisitor<T>(std::marker::PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> serde::de::Visitor<'de> for EntryTypeVisitor<T> {
+    type Value = core::result::Result<T, u8>;
+
+    fn expecting(
+        &self,
+        formatter: &mut std::fmt::Formatter,
+    ) -> std::fmt::Result {
+        formatter.write_str("an entry type name or an entry type number")
+    }
+
+    fn visit_str<E>(self, value: &str) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        T::deserialize(serde::de::value::StrDeserializer::<E>::new(value))
+            .map(Ok)
+    }
+
+    fn visit_u64<E>(self, value: u64) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        u8::try_from(value).map(Err).map_err(|_| {
+            E::invalid_value(
+                serde::de::Unexpected::Unsigned(value),
+                &"an entry type number < 0x100",
+            )
+        })
+    }
+
+    fn visit_i64<E>(self, value: i64) -> std::result::Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        u8::try_from(value).map(Err).map_err(|_| {
+            E::invalid_value(
+                serde::de::Unexpected::Signed(value),
+                &"an entry type number < 0x100",
+            )
+        })
+    }
+}
+
+/// The type of a PSP directory entry. Types that amd-efs doesn't know (yet)
+/// are kept as a raw number so that they survive dump and generate.
+#[derive(Clone, Copy, Debug, PartialEq, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum SerdePspDirectoryEntryType {
+    Known(PspDirectoryEntryType),
+    Unknown(u8),
+}
+
+impl From<PspDirectoryEntryType> for SerdePspDirectoryEntryType {
+    fn from(typ: PspDirectoryEntryType) -> Self {
+        Self::Known(typ)
+    }
+}
+
+impl From<&PspDirectoryEntry> for SerdePspDirectoryEntryType {
+    fn from(entry: &PspDirectoryEntry) -> Self {
+        match entry.typ_or_err() {
+            Ok(typ) => Self::Known(typ),
+            Err(_) => Self::Unknown(entry.as_bytes()[0]),
+        }
+    }
+}
+
+impl PartialEq<PspDirectoryEntryType> for SerdePspDirectoryEntryType {
+    fn eq(&self, other: &PspDirectoryEntryType) -> bool {
+        *self == Self::Known(*other)
+    }
+}
+
+impl std::fmt::Display for SerdePspDirectoryEntryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Known(typ) => write!(f, "{typ}"),
+            Self::Unknown(typ) => write!(f, "0x{typ:02x}"),
+        }
+    }
+}
+
+impl serde::ser::Serialize for SerdePspDirectoryEntryType {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        match self {
+            Self::Known(typ) => serde::Serialize::serialize(typ, serializer),
+            Self::Unknown(typ) => serializer.serialize_u8(*typ),
+        }
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for SerdePspDirectoryEntryType {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        Ok(deserializer
+            .deserialize_any(EntryTypeVisitor(std::marker::PhantomData))?
+            .map_or_else(Self::Unknown, Self::Known))
+    }
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct SerdePspDirectoryEntryAttrs {
     #[serde(rename = "type")]
-    pub type_: PspDirectoryEntryType,
+    pub type_: SerdePspDirectoryEntryType,
     /// Function of AMD Family and Model; only useful for types 8, 0x24, 0x25
     #[serde(default)]
     pub sub_program: u8,
@@ -93,31 +224,66 @@ impl TryFromSerdeDirectoryEntryWithContext<SerdePspDirectoryEntry>
         target: &SerdePspDirectoryEntry,
     ) -> Result<Self> {
         let blob = target.blob.as_ref();
-        Ok(Self::new_payload(
-            directory_address_mode,
-            target.attrs.type_,
-            blob.and_then(|y| y.size),
-            blob.and_then(|x| {
-                x.flash_location.map(ValueOrLocation::EfsRelativeOffset)
-            }),
-        )?
-        .with_instance(target.attrs.instance)
-        .with_sub_program(target.attrs.sub_program)
-        .with_rom_id(target.attrs.rom_id)
-        .build())
+        let size = blob.and_then(|y| y.size);
+        let source = blob.and_then(|x| {
+            x.flash_location.map(ValueOrLocation::EfsRelativeOffset)
+        });
+        match target.attrs.type_ {
+            SerdePspDirectoryEntryType::Known(typ) => Ok(Self::new_payload(
+                directory_address_mode,
+                typ,
+                size,
+                source,
+            )?
+            .with_instance(target.attrs.instance)
+            .with_sub_program(target.attrs.sub_program)
+            .with_rom_id(target.attrs.rom_id)
+            .build()),
+            SerdePspDirectoryEntryType::Unknown(typ) => {
+                let mut entry =
+                    raw_entry::psp_directory_entry(typ, &target.attrs)?;
+                entry.set_size(size);
+                if let Some(source) = source {
+                    entry.set_source(directory_address_mode, source)?;
+                }
+                Ok(entry)
+            }
+        }
     }
 }
 
-#[derive(Clone, serde::Serialize, schemars::JsonSchema)]
+#[derive(Clone, schemars::JsonSchema)]
 #[serde(rename = "SerdePspEntrySourceValue")]
 #[serde(deny_unknown_fields)]
 #[non_exhaustive]
 pub enum SerdePspEntrySourceValue {
-    PspSoftFuseChain(PspSoftFuseChain),
+    PspSoftFuseChain(SerdePspSoftFuseChain),
     #[serde(deserialize_with = "deserialize_raw")]
     Unknown(u64),
 }
 
+// Note: Has to match the Deserialize impl below (so a config we serialized
+// can be read back in).
+impl serde::ser::Serialize for SerdePspEntrySourceValue {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        match self {
+            Self::PspSoftFuseChain(x) => serializer.serialize_newtype_variant(
+                "SerdePspEntrySourceValue",
+                0,
+                "PspSoftFuseChain",
+                x,
+            ),
+            Self::Unknown(x) => serializer.serialize_u64(*x),
+        }
+    }
+}
+
 impl<'de> serde::de::Deserialize<'de> for SerdePspEntrySourceValue {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
@@ -174,7 +340,7 @@ impl<'de> serde::de::Deserialize<'de> for SerdePspEntrySourceValue {
                     match key.as_str() {
                         "PspSoftFuseChain" => {
                             Ok(SerdePspEntrySourceValue::PspSoftFuseChain(
-                                map.next_value::<PspSoftFuseChain>()?,
+                                map.next_value::<SerdePspSoftFuseChain>()?,
                             ))
                         }
                         _ => Err(serde::de::Error::custom(
@@ -194,10 +360,13 @@ impl<'de> serde::de::Deserialize<'de> for SerdePspEntrySourceValue {
 }
 
 impl SerdePspEntrySourceValue {
-    pub fn from_u64(value: u64, typ: PspDirectoryEntryType) -> Self {
-        match typ {
-            PspDirectoryEntryType::PspSoftFuseChain => {
-                Self::PspSoftFuseChain(PspSoftFuseChain::from(value))
+    pub fn from_u64(
+        value: u64,
+        typ_or_err: std::result::Result<PspDirectoryEntryType, amd_efs::Error>,
+    ) -> Self {
+        match typ_or_err {
+            Ok(PspDirectoryEntryType::PspSoftFuseChain) => {
+                Self::PspSoftFuseChain(SerdePspSoftFuseChain::from(value))
             }
             _ => SerdePspEntrySourceValue::Unknown(value),
         }
@@ -213,7 +382,7 @@ impl SerdePspEntrySourceValue {
             let typ = typ_or_err.unwrap();
             match typ {
                 PspDirectoryEntryType::PspSoftFuseChain => match self {
-                    Self::PspSoftFuseChain(x) => Ok(u64::from(*x)),
+                    Self::PspSoftFuseChain(x) => x.to_u64(),
                     _ => Err(Error::PspEntrySourceUnknown(typ)),
                 },
                 _ => Err(Error::PspEntrySourceUnknown(typ)),
@@ -225,18 +394,49 @@ impl SerdePspEntrySourceValue {
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename = "PspEntrySource")]
 #[serde(deny_unknown_fields)]
-pub enum SerdePspEntrySource {
+pub enum SerdePspEntrySource<'a> {
     Value(SerdePspEntrySourceValue),
     BlobFile(PathBuf),
-    SecondLevelDirectory(SerdePspDirectory),
+    SecondLevelDirectory(SerdePspDirectory<'a>),
+    /// Image Slot Header (the payload of a PSP level 2 A or B directory
+    /// entry) and the level 2 directory it points to.
+    ImageSlotHeader(SerdeImageSlot<'a>),
+    /// BHD level 2 directory (only in PSP level 2 directories).
+    #[serde(bound(deserialize = "SerdeBhdDirectory<'a>: Deserialize<'de>"))]
+    BhdDirectory(SerdeBhdDirectory<'a>),
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename = "PspEntry")]
 #[serde(deny_unknown_fields)]
-pub struct SerdePspEntry {
-    pub source: SerdePspEntrySource,
+pub struct SerdePspEntry<'a> {
+    #[serde(bound(deserialize = "SerdePspEntrySource<'a>: Deserialize<'de>"))]
+    pub source: SerdePspEntrySource<'a>,
     pub target: SerdePspDirectoryEntry,
+    /// Where in the config this entry is (if known)
+    #[serde(skip)]
+    pub span: Option<SourceSpan>,
+}
+
+/// Image Slot Header: Tells the PSP where a level 2 PSP directory is and
+/// how to choose between the A and B level 2 directories.
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename = "ImageSlot")]
+#[serde(deny_unknown_fields)]
+pub struct SerdeImageSlot<'a> {
+    /// The slot with the higher priority is tried first.
+    pub boot_priority: u32,
+    #[serde(default)]
+    pub update_retry_count: u32,
+    #[serde(default)]
+    pub glitch_retry_count: u8,
+    #[serde(default)]
+    pub psp_id: u32,
+    pub slot_max_size: u32,
+    #[serde(default)]
+    pub directory_flash_location: Option<Location>,
+    #[serde(bound(deserialize = "SerdePspDirectory<'a>: Deserialize<'de>"))]
+    pub directory: SerdePspDirectory<'a>,
 }
 
 #[derive(
@@ -253,11 +453,76 @@ pub struct SerdeBhdDirectoryEntryBlob {
     pub ram_destination_address: Option<u64>,
 }
 
+/// The type of a BHD directory entry. Types that amd-efs doesn't know (yet)
+/// are kept as a raw number so that they survive dump and generate.
+#[derive(Clone, Copy, Debug, PartialEq, schemars::JsonSchema)]
+#[serde(untagged)]
+pub enum SerdeBhdDirectoryEntryType {
+    Known(BhdDirectoryEntryType),
+    Unknown(u8),
+}
+
+impl From<BhdDirectoryEntryType> for SerdeBhdDirectoryEntryType {
+    fn from(typ: BhdDirectoryEntryType) -> Self {
+        Self::Known(typ)
+    }
+}
+
+impl From<&BhdDirectoryEntry> for SerdeBhdDirectoryEntryType {
+    fn from(entry: &BhdDirectoryEntry) -> Self {
+        match entry.typ_or_err() {
+            Ok(typ) => Self::Known(typ),
+            Err(_) => Self::Unknown(entry.as_bytes()[0]),
+        }
+    }
+}
+
+impl PartialEq<BhdDirectoryEntryType> for SerdeBhdDirectoryEntryType {
+    fn eq(&self, other: &BhdDirectoryEntryType) -> bool {
+        *self == Self::Known(*other)
+    }
+}
+
+impl std::fmt::Display for SerdeBhdDirectoryEntryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Known(typ) => write!(f, "{typ}"),
+            Self::Unknown(typ) => write!(f, "0x{typ:02x}"),
+        }
+    }
+}
+
+impl serde::ser::Serialize for SerdeBhdDirectoryEntryType {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        match self {
+            Self::Known(typ) => serde::Serialize::serialize(typ, serializer),
+            Self::Unknown(typ) => serializer.serialize_u8(*typ),
+        }
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for SerdeBhdDirectoryEntryType {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        Ok(deserializer
+            .deserialize_any(EntryTypeVisitor(std::marker::PhantomData))?
+            .map_or_else(Self::Unknown, Self::Known))
+    }
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct SerdeBhdDirectoryEntryAttrs {
     #[serde(rename = "type")]
-    pub type_: BhdDirectoryEntryType,
+    pub type_: SerdeBhdDirectoryEntryType,
     #[serde(default)]
     pub region_type: BhdDirectoryEntryRegionType,
     #[serde(default)]
@@ -279,7 +544,7 @@ pub struct SerdeBhdDirectoryEntryAttrs {
 impl SerdeBhdDirectoryEntryAttrs {
     pub fn builder() -> Self {
         Self {
-            type_: BhdDirectoryEntryType::OemPublicKey,
+            type_: BhdDirectoryEntryType::OemPublicKey.into(),
             region_type: BhdDirectoryEntryRegionType::Normal,
             reset_image: false,
             copy_image: false,
@@ -315,24 +580,42 @@ impl TryFromSerdeDirectoryEntryWithContext<SerdeBhdDirectoryEntry>
         target: &SerdeBhdDirectoryEntry,
     ) -> Result<Self> {
         let blob = target.blob.as_ref();
-        Ok(Self::new_payload(
-            directory_address_mode,
-            target.attrs.type_,
-            blob.and_then(|y| y.size),
-            blob.and_then(|x| {
-                x.flash_location.map(ValueOrLocation::EfsRelativeOffset)
-            }),
-            blob.and_then(|y| y.ram_destination_address),
-        )?
-        .with_region_type(target.attrs.region_type)
-        .with_reset_image(target.attrs.reset_image)
-        .with_copy_image(target.attrs.copy_image)
-        .with_read_only(target.attrs.read_only)
-        .with_compressed(target.attrs.compressed)
-        .with_instance(target.attrs.instance)
-        .with_sub_program(target.attrs.sub_program)
-        .with_rom_id(target.attrs.rom_id)
-        .build())
+        let size = blob.and_then(|y| y.size);
+        let source = blob.and_then(|x| {
+            x.flash_location.map(ValueOrLocation::EfsRelativeOffset)
+        });
+        let ram_destination_address =
+            blob.and_then(|y| y.ram_destination_address);
+        match target.attrs.type_ {
+            SerdeBhdDirectoryEntryType::Known(typ) => Ok(Self::new_payload(
+                directory_address_mode,
+                typ,
+                size,
+                source,
+                ram_destination_address,
+            )?
+            .with_region_type(target.attrs.region_type)
+            .with_reset_image(target.attrs.reset_image)
+            .with_copy_image(target.attrs.copy_image)
+            .with_read_only(target.attrs.read_only)
+            .with_compressed(target.attrs.compressed)
+            .with_instance(target.attrs.instance)
+            .with_sub_program(target.attrs.sub_program)
+            .with_rom_id(target.attrs.rom_id)
+            .build()),
+            SerdeBhdDirectoryEntryType::Unknown(typ) => {
+                let mut entry = raw_entry::bhd_directory_entry(
+                    typ,
+                    &target.attrs,
+                    ram_destination_address,
+                )?;
+                entry.set_size(size);
+                if let Some(source) = source {
+                    entry.set_source(directory_address_mode, source)?;
+                }
+                Ok(entry)
+            }
+        }
     }
 }
 
@@ -342,6 +625,12 @@ impl TryFromSerdeDirectoryEntryWithContext<SerdeBhdDirectoryEntry>
 pub enum SerdeBhdSource<'a> {
     Implied,
     BlobFile(PathBuf),
+    /// ELF file whose loadable segments are copied to RAM at their physical
+    /// addresses--one entry per (contiguous) segment, with consecutive
+    /// instances beginning at the instance of the target. No other entry
+    /// of the directory may have one of those instances (with the same
+    /// type, sub_program and rom_id).
+    ElfFile(PathBuf),
     #[serde(bound(deserialize = "Apcb<'a>: Deserialize<'de>"))]
     ApcbJson(amd_apcb::Apcb<'a>),
     SecondLevelDirectory(SerdeBhdDirectory<'a>),
@@ -354,27 +643,38 @@ pub struct SerdeBhdEntry<'a> {
     #[serde(bound(deserialize = "SerdeBhdSource<'a>: Deserialize<'de>"))]
     pub source: SerdeBhdSource<'a>, // PathBuf,
     pub target: SerdeBhdDirectoryEntry,
+    /// Where in the config this entry is (if known)
+    #[serde(skip)]
+    pub span: Option<SourceSpan>,
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename = "PspDirectory")]
 #[serde(deny_unknown_fields)]
-pub struct SerdePspDirectory {
-    pub entries: Vec<SerdePspEntry>,
+pub struct SerdePspDirectory<'a> {
+    #[serde(bound(deserialize = "Vec<SerdePspEntry<'a>>: Deserialize<'de>"))]
+    pub entries: Vec<SerdePspEntry<'a>>,
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename = "PspComboDirectory")]
 #[serde(deny_unknown_fields)]
-pub struct SerdePspComboDirectory {
-    pub directories: BTreeMap<ComboDirectoryEntryFilter, SerdePspDirectory>,
+pub struct SerdePspComboDirectory<'a> {
+    #[serde(bound(
+        deserialize = "BTreeMap<ComboDirectoryEntryFilter, SerdePspDirectory<'a>>: Deserialize<'de>"
+    ))]
+    pub directories: BTreeMap<ComboDirectoryEntryFilter, SerdePspDirectory<'a>>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Clone)]
 #[serde(deny_unknown_fields)]
-pub enum SerdePspDirectoryVariant {
-    PspDirectory(SerdePspDirectory),
-    PspComboDirectory(SerdePspComboDirectory),
+pub enum SerdePspDirectoryVariant<'a> {
+    #[serde(bound(deserialize = "SerdePspDirectory<'a>: Deserialize<'de>"))]
+    PspDirectory(SerdePspDirectory<'a>),
+    #[serde(bound(
+        deserialize = "SerdePspComboDirectory<'a>: Deserialize<'de>"
+    ))]
+    PspComboDirectory(SerdePspComboDirectory<'a>),
 }
 
 #[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
@@ -407,6 +707,23 @@ pub enum SerdeBhdDirectoryVariant<'a> {
     BhdComboDirectory(SerdeBhdComboDirectory<'a>),
 }
 
+/// Where in DRAM the ABL puts the APOB (for BHD directories that don't have
+/// an Apob entry of their own)
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename = "Apob")]
+#[serde(deny_unknown_fields)]
+pub struct SerdeApob {
+    /// Default depends on the processor generation (0x400_0000 so far)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ram_destination_address: Option<u64>,
+    /// Space to leave free for the APOB in DRAM. Default depends on the
+    /// processor generation (1 MiB so far)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(rename = "Config")]
 #[serde(deny_unknown_fields)]
@@ -424,11 +741,20 @@ struct RawSerdeConfig<'a> {
     pub psp_main_directory_flash_location: Option<Location>,
     #[serde(alias = "bhd_main_directory_location")]
     pub bhd_main_directory_flash_location: Option<Location>,
-    pub psp: SerdePspDirectoryVariant,
+    #[serde(bound(
+        deserialize = "SerdePspDirectoryVariant<'a>: Deserialize<'de>"
+    ))]
+    pub psp: SerdePspDirectoryVariant<'a>,
     #[serde(bound(
         deserialize = "SerdeBhdDirectoryVariant<'a>: Deserialize<'de>"
     ))]
     pub bhd: SerdeBhdDirectoryVariant<'a>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apob: Option<SerdeApob>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<SerdePolicy>,
 }
 
 // The distinction SerdeConfig vs RawSerdeConfig is so we can validate
@@ -448,8 +774,10 @@ pub struct SerdeConfig<'a> {
     pub psp_main_directory_flash_location: Option<Location>,
     #[serde(alias = "bhd_main_directory_location")]
     pub bhd_main_directory_flash_location: Option<Location>,
-    pub psp: SerdePspDirectoryVariant,
+    pub psp: SerdePspDirectoryVariant<'a>,
     pub bhd: SerdeBhdDirectoryVariant<'a>,
+    pub apob: Option<SerdeApob>,
+    pub policy: Option<SerdePolicy>,
 }
 
 impl schemars::JsonSchema for SerdeConfig<'_> {
@@ -481,6 +809,8 @@ impl<'a> From<SerdeConfig<'a>> for RawSerdeConfig<'a> {
                 .bhd_main_directory_flash_location,
             psp: config.psp,
             bhd: config.bhd,
+            apob: config.apob,
+            policy: config.policy,
         }
     }
 }
@@ -512,6 +842,8 @@ impl<'a> core::convert::TryFrom<RawSerdeConfig<'a>> for SerdeConfig<'a> {
                             .bhd_main_directory_flash_location,
                         psp: raw.psp,
                         bhd: raw.bhd,
+                        apob: raw.apob,
+                        policy: raw.policy,
                     });
                 }
             }
@@ -533,6 +865,8 @@ impl<'a> core::convert::TryFrom<RawSerdeConfig<'a>> for SerdeConfig<'a> {
                             .bhd_main_directory_flash_location,
                         psp: raw.psp,
                         bhd: raw.bhd,
+                        apob: raw.apob,
+                        policy: raw.policy,
                     });
                 }
             }
@@ -558,6 +892,8 @@ impl<'a> core::convert::TryFrom<RawSerdeConfig<'a>> for SerdeConfig<'a> {
                             .bhd_main_directory_flash_location,
                         psp: raw.psp,
                         bhd: raw.bhd,
+                        apob: raw.apob,
+                        policy: raw.policy,
                     });
                 }
             }
@@ -597,6 +933,8 @@ mod tests {
             spi_mode_zen_rome: None,
             espi0_configuration: None,
             espi1_configuration: None,
+            apob: None,
+            policy: None,
         })
         .unwrap();
     }
@@ -622,6 +960,8 @@ mod tests {
             }),
             espi0_configuration: None,
             espi1_configuration: None,
+            apob: None,
+            policy: None,
         })
         .unwrap();
     }
@@ -647,6 +987,8 @@ mod tests {
             }),
             espi0_configuration: None,
             espi1_configuration: None,
+            apob: None,
+            policy: None,
         })
         .unwrap();
     }
@@ -673,6 +1015,8 @@ mod tests {
             }),
             espi0_configuration: None,
             espi1_configuration: None,
+            apob: None,
+            policy: None,
         })
         .unwrap();
     }
@@ -698,6 +1042,8 @@ mod tests {
             spi_mode_zen_rome: None,
             espi0_configuration: None,
             espi1_configuration: None,
+            apob: None,
+            policy: None,
         })
         .unwrap();
     }
@@ -0,0 +1,49 @@
+//! Policy profiles: what an image that is built for a given purpose must
+//! and must not contain. The rules of each profile are in the builder; the
+//! config only says which profile applies (and adds what only the project
+//! can know).
+
+/// Purpose of an image.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename = "PolicyProfile")]
+pub enum SerdePolicyProfile {
+    /// Images for machines in the field
+    Production,
+    /// Images for bring-up and debugging (no restrictions)
+    Development,
+}
+
+impl std::str::FromStr for SerdePolicyProfile {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "production" => Ok(Self::Production),
+            "development" => Ok(Self::Development),
+            _ => Err(format!(
+                "unknown policy profile {s:?}; expected production or development"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename = "Policy")]
+#[serde(deny_unknown_fields)]
+pub struct SerdePolicy {
+    pub profile: SerdePolicyProfile,
+    /// ELF symbols that the reset image has to have in addition to the ones
+    /// the profile requires--for example a marker that only the production
+    /// build of the loader has
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub required_reset_image_symbols: Vec<String>,
+}
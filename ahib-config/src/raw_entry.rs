@@ -0,0 +1,172 @@
+//! Directory entries of types that amd-efs doesn't know (by name).
+//!
+//! amd-efs only makes entries from its type enums, so these are assembled
+//! from the layout of the entries (see AMD pub. 55758) instead. The
+//! attributes come first, followed by the size and the location (and, in
+//! BHD directories, the RAM destination address):
+//!
+//! * PSP: type (bits 0..8), sub_program (8..16), rom_id (16..18),
+//!   writable (18), instance (19..23)
+//! * BHD: type (bits 0..8), region_type (8..16), reset_image (16),
+//!   copy_image (17), read_only (18), compressed (19), instance (20..24),
+//!   sub_program (24..27), rom_id (27..29)
+
+use amd_efs::{BhdDirectoryEntry, PspDirectoryEntry};
+use zerocopy::FromBytes;
+
+use crate::{
+    Error, Result, SerdeBhdDirectoryEntryAttrs, SerdePspDirectoryEntryAttrs,
+};
+
+const PSP_DIRECTORY_ENTRY_SIZE: usize = 16;
+const BHD_DIRECTORY_ENTRY_SIZE: usize = 24;
+
+/// RAM destination address of BHD entries that have none.
+const NO_RAM_DESTINATION_ADDRESS: u64 = !0;
+
+/// Returns VALUE if it fits into BITS bits, otherwise an error about the
+/// attribute NAME.
+fn bitfield(name: &'static str, value: u8, bits: u32) -> Result<u32> {
+    if u32::from(value) >> bits == 0 {
+        Ok(value.into())
+    } else {
+        Err(Error::EntryAttributeOutOfRange(name, value))
+    }
+}
+
+/// Returns a PSP directory entry of raw type TYP with the other attributes
+/// of ATTRS (its type is not used), without size or location.
+pub fn psp_directory_entry(
+    typ: u8,
+    attrs: &SerdePspDirectoryEntryAttrs,
+) -> Result<PspDirectoryEntry> {
+    let attributes = u32::from(typ)
+        | u32::from(attrs.sub_program) << 8
+        | bitfield("rom_id", attrs.rom_id as u8, 2)? << 16
+        | bitfield("instance", attrs.instance, 4)? << 19;
+    let mut bytes = [0u8; PSP_DIRECTORY_ENTRY_SIZE];
+    bytes[0..4].copy_from_slice(&attributes.to_le_bytes());
+    Ok(PspDirectoryEntry::read_from_bytes(&bytes)
+        .expect("PSP directory entries have 16 Bytes"))
+}
+
+/// Returns a BHD directory entry of raw type TYP with the other attributes
+/// of ATTRS (its type is not used) and RAM_DESTINATION_ADDRESS, without size
+/// or location.
+pub fn bhd_directory_entry(
+    typ: u8,
+    attrs: &SerdeBhdDirectoryEntryAttrs,
+    ram_destination_address: Option<u64>,
+) -> Result<BhdDirectoryEntry> {
+    let attributes = u32::from(typ)
+        | u32::from(attrs.region_type as u8) << 8
+        | u32::from(attrs.reset_image) << 16
+        | u32::from(attrs.copy_image) << 17
+        | u32::from(attrs.read_only) << 18
+        | u32::from(attrs.compressed) << 19
+        | bitfield("instance", attrs.instance, 4)? << 20
+        | bitfield("sub_program", attrs.sub_program, 3)? << 24
+        | bitfield("rom_id", attrs.rom_id as u8, 2)? << 27;
+    let mut bytes = [0u8; BHD_DIRECTORY_ENTRY_SIZE];
+    bytes[0..4].copy_from_slice(&attributes.to_le_bytes());
+    bytes[16..24].copy_from_slice(
+        &ram_destination_address
+            .unwrap_or(NO_RAM_DESTINATION_ADDRESS)
+            .to_le_bytes(),
+    );
+    Ok(BhdDirectoryEntry::read_from_bytes(&bytes)
+        .expect("BHD directory entries have 24 Bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bhd_directory_entry, psp_directory_entry};
+    use crate::{SerdeBhdDirectoryEntryAttrs, SerdePspDirectoryEntryAttrs};
+    use amd_efs::{
+        AddressMode, BhdDirectoryEntry, BhdDirectoryEntryRegionType,
+        BhdDirectoryEntryType, BhdDirectoryRomId, PspDirectoryEntry,
+        PspDirectoryEntryType, PspDirectoryRomId,
+    };
+    use zerocopy::IntoBytes;
+
+    /// The layout has to be the one amd-efs uses for known types.
+    #[test]
+    fn raw_psp_entry_matches_amd_efs() {
+        let attrs = SerdePspDirectoryEntryAttrs {
+            type_: PspDirectoryEntryType::PspBootloader.into(),
+            sub_program: 1,
+            rom_id: PspDirectoryRomId::SpiCs2,
+            instance: 5,
+        };
+        let expected = PspDirectoryEntry::new_payload(
+            AddressMode::EfsRelativeOffset,
+            PspDirectoryEntryType::PspBootloader,
+            None,
+            None,
+        )
+        .unwrap()
+        .with_instance(attrs.instance)
+        .with_sub_program(attrs.sub_program)
+        .with_rom_id(attrs.rom_id)
+        .build();
+        let entry = psp_directory_entry(
+            PspDirectoryEntryType::PspBootloader as u8,
+            &attrs,
+        )
+        .unwrap();
+        assert_eq!(entry.as_bytes()[0..4], expected.as_bytes()[0..4]);
+    }
+
+    #[test]
+    fn raw_bhd_entry_matches_amd_efs() {
+        let attrs = SerdeBhdDirectoryEntryAttrs {
+            type_: BhdDirectoryEntryType::Bios.into(),
+            region_type: BhdDirectoryEntryRegionType::Normal,
+            reset_image: true,
+            copy_image: true,
+            read_only: false,
+            compressed: true,
+            instance: 3,
+            sub_program: 2,
+            rom_id: BhdDirectoryRomId::SpiCs2,
+        };
+        let expected = BhdDirectoryEntry::new_payload(
+            AddressMode::EfsRelativeOffset,
+            BhdDirectoryEntryType::Bios,
+            None,
+            None,
+            Some(0x7600_0000),
+        )
+        .unwrap()
+        .with_region_type(attrs.region_type)
+        .with_reset_image(attrs.reset_image)
+        .with_copy_image(attrs.copy_image)
+        .with_read_only(attrs.read_only)
+        .with_compressed(attrs.compressed)
+        .with_instance(attrs.instance)
+        .with_sub_program(attrs.sub_program)
+        .with_rom_id(attrs.rom_id)
+        .build();
+        let entry = bhd_directory_entry(
+            BhdDirectoryEntryType::Bios as u8,
+            &attrs,
+            Some(0x7600_0000),
+        )
+        .unwrap();
+        assert_eq!(entry.as_bytes()[0..4], expected.as_bytes()[0..4]);
+        assert_eq!(entry.destination_location(), Some(0x7600_0000));
+        let entry = bhd_directory_entry(0xd0, &attrs, None).unwrap();
+        assert_eq!(entry.destination_location(), None);
+    }
+
+    #[test]
+    fn raw_entry_attribute_out_of_range() {
+        let attrs = SerdePspDirectoryEntryAttrs {
+            type_: PspDirectoryEntryType::PspBootloader.into(),
+            sub_program: 0,
+            rom_id: PspDirectoryRomId::SpiCs1,
+            instance: 16,
+        };
+        assert!(psp_directory_entry(0xd0, &attrs).is_err());
+    }
+}
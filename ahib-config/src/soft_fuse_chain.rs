@@ -0,0 +1,230 @@
+//! Symbolic PSP soft fuse chains. The bits that amd-efs has names for are
+//! given by name; all the other bits are kept as a number (so nothing is
+//! lost when dumping and generating).
+
+use amd_efs::{ProcessorGeneration, PspSoftFuseChain};
+use serde_json::{Map, Value};
+use std::sync::OnceLock;
+
+/// Name of the field of the config that has the bits without name.
+const UNKNOWN_BITS: &str = "unknown_bits";
+
+/// A named field of the soft fuse chain and the bits it consists of.
+pub struct SoftFuseField {
+    pub name: String,
+    pub bits: Vec<u32>,
+}
+
+/// Returns the fields of CHAIN (in the serde representation of amd-efs).
+fn fields(chain: PspSoftFuseChain) -> Map<String, Value> {
+    match serde_json::to_value(chain) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+/// Returns the named fields of the soft fuse chain.
+/// Note: Those are found by setting one bit at a time and checking which
+/// field changes.
+pub fn soft_fuse_fields() -> &'static [SoftFuseField] {
+    static FIELDS: OnceLock<Vec<SoftFuseField>> = OnceLock::new();
+    FIELDS.get_or_init(|| {
+        let defaults = fields(PspSoftFuseChain::from(0));
+        let mut result = Vec::<SoftFuseField>::new();
+        for bit in 0..u64::BITS {
+            let changed = fields(PspSoftFuseChain::from(1u64 << bit))
+                .into_iter()
+                .filter(|(name, value)| defaults.get(name) != Some(value));
+            for (name, _) in changed {
+                match result.iter_mut().find(|x| x.name == name) {
+                    Some(field) => field.bits.push(bit),
+                    None => {
+                        result.push(SoftFuseField { name, bits: vec![bit] })
+                    }
+                }
+            }
+        }
+        result
+    })
+}
+
+/// Returns the mask of all the bits that are part of a named field.
+fn named_bits_mask() -> u64 {
+    soft_fuse_fields()
+        .iter()
+        .flat_map(|x| x.bits.iter())
+        .fold(0, |mask, bit| mask | (1u64 << bit))
+}
+
+/// Returns the names of the soft fuse fields that are known to be missing
+/// on PROCESSOR_GENERATION.
+/// Note: amd-efs has one soft fuse chain layout for all processor
+/// generations, so this is not a complete list of what each generation
+/// has--only the fields that are known not to exist there.
+pub fn invalid_soft_fuse_fields(
+    processor_generation: ProcessorGeneration,
+) -> &'static [&'static str] {
+    match processor_generation {
+        // Those can address the entire SPI flash at once.
+        ProcessorGeneration::Genoa | ProcessorGeneration::Turin => {
+            &["spi_decoding"]
+        }
+        _ => &[],
+    }
+}
+
+/// PSP soft fuse chain with the named bits by name and the other bits in
+/// `unknown_bits`.
+#[derive(Clone, Copy)]
+pub struct SerdePspSoftFuseChain {
+    pub named_bits: PspSoftFuseChain,
+    pub unknown_bits: u64,
+}
+
+impl From<u64> for SerdePspSoftFuseChain {
+    fn from(value: u64) -> Self {
+        let mask = named_bits_mask();
+        Self {
+            named_bits: PspSoftFuseChain::from(value & mask),
+            unknown_bits: value & !mask,
+        }
+    }
+}
+
+impl SerdePspSoftFuseChain {
+    pub fn to_u64(self) -> crate::Result<u64> {
+        let overlap = self.unknown_bits & named_bits_mask();
+        if overlap != 0 {
+            return Err(crate::Error::SoftFuseBitsNotUnknown(overlap));
+        }
+        Ok(u64::from(self.named_bits) | self.unknown_bits)
+    }
+
+    /// Returns the named fields that are set (that is, not at their
+    /// default), and their values.
+    pub fn set_fields(&self) -> Vec<(&'static SoftFuseField, Value)> {
+        let defaults = fields(PspSoftFuseChain::from(0));
+        let fields = fields(self.named_bits);
+        soft_fuse_fields()
+            .iter()
+            .filter_map(|field| {
+                let value = fields.get(&field.name)?;
+                if defaults.get(&field.name) == Some(value) {
+                    None
+                } else {
+                    Some((field, value.clone()))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the names of the fields that are set but that are known to
+    /// be missing on PROCESSOR_GENERATION (see invalid_soft_fuse_fields).
+    pub fn invalid_fields(
+        &self,
+        processor_generation: ProcessorGeneration,
+    ) -> Vec<&'static str> {
+        let invalid_names = invalid_soft_fuse_fields(processor_generation);
+        self.set_fields()
+            .into_iter()
+            .filter_map(|(field, _)| {
+                invalid_names.iter().copied().find(|&x| x == field.name)
+            })
+            .collect()
+    }
+}
+
+impl serde::ser::Serialize for SerdePspSoftFuseChain {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        let mut fields = match serde_json::to_value(self.named_bits)
+            .map_err(serde::ser::Error::custom)?
+        {
+            Value::Object(fields) => fields,
+            _ => Map::new(),
+        };
+        if self.unknown_bits != 0 {
+            fields.insert(UNKNOWN_BITS.into(), self.unknown_bits.into());
+        }
+        fields.serialize(serializer)
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for SerdePspSoftFuseChain {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let mut fields = Map::<String, Value>::deserialize(deserializer)?;
+        let unknown_bits = match fields.remove(UNKNOWN_BITS) {
+            None => 0,
+            Some(x) => x.as_u64().ok_or_else(|| {
+                D::Error::custom("expected unknown_bits to be a u64")
+            })?,
+        };
+        let named_bits = serde_json::from_value(Value::Object(fields))
+            .map_err(D::Error::custom)?;
+        Ok(Self { named_bits, unknown_bits })
+    }
+}
+
+impl schemars::JsonSchema for SerdePspSoftFuseChain {
+    fn schema_name() -> std::string::String {
+        PspSoftFuseChain::schema_name()
+    }
+    fn json_schema(
+        generator: &mut schemars::r#gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        let mut schema = PspSoftFuseChain::json_schema(generator).into_object();
+        schema
+            .object()
+            .properties
+            .insert(UNKNOWN_BITS.into(), generator.subschema_for::<u64>());
+        schema.into()
+    }
+}
+
+#[test]
+fn test_soft_fuse_chain() {
+    let field = |name: &str| {
+        soft_fuse_fields().iter().find(|x| x.name == name).unwrap().bits[0]
+    };
+    let unknown_bit = (0..u64::BITS)
+        .find(|bit| named_bits_mask() & (1u64 << bit) == 0)
+        .unwrap();
+    let value = (1u64 << field("secure_debug_unlock")) | (1 << unknown_bit);
+    let chain = SerdePspSoftFuseChain::from(value);
+    assert_eq!(chain.unknown_bits, 1u64 << unknown_bit);
+    assert_eq!(chain.to_u64().unwrap(), value);
+    let set_fields = chain.set_fields();
+    assert_eq!(set_fields.len(), 1);
+    assert_eq!(set_fields[0].0.name, "secure_debug_unlock");
+    assert_eq!(set_fields[0].1, Value::Bool(true));
+
+    let serialized = serde_json::to_value(chain).unwrap();
+    assert_eq!(serialized["secure_debug_unlock"], Value::Bool(true));
+    assert_eq!(serialized[UNKNOWN_BITS], Value::from(1u64 << unknown_bit));
+    let deserialized: SerdePspSoftFuseChain =
+        serde_json::from_value(serialized).unwrap();
+    assert_eq!(deserialized.to_u64().unwrap(), value);
+
+    // Named bits can't be given as unknown bits.
+    let chain = SerdePspSoftFuseChain {
+        named_bits: PspSoftFuseChain::from(0),
+        unknown_bits: 1u64 << field("secure_debug_unlock"),
+    };
+    assert!(chain.to_u64().is_err());
+
+    let chain = SerdePspSoftFuseChain::from(1u64 << field("spi_decoding"));
+    assert_eq!(
+        chain.invalid_fields(ProcessorGeneration::Genoa),
+        vec!["spi_decoding"]
+    );
+    assert!(chain.invalid_fields(ProcessorGeneration::Milan).is_empty());
+}
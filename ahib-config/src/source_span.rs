@@ -0,0 +1,330 @@
+//! Locations of directory entries in the config text. Semantic errors are
+//! only found after deserialization, so without those they could not point
+//! at the offending entry.
+//!
+//! An entry only remembers the way to it from the root of the config. Its
+//! line and column are found (when an error is rendered) by the json5
+//! crate--the same parser that deserialized the config--by letting it fail
+//! at that entry: json5 only says where a value is in its errors.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess,
+    Visitor,
+};
+
+use crate::{
+    SerdeBhdDirectory, SerdeBhdDirectoryVariant, SerdeBhdSource, SerdeConfig,
+    SerdePspDirectory, SerdePspDirectoryVariant, SerdePspEntrySource,
+};
+
+/// Excerpts longer than that are abridged to their beginning and end.
+const MAX_EXCERPT_LINES: usize = 10;
+
+/// Message of the error that json5 is made to fail with at the value.
+const FOUND: &str = "found";
+
+/// One step on the way from the root of the config to a value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Key(&'static str),
+    Index(usize),
+}
+
+/// Where in the config text a directory entry is.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceSpan {
+    path: Vec<Step>,
+}
+
+impl fmt::Display for SourceSpan {
+    /// Formats the span as a JSON pointer.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.path {
+            match step {
+                Step::Key(key) => write!(f, "/{key}")?,
+                Step::Index(i) => write!(f, "/{i}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Visits the config down to the value at a path and fails there.
+struct Locator<'p>(&'p [Step]);
+
+impl<'de> DeserializeSeed<'de> for Locator<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Locator<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an object or an array")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Some((step, rest)) = self.0.split_first() else {
+            return Err(de::Error::custom(FOUND));
+        };
+        while let Some(key) = map.next_key::<String>()? {
+            if matches!(step, Step::Key(x) if *x == key) {
+                map.next_value_seed(Locator(rest))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Some((step, rest)) = self.0.split_first() else {
+            return Err(de::Error::custom(FOUND));
+        };
+        let mut i = 0;
+        loop {
+            let found = if *step == Step::Index(i) {
+                seq.next_element_seed(Locator(rest))?
+            } else {
+                seq.next_element::<IgnoredAny>()?.map(|_| ())
+            };
+            if found.is_none() {
+                return Ok(());
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Returns the lines of the value that begins at LINE (1-based) of TEXT:
+/// that line, the lines indented deeper than it and the closing line at
+/// the same indentation (abridged if long).
+fn excerpt(text: &str, line: usize) -> Vec<(usize, String)> {
+    let indentation = |x: &str| x.len() - x.trim_start().len();
+    let mut lines = text.lines().enumerate().skip(line - 1);
+    let Some((_, first)) = lines.next() else {
+        return Vec::new();
+    };
+    let mut result = vec![(line, first.to_string())];
+    for (i, x) in lines {
+        if !x.trim().is_empty() && indentation(x) <= indentation(first) {
+            if indentation(x) == indentation(first)
+                && x.trim_start().starts_with(['}', ']'])
+            {
+                result.push((i + 1, x.to_string()));
+            }
+            break;
+        }
+        result.push((i + 1, x.to_string()));
+    }
+    if result.len() > MAX_EXCERPT_LINES {
+        let head = MAX_EXCERPT_LINES / 2;
+        let tail = MAX_EXCERPT_LINES - head;
+        result.drain(head..result.len() - tail);
+    }
+    result
+}
+
+/// Renders MESSAGE about LINE and COLUMN (both 1-based) of
+/// EFS_CONFIGURATION_FILENAME, with the EXCERPT of the source (in the
+/// style of rustc).
+fn render_excerpt(
+    efs_configuration_filename: &Path,
+    line: usize,
+    column: usize,
+    excerpt: &[(usize, String)],
+    message: &str,
+) -> String {
+    let width =
+        excerpt.last().map(|(line, _)| line.to_string().len()).unwrap_or(1);
+    let mut result = format!(
+        "{message}\n{:width$}--> {}:{line}:{column}\n{:width$} |\n",
+        "",
+        efs_configuration_filename.display(),
+        ""
+    );
+    let mut previous_line = None;
+    for (line, contents) in excerpt {
+        if previous_line.is_some_and(|x| x + 1 != *line) {
+            result.push_str(&format!("{:width$} | ...\n", ""));
+        }
+        result.push_str(&format!("{line:width$} | {contents}\n"));
+        if previous_line.is_none() {
+            result.push_str(&format!("{:width$} | {:>column$}\n", "", "^"));
+        }
+        previous_line = Some(*line);
+    }
+    result
+}
+
+/// Renders MESSAGE about the json5 error LOCATION in TEXT (the contents
+/// of EFS_CONFIGURATION_FILENAME), with an excerpt of the source.
+pub fn render_json5_location(
+    efs_configuration_filename: &Path,
+    text: &str,
+    location: &json5::Location,
+    message: &str,
+) -> String {
+    let excerpt = text
+        .lines()
+        .nth(location.line.saturating_sub(1))
+        .map(|x| vec![(location.line, x.to_string())])
+        .unwrap_or_default();
+    render_excerpt(
+        efs_configuration_filename,
+        location.line,
+        location.column,
+        &excerpt,
+        message,
+    )
+}
+
+impl SourceSpan {
+    fn new(path: Vec<Step>) -> Self {
+        Self { path }
+    }
+
+    fn join(&self, steps: &[Step]) -> Self {
+        let mut path = self.path.clone();
+        path.extend_from_slice(steps);
+        Self { path }
+    }
+
+    /// Returns the line and column (both 1-based) of the value in TEXT.
+    fn locate(&self, text: &str) -> Option<(usize, usize)> {
+        let mut deserializer = json5::Deserializer::from_str(text).ok()?;
+        match Locator(&self.path).deserialize(&mut deserializer) {
+            Err(json5::Error::Message { msg, location: Some(location) })
+                if msg == FOUND =>
+            {
+                Some((location.line, location.column))
+            }
+            _ => None,
+        }
+    }
+
+    /// Renders MESSAGE about this span of EFS_CONFIGURATION_FILENAME, with
+    /// an excerpt of the source (in the style of rustc). The file is read
+    /// again for that.
+    pub fn render(
+        &self,
+        efs_configuration_filename: &Path,
+        message: &str,
+    ) -> String {
+        let text = std::fs::read_to_string(efs_configuration_filename)
+            .unwrap_or_default();
+        match self.locate(&text) {
+            Some((line, column)) => render_excerpt(
+                efs_configuration_filename,
+                line,
+                column,
+                &excerpt(&text, line),
+                message,
+            ),
+            None => format!(
+                "{message} at {self} in file {efs_configuration_filename:?}"
+            ),
+        }
+    }
+}
+
+impl SerdePspDirectory<'_> {
+    fn locate_entries(&mut self, span: &SourceSpan) {
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            let entry_span = span.join(&[Step::Key("entries"), Step::Index(i)]);
+            match &mut entry.source {
+                SerdePspEntrySource::SecondLevelDirectory(d) => d
+                    .locate_entries(&entry_span.join(&[
+                        Step::Key("source"),
+                        Step::Key("SecondLevelDirectory"),
+                    ])),
+                SerdePspEntrySource::ImageSlotHeader(slot) => {
+                    slot.directory.locate_entries(&entry_span.join(&[
+                        Step::Key("source"),
+                        Step::Key("ImageSlotHeader"),
+                        Step::Key("directory"),
+                    ]))
+                }
+                SerdePspEntrySource::BhdDirectory(d) => {
+                    d.locate_entries(&entry_span.join(&[
+                        Step::Key("source"),
+                        Step::Key("BhdDirectory"),
+                    ]))
+                }
+                SerdePspEntrySource::Value(_)
+                | SerdePspEntrySource::BlobFile(_) => {}
+            }
+            entry.span = Some(entry_span);
+        }
+    }
+}
+
+impl SerdeBhdDirectory<'_> {
+    fn locate_entries(&mut self, span: &SourceSpan) {
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            let entry_span = span.join(&[Step::Key("entries"), Step::Index(i)]);
+            if let SerdeBhdSource::SecondLevelDirectory(d) = &mut entry.source {
+                d.locate_entries(&entry_span.join(&[
+                    Step::Key("source"),
+                    Step::Key("SecondLevelDirectory"),
+                ]));
+            }
+            entry.span = Some(entry_span);
+        }
+    }
+}
+
+impl SerdeConfig<'_> {
+    /// Remembers where in the config text each directory entry is, so
+    /// errors can point there.
+    /// Note: Entries of combo directories are not located.
+    pub fn locate_entries(&mut self) {
+        if let SerdePspDirectoryVariant::PspDirectory(d) = &mut self.psp {
+            d.locate_entries(&SourceSpan::new(vec![
+                Step::Key("psp"),
+                Step::Key("PspDirectory"),
+            ]));
+        }
+        if let SerdeBhdDirectoryVariant::BhdDirectory(d) = &mut self.bhd {
+            d.locate_entries(&SourceSpan::new(vec![
+                Step::Key("bhd"),
+                Step::Key("BhdDirectory"),
+            ]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let text = "[\n  1,\n  {\n    a: [2, {b: 3}],\n  },\n]";
+        let span = SourceSpan::new(vec![Step::Index(1)]);
+        assert_eq!(span.locate(text), Some((3, 3)));
+        assert_eq!(
+            excerpt(text, 3),
+            vec![
+                (3, "  {".to_string()),
+                (4, "    a: [2, {b: 3}],".to_string()),
+                (5, "  },".to_string()),
+            ]
+        );
+        let span = span.join(&[Step::Key("a"), Step::Index(1)]);
+        assert_eq!(span.locate(text), Some((4, 12)));
+        assert_eq!(span.to_string(), "/1/a/1");
+        let span = SourceSpan::new(vec![Step::Index(2)]);
+        assert_eq!(span.locate(text), None);
+    }
+}
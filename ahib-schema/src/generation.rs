@@ -0,0 +1,402 @@
+//! Restrictions of the config schema to one processor generation, so an
+//! editor can flag entry types, soft fuse bits and APCB tokens that the
+//! processor generation doesn't have.
+//!
+//! amd-efs and amd-apcb don't say which processor generations have which
+//! entry types and tokens, so those are listed here.  Each name is only
+//! removed below the config property it is used in (for example, tokens
+//! below `tokens`), and all of them have to be found there.
+
+use amd_efs::ProcessorGeneration;
+use amd_host_image_builder_config::invalid_soft_fuse_fields;
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashSet};
+
+/// Things that the schema for all processor generations allows but that
+/// one processor generation does not have.
+pub struct Generation {
+    /// Name as in `processor_generation` of the config
+    pub name: &'static str,
+    /// The processor generation itself (for the soft fuse fields it doesn't
+    /// have)
+    pub processor_generation: ProcessorGeneration,
+    /// PSP directory entry types
+    pub invalid_psp_entry_types: &'static [&'static [&'static str]],
+    /// APCB tokens
+    pub invalid_apcb_tokens: &'static [&'static [&'static str]],
+}
+
+/// Firmware that only exists on Zen 4 and later.
+const ZEN4_PSP_ENTRY_TYPES: [&str; 4] = [
+    "Gmi3PhyFirmware",
+    "MpioOffchipFirmware",
+    "MpdmaTigerfishFirmware",
+    "MpdmaPageMigrationFirmware",
+];
+
+/// Drivers of the trusted execution environment of Zen 5.
+const ZEN5_PSP_ENTRY_TYPES: [&str; 10] = [
+    "TeeBootDriver",
+    "TeeDebugDriver",
+    "TeeDpeDriver",
+    "TeeFhpDriver",
+    "TeeInterfaceDriver",
+    "TeeIpKeyManagerDriver",
+    "TeeSevDriver",
+    "TeeSocDriver",
+    "TeeSpdmDriver",
+    "TosSecurityPolicyBinary",
+];
+
+/// Tokens that only exist up to Zen 3 (DDR4 memory).
+const ZEN3_APCB_TOKENS: [&str; 4] = [
+    "CbsMemUncorrectedEccRetryDdr4",
+    "MemParityErrorMaxReplayDdr4",
+    "MemUncorrectedEccRetryDdr4",
+    "MemTsmeModeMilan",
+];
+
+/// Tokens that only exist on Zen 4 and later (DDR5 memory, CXL, I3C and
+/// SDXI).
+const ZEN4_APCB_TOKENS: [&str; 36] = [
+    "CxlResetPin",
+    "DfCxlMemInterleaving",
+    "DfCxlSublinkInterleaving",
+    "DfUmcCxlMixedInterleavedMode",
+    "FchI2cI3cController0Mode",
+    "FchI2cI3cController1Mode",
+    "FchI2cI3cController2Mode",
+    "FchI2cI3cController3Mode",
+    "FchI2cI3cController4Mode",
+    "FchI2cI3cController5Mode",
+    "FchI3c0SdaTxHold",
+    "FchI3c1SdaTxHold",
+    "FchI3c2SdaTxHold",
+    "FchI3c3SdaTxHold",
+    "FchI3cSdaHoldOverrideMode",
+    "FchI3cSdaHoldSwitchDelay",
+    "FchIc3PushPullHighCount",
+    "FchIc3TransferSpeed",
+    "MemDataPoisonDdr",
+    "MemEccErrInjectionDdr",
+    "MemEcsModeDdr",
+    "MemHealMaxBankFailsDdr",
+    "MemHealingBistRepairTypeDdr",
+    "MemMbistAggressorsChannelDdrMode",
+    "MemMbistAggressorsDdr",
+    "MemMbistDataEyeSilentExecutionDdr",
+    "MemMbistDdrMode",
+    "MemMbistPatternLengthDdr",
+    "MemMbistPatternSelectDdr",
+    "MemMbistPerBitSlaveDieReportDdr",
+    "MemMbistTestModeDdr",
+    "MemPmuBistAlgorithmSelectDdr",
+    "MemReadCrcEnableDdr",
+    "MemUeccRetryEnableDdr",
+    "MemWriteCrcEnableDdr",
+    "ProgSdxiClassCode",
+];
+
+/// Tokens that only exist on Zen 5.
+const ZEN5_APCB_TOKENS: [&str; 1] = ["MemForcePowerDownThrottleEnableTurin"];
+
+pub const GENERATIONS: [Generation; 3] = [
+    Generation {
+        name: "Milan",
+        processor_generation: ProcessorGeneration::Milan,
+        invalid_psp_entry_types: &[
+            &ZEN4_PSP_ENTRY_TYPES,
+            &ZEN5_PSP_ENTRY_TYPES,
+        ],
+        invalid_apcb_tokens: &[&ZEN4_APCB_TOKENS, &ZEN5_APCB_TOKENS],
+    },
+    Generation {
+        name: "Genoa",
+        processor_generation: ProcessorGeneration::Genoa,
+        invalid_psp_entry_types: &[&ZEN5_PSP_ENTRY_TYPES],
+        invalid_apcb_tokens: &[&ZEN3_APCB_TOKENS, &ZEN5_APCB_TOKENS],
+    },
+    Generation {
+        name: "Turin",
+        processor_generation: ProcessorGeneration::Turin,
+        invalid_psp_entry_types: &[&["SmuOffChipFirmware12"]],
+        invalid_apcb_tokens: &[&ZEN3_APCB_TOKENS],
+    },
+];
+
+/// Names that one processor generation does not have, by kind.
+struct InvalidNames {
+    psp_entry_types: HashSet<&'static str>,
+    apcb_tokens: HashSet<&'static str>,
+    soft_fuse_fields: HashSet<&'static str>,
+}
+
+impl InvalidNames {
+    /// Returns the invalid names of the kind that is used (somewhere) below
+    /// the config property NAME, if any.
+    fn below_property(&self, name: &str) -> Option<&HashSet<&'static str>> {
+        match name {
+            "type" => Some(&self.psp_entry_types),
+            "tokens" => Some(&self.apcb_tokens),
+            "PspSoftFuseChain" => Some(&self.soft_fuse_fields),
+            _ => None,
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.psp_entry_types
+            .iter()
+            .chain(&self.apcb_tokens)
+            .chain(&self.soft_fuse_fields)
+            .copied()
+    }
+}
+
+impl Generation {
+    fn invalid_names(&self) -> InvalidNames {
+        let flatten = |lists: &[&[&'static str]]| -> HashSet<&'static str> {
+            lists.iter().flat_map(|names| names.iter().copied()).collect()
+        };
+        InvalidNames {
+            psp_entry_types: flatten(self.invalid_psp_entry_types),
+            apcb_tokens: flatten(self.invalid_apcb_tokens),
+            soft_fuse_fields: invalid_soft_fuse_fields(
+                self.processor_generation,
+            )
+            .iter()
+            .copied()
+            .collect(),
+        }
+    }
+
+    /// Restricts SCHEMA (of an entire config) to this processor generation.
+    /// If some of the names that this processor generation doesn't have
+    /// are not where they were expected in SCHEMA, returns those instead.
+    pub fn restrict(
+        &self,
+        schema: &mut Value,
+    ) -> Result<(), BTreeSet<&'static str>> {
+        if let Some(properties) =
+            schema.get_mut("properties").and_then(Value::as_object_mut)
+        {
+            properties.insert(
+                "processor_generation".into(),
+                json!({ "type": "string", "enum": [self.name] }),
+            );
+        }
+        let invalid_names = self.invalid_names();
+        let mut removed_names = HashSet::new();
+        remove_names(schema, None, &invalid_names, &mut removed_names);
+        let missing_names = invalid_names
+            .all()
+            .filter(|name| !removed_names.contains(name))
+            .collect::<BTreeSet<_>>();
+        if missing_names.is_empty() { Ok(()) } else { Err(missing_names) }
+    }
+}
+
+/// If the alternative SCHEMA only accepts some of NAMES--either as a value,
+/// or as the key of an externally tagged enum variant--, returns those.
+fn only_about(
+    schema: &Value,
+    names: &HashSet<&'static str>,
+) -> Option<Vec<&'static str>> {
+    let Some(Value::Array(values)) =
+        schema.get("enum").or_else(|| schema.get("required"))
+    else {
+        return None;
+    };
+    if values.is_empty() {
+        return None;
+    }
+    values
+        .iter()
+        .map(|x| x.as_str().and_then(|x| names.get(x).copied()))
+        .collect()
+}
+
+/// Removes NAMES from the schema object MAP itself (not from its
+/// subschemas): enum values, alternatives that only accept some of them,
+/// and properties.  Adds the names that it removed to REMOVED_NAMES.
+fn remove_names_here(
+    map: &mut serde_json::Map<String, Value>,
+    names: &HashSet<&'static str>,
+    removed_names: &mut HashSet<&'static str>,
+) {
+    let mut is_valid = |x: &Value| match x.as_str().and_then(|x| names.get(x)) {
+        Some(name) => {
+            removed_names.insert(*name);
+            false
+        }
+        None => true,
+    };
+    if let Some(Value::Array(values)) = map.get_mut("enum") {
+        values.retain(&mut is_valid);
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(alternatives)) = map.get_mut(key) {
+            alternatives.retain(|x| match only_about(x, names) {
+                Some(found_names) => {
+                    removed_names.extend(found_names);
+                    false
+                }
+                None => true,
+            });
+        }
+    }
+    if let Some(Value::Object(properties)) = map.get_mut("properties") {
+        let count = properties.len();
+        properties.retain(|k, _| match names.get(k.as_str()) {
+            Some(name) => {
+                removed_names.insert(*name);
+                false
+            }
+            None => true,
+        });
+        if properties.len() != count {
+            if let Some(Value::Array(required)) = map.get_mut("required") {
+                required
+                    .retain(|x| !x.as_str().is_some_and(|x| names.contains(x)));
+            }
+            map.insert("additionalProperties".into(), false.into());
+        }
+    }
+}
+
+/// Removes the names in INVALID_NAMES from SCHEMA, each kind only below
+/// the config property it is used in.  NAMES are the invalid names of the
+/// kind that SCHEMA is (somewhere) below, if any.  Adds the names that it
+/// removed to REMOVED_NAMES.
+fn remove_names(
+    schema: &mut Value,
+    names: Option<&HashSet<&'static str>>,
+    invalid_names: &InvalidNames,
+    removed_names: &mut HashSet<&'static str>,
+) {
+    match schema {
+        Value::Array(items) => {
+            for item in items {
+                remove_names(item, names, invalid_names, removed_names);
+            }
+        }
+        Value::Object(map) => {
+            if let Some(names) = names {
+                remove_names_here(map, names, removed_names);
+            }
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), value) {
+                    // The keys of those are names of config properties.
+                    ("properties", Value::Object(properties)) => {
+                        for (name, property) in properties.iter_mut() {
+                            remove_names(
+                                property,
+                                invalid_names.below_property(name).or(names),
+                                invalid_names,
+                                removed_names,
+                            );
+                        }
+                    }
+                    (_, value) => {
+                        remove_names(value, names, invalid_names, removed_names)
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns whether SCHEMA mentions NAME as an enum value or a property.
+    fn mentions(schema: &Value, name: &str) -> bool {
+        match schema {
+            Value::Array(items) => items.iter().any(|x| mentions(x, name)),
+            Value::Object(map) => {
+                map.get("enum").and_then(Value::as_array).is_some_and(
+                    |values| values.iter().any(|x| x.as_str() == Some(name)),
+                ) || map
+                    .get("properties")
+                    .and_then(Value::as_object)
+                    .is_some_and(|properties| properties.contains_key(name))
+                    || map.values().any(|x| mentions(x, name))
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_remove_names_only_below_their_property() {
+        let mut schema = json!({ "properties": {
+            "target": { "properties": { "type": { "anyOf": [
+                { "enum": ["PspBootloader", "Gmi3PhyFirmware"] },
+                { "type": "integer" },
+            ] } } },
+            "tokens": { "items": { "oneOf": [
+                { "required": ["MemA"], "properties": { "MemA": {} } },
+                { "required": ["MemB"], "properties": { "MemB": {} } },
+            ] } },
+            "other": { "properties": {
+                "MemA": { "enum": ["Gmi3PhyFirmware"] },
+            } },
+        } });
+        let invalid_names = InvalidNames {
+            psp_entry_types: ["Gmi3PhyFirmware"].into(),
+            apcb_tokens: ["MemA"].into(),
+            soft_fuse_fields: HashSet::new(),
+        };
+        let mut removed_names = HashSet::new();
+        remove_names(&mut schema, None, &invalid_names, &mut removed_names);
+        assert_eq!(removed_names, HashSet::from(["Gmi3PhyFirmware", "MemA"]));
+        assert_eq!(
+            schema["properties"]["target"]["properties"]["type"]["anyOf"][0],
+            json!({ "enum": ["PspBootloader"] })
+        );
+        assert_eq!(
+            schema["properties"]["tokens"]["items"]["oneOf"],
+            json!([{ "required": ["MemB"], "properties": { "MemB": {} } }])
+        );
+        assert_eq!(
+            schema["properties"]["other"],
+            json!({ "properties": {
+                "MemA": { "enum": ["Gmi3PhyFirmware"] },
+            } })
+        );
+    }
+
+    #[test]
+    fn test_generation_schemas() {
+        let schema =
+            serde_json::to_value(crate::generate_config_json_schema()).unwrap();
+        let restricted = |name: &str| {
+            let generation =
+                GENERATIONS.iter().find(|x| x.name == name).unwrap();
+            let mut schema = schema.clone();
+            generation.restrict(&mut schema).unwrap();
+            schema
+        };
+        assert!(mentions(&schema, "Gmi3PhyFirmware"));
+        assert!(mentions(&schema, "MemForcePowerDownThrottleEnableTurin"));
+
+        let milan = restricted("Milan");
+        assert!(!mentions(&milan, "Gmi3PhyFirmware"));
+        assert!(!mentions(&milan, "TeeSevDriver"));
+        assert!(!mentions(&milan, "MemForcePowerDownThrottleEnableTurin"));
+        assert!(mentions(&milan, "MemTsmeModeMilan"));
+        assert!(mentions(&milan, "spi_decoding"));
+
+        let genoa = restricted("Genoa");
+        assert!(mentions(&genoa, "Gmi3PhyFirmware"));
+        assert!(!mentions(&genoa, "MemForcePowerDownThrottleEnableTurin"));
+        assert!(!mentions(&genoa, "MemTsmeModeMilan"));
+        assert!(!mentions(&genoa, "spi_decoding"));
+
+        let turin = restricted("Turin");
+        assert!(mentions(&turin, "TeeSevDriver"));
+        assert!(mentions(&turin, "MemForcePowerDownThrottleEnableTurin"));
+        assert!(!mentions(&turin, "SmuOffChipFirmware12"));
+        assert!(!mentions(&turin, "MemTsmeModeMilan"));
+    }
+}
@@ -1,9 +1,12 @@
 use amd_host_image_builder_config::SerdeConfig;
 use schemars::r#gen::SchemaSettings;
 use schemars::schema::RootSchema;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use valico::json_schema;
 
+mod generation;
+use generation::GENERATIONS;
+
 pub fn generate_config_json_schema() -> RootSchema {
     let settings = SchemaSettings::default().with(|s| {
         // Work around schemars issue #62.
@@ -15,13 +18,11 @@ pub fn generate_config_json_schema() -> RootSchema {
     generator.into_root_schema_for::<SerdeConfig>()
 }
 
-fn test_schema(schema_str: &str) {
+fn test_schema(schema_str: &str, configuration_filename: &Path) {
     // Make sure our test efs config validates using the schema we just
     // generated.
     let schema_json: serde_json::Value =
         serde_json::from_str(schema_str).expect("Schema");
-    let configuration_filename =
-        Path::new("tests").join("data").join("Milan.efs.json5");
     let configuration_str =
         std::fs::read_to_string(configuration_filename).expect("configuration");
     let configuration_json: serde_json::Value =
@@ -46,9 +47,58 @@ fn test_schema(schema_str: &str) {
     }
 }
 
+/// Writes the schema for all processor generations into
+/// OUTPUT_DIRNAME/efs.schema.json and the schema for each processor
+/// generation into (for example) OUTPUT_DIRNAME/efs.milan.schema.json.
+fn write_schemas(schema: &RootSchema, output_dirname: &Path) {
+    std::fs::create_dir_all(output_dirname).expect("output directory");
+    let schema_string = serde_json::to_string_pretty(schema).unwrap();
+    std::fs::write(
+        output_dirname.join("efs.schema.json"),
+        format!("{schema_string}\n"),
+    )
+    .expect("schema file");
+    let schema = serde_json::to_value(schema).unwrap();
+    for generation in &GENERATIONS {
+        let mut schema = schema.clone();
+        if let Err(names) = generation.restrict(&mut schema) {
+            panic!(
+                "{names:?} not found in the schema for {}. Hint: Update ahib-schema/src/generation.rs",
+                generation.name
+            );
+        }
+        let schema_string = serde_json::to_string_pretty(&schema).unwrap();
+        test_schema(
+            &schema_string,
+            &Path::new("tests")
+                .join("data")
+                .join("round-trip")
+                .join(format!("{}.efs.json5", generation.name)),
+        );
+        std::fs::write(
+            output_dirname.join(format!(
+                "efs.{}.schema.json",
+                generation.name.to_lowercase()
+            )),
+            format!("{schema_string}\n"),
+        )
+        .expect("schema file");
+    }
+}
+
+/// Without arguments, prints the schema for all processor generations.
+/// With an output directory as argument, writes all the schemas there.
 fn main() {
     let schema = generate_config_json_schema();
     let schema_string = serde_json::to_string_pretty(&schema).unwrap();
-    test_schema(&schema_string);
-    println!("{}", schema_string);
+    test_schema(
+        &schema_string,
+        &Path::new("tests").join("data").join("Milan.efs.json5"),
+    );
+    match std::env::args_os().nth(1) {
+        Some(output_dirname) => {
+            write_schemas(&schema, &PathBuf::from(output_dirname))
+        }
+        None => println!("{}", schema_string),
+    }
 }
@@ -0,0 +1,896 @@
+//! Query and change individual APCB tokens, either in a config file or in
+//! the Apcb/ApcbBackup entry of an existing flash image.
+//!
+//! Tokens are addressed by name (as in the JSON5 config, e.g.
+//! `AblSerialBaudRate`) and, if necessary, by the instance_id and
+//! board_instance_mask of the tokens entry they are in.
+//!
+//! The editing itself is done on the serde representation of the APCB--that
+//! is, the same thing a user would do when editing the JSON5 by hand. The
+//! result is then deserialized again and validated.
+
+use amd_apcb::{Apcb, ApcbIoOptions};
+use amd_efs::flash::{FlashRead, Location};
+use amd_efs::{
+    BhdDirectory, BhdDirectoryEntry, BhdDirectoryEntryType, DirectoryEntry,
+    Efs, ProcessorGeneration, PspDirectory, PspDirectoryEntry,
+    PspDirectoryEntryType,
+};
+use amd_host_image_builder_config::{
+    SerdeBhdDirectory, SerdeBhdDirectoryEntryType, SerdeBhdDirectoryVariant,
+    SerdeBhdSource, SerdeConfig, SerdePspDirectory, SerdePspDirectoryVariant,
+    SerdePspEntrySource,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use crate::images::FlashImage;
+use crate::{DirectoryVisitor, DirectoryWalker};
+
+/// Group id of the APCB group that contains all the tokens entries.
+const TOKENS_GROUP_ID: u64 = 0x3000;
+
+fn parse_apcb_entry_type(
+    s: &str,
+) -> std::result::Result<BhdDirectoryEntryType, String> {
+    match s {
+        "Apcb" => Ok(BhdDirectoryEntryType::Apcb),
+        "ApcbBackup" => Ok(BhdDirectoryEntryType::ApcbBackup),
+        _ => Err(format!("expected Apcb or ApcbBackup, got {s:?}")),
+    }
+}
+
+fn parse_int(s: &str) -> std::result::Result<u16, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// Which APCB to operate on.
+#[derive(Debug, Default, StructOpt)]
+pub(crate) struct ApcbSelection {
+    /// Config file to use
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    efs_configuration_filename: Option<PathBuf>,
+
+    /// Existing flash image to use
+    #[structopt(short = "i", long = "existing-file", parse(from_os_str))]
+    input_filename: Option<PathBuf>,
+
+    /// Blob directories (only used to find the ABL version of a config)
+    #[structopt(short = "B", long = "blobdir", parse(from_os_str))]
+    blobdirs: Vec<PathBuf>,
+
+    /// Only use BHD entries of this type (Apcb or ApcbBackup)
+    #[structopt(long = "entry-type", parse(try_from_str = parse_apcb_entry_type))]
+    entry_type: Option<BhdDirectoryEntryType>,
+
+    /// Only use BHD entries with this instance
+    #[structopt(long = "entry-instance")]
+    entry_instance: Option<u8>,
+
+    /// Only use BHD entries with this sub_program
+    #[structopt(long = "sub-program")]
+    sub_program: Option<u8>,
+}
+
+/// Which token to operate on.
+#[derive(Debug, StructOpt)]
+pub(crate) struct TokenAddress {
+    /// Name of the token, for example AblSerialBaudRate
+    name: String,
+
+    /// instance_id of the tokens entry
+    #[structopt(long = "token-instance", parse(try_from_str = parse_int))]
+    instance_id: Option<u16>,
+
+    /// board_instance_mask of the tokens entry
+    #[structopt(long = "board-instance-mask", parse(try_from_str = parse_int))]
+    board_instance_mask: Option<u16>,
+}
+
+#[derive(Debug, StructOpt)]
+pub(crate) enum ApcbCommand {
+    /// Lists all the tokens
+    List {
+        #[structopt(flatten)]
+        selection: ApcbSelection,
+    },
+    /// Prints the value of a token
+    Get {
+        #[structopt(flatten)]
+        selection: ApcbSelection,
+        #[structopt(flatten)]
+        token: TokenAddress,
+    },
+    /// Changes the value of a token (or adds the token)
+    Set {
+        #[structopt(flatten)]
+        selection: ApcbSelection,
+        #[structopt(flatten)]
+        token: TokenAddress,
+        /// New value (as in the JSON5 config), for example 115200 or "Auto"
+        value: String,
+        /// Kind of the token (Bool, Byte, Word or Dword). Only needed when
+        /// adding a token
+        #[structopt(long = "kind")]
+        kind: Option<String>,
+        /// Write the result into this file instead of updating the input
+        #[structopt(short = "o", long = "output-file", parse(from_os_str))]
+        output_filename: Option<PathBuf>,
+    },
+}
+
+/// One token as found in the serde representation of an APCB.
+pub(crate) struct Token {
+    instance_id: u64,
+    board_instance_mask: u64,
+    kind: String,
+    pub(crate) name: String,
+    pub(crate) value: Value,
+    /// JSON pointer of the value
+    pub(crate) pointer: String,
+}
+
+/// Finds all tokens in APCB (the serde representation of an APCB).
+pub(crate) fn tokens(apcb: &Value) -> Vec<Token> {
+    let mut result = Vec::new();
+    let entries = apcb.get("entries").and_then(Value::as_array);
+    for (entry_index, entry) in entries.into_iter().flatten().enumerate() {
+        let header = &entry["header"];
+        let instance_id = header["instance_id"].as_u64().unwrap_or(0);
+        let board_instance_mask =
+            header["board_instance_mask"].as_u64().unwrap_or(0xffff);
+        let Some(entry_tokens) = entry.get("tokens").and_then(Value::as_array)
+        else {
+            continue;
+        };
+        for (token_index, token) in entry_tokens.iter().enumerate() {
+            let Some((kind, inner)) =
+                token.as_object().and_then(|x| x.iter().next())
+            else {
+                continue;
+            };
+            let prefix = format!("/entries/{entry_index}/tokens/{token_index}");
+            let (name, value, pointer) = if kind == "Unknown" {
+                // Tokens that amd-apcb does not know: Name them by their tag.
+                let tag = inner["tag"].as_u64().unwrap_or(0);
+                (
+                    format!("0x{tag:08x}"),
+                    inner["value"].clone(),
+                    format!("{prefix}/Unknown/value"),
+                )
+            } else {
+                let Some((name, value)) =
+                    inner.as_object().and_then(|x| x.iter().next())
+                else {
+                    continue;
+                };
+                (name.clone(), value.clone(), format!("{prefix}/{kind}/{name}"))
+            };
+            result.push(Token {
+                instance_id,
+                board_instance_mask,
+                kind: kind.clone(),
+                name,
+                value,
+                pointer,
+            });
+        }
+    }
+    result
+}
+
+fn matching_tokens<'a>(
+    tokens: &'a [Token],
+    address: &TokenAddress,
+) -> Vec<&'a Token> {
+    tokens
+        .iter()
+        .filter(|token| {
+            token.name == address.name
+                && address
+                    .instance_id
+                    .is_none_or(|x| u64::from(x) == token.instance_id)
+                && address
+                    .board_instance_mask
+                    .is_none_or(|x| u64::from(x) == token.board_instance_mask)
+        })
+        .collect()
+}
+
+fn format_value(value: &Value) -> String {
+    match value.as_u64() {
+        Some(x) => format!("{x} (0x{x:x})"),
+        None => value.to_string(),
+    }
+}
+
+fn print_token(token: &Token) {
+    println!(
+        "instance_id 0x{:04x} board_instance_mask 0x{:04x} {} {} = {}",
+        token.instance_id,
+        token.board_instance_mask,
+        token.kind,
+        token.name,
+        format_value(&token.value)
+    );
+}
+
+/// Parses VALUE (as written on the command line). Bare words are taken to
+/// be strings, so `Auto` means the same as `"Auto"`.
+fn parse_token_value(value: &str) -> Value {
+    json5::from_str::<Value>(value)
+        .unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// Changes the token ADDRESS in APCB (the serde representation of an APCB)
+/// to VALUE. If there is no such token yet, adds it to the tokens entry for
+/// KIND.
+fn set_token(
+    apcb: &mut Value,
+    address: &TokenAddress,
+    kind: Option<&str>,
+    value: Value,
+) -> std::io::Result<()> {
+    let all_tokens = tokens(apcb);
+    let matches = matching_tokens(&all_tokens, address);
+    match matches[..] {
+        [token] => {
+            *apcb.pointer_mut(&token.pointer).unwrap() = value;
+            return Ok(());
+        }
+        [] => {}
+        _ => {
+            for token in matches {
+                print_token(token);
+            }
+            return Err(std::io::Error::other(format!(
+                "Token {} is ambiguous. Hint: Use --token-instance or --board-instance-mask",
+                address.name
+            )));
+        }
+    }
+    let kind = kind.ok_or_else(|| {
+        std::io::Error::other(format!(
+            "Token {} not found. Hint: To add it, specify its --kind",
+            address.name
+        ))
+    })?;
+    let entry_id = match kind {
+        "Bool" => 0,
+        "Byte" => 1,
+        "Word" => 2,
+        "Dword" => 4,
+        _ => {
+            return Err(std::io::Error::other(format!(
+                "Unknown token kind {kind:?} (expected Bool, Byte, Word or Dword)"
+            )));
+        }
+    };
+    let instance_id = u64::from(address.instance_id.unwrap_or(0));
+    let mut candidates = apcb
+        .get_mut("entries")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            let header = &entry["header"];
+            header["group_id"].as_u64() == Some(TOKENS_GROUP_ID)
+                && header["entry_id"].as_u64() == Some(entry_id)
+                && header["instance_id"].as_u64() == Some(instance_id)
+                && address.board_instance_mask.is_none_or(|x| {
+                    header["board_instance_mask"].as_u64() == Some(x.into())
+                })
+                && entry["tokens"].is_array()
+        })
+        .collect::<Vec<_>>();
+    match &mut candidates[..] {
+        [entry] => {
+            let mut token = serde_json::Map::new();
+            token.insert(address.name.clone(), value);
+            let mut outer = serde_json::Map::new();
+            outer.insert(kind.to_string(), Value::Object(token));
+            entry["tokens"].as_array_mut().unwrap().push(Value::Object(outer));
+            Ok(())
+        }
+        [] => Err(std::io::Error::other(format!(
+            "There is no {kind} tokens entry with instance_id {instance_id} to add {} to",
+            address.name
+        ))),
+        _ => Err(std::io::Error::other(format!(
+            "There are multiple {kind} tokens entries to add {} to. Hint: Use --board-instance-mask",
+            address.name
+        ))),
+    }
+}
+
+/// Returns whether the BHD entry with the given attributes was selected by
+/// the user.
+fn is_selected(
+    selection: &ApcbSelection,
+    typ: BhdDirectoryEntryType,
+    instance: u8,
+    sub_program: u8,
+) -> bool {
+    matches!(
+        typ,
+        BhdDirectoryEntryType::Apcb | BhdDirectoryEntryType::ApcbBackup
+    ) && selection.entry_type.is_none_or(|x| x == typ)
+        && selection.entry_instance.is_none_or(|x| x == instance)
+        && selection.sub_program.is_none_or(|x| x == sub_program)
+}
+
+fn describe(
+    typ: BhdDirectoryEntryType,
+    instance: u8,
+    sub_program: u8,
+) -> String {
+    format!("{typ} (instance {instance}, sub_program {sub_program})")
+}
+
+fn apcb_to_io_error(e: amd_apcb::Error) -> std::io::Error {
+    std::io::Error::other(format!("APCB error: {e:?}"))
+}
+
+// Config files
+
+/// Finds the (first) Abl0 blob in DIRECTORY and returns its version.
+fn config_psp_abl_version(
+    directory: &SerdePspDirectory<'_>,
+    blobdirs: &[PathBuf],
+) -> Option<u32> {
+    directory.entries.iter().find_map(|entry| match &entry.source {
+        SerdePspEntrySource::BlobFile(blob_filename)
+            if entry.target.attrs.type_ == PspDirectoryEntryType::Abl0 =>
+        {
+            let blob_filename =
+                crate::resolve_blob(blobdirs, blob_filename.clone(), false)
+                    .ok()?;
+            crate::abl_file_version(&blob_filename)
+        }
+        SerdePspEntrySource::SecondLevelDirectory(d) => {
+            config_psp_abl_version(d, blobdirs)
+        }
+        SerdePspEntrySource::ImageSlotHeader(slot) => {
+            config_psp_abl_version(&slot.directory, blobdirs)
+        }
+        _ => None,
+    })
+}
+
+pub(crate) fn config_abl_version(
+    config: &SerdeConfig<'_>,
+    blobdirs: &[PathBuf],
+) -> Option<u32> {
+    match &config.psp {
+        SerdePspDirectoryVariant::PspDirectory(d) => {
+            config_psp_abl_version(d, blobdirs)
+        }
+        _ => None,
+    }
+}
+
+/// Finds the JSON pointers of all the selected ApcbJson entries in
+/// DIRECTORY (the serde representation of a BhdDirectory at POINTER).
+fn config_apcb_pointers(
+    selection: &ApcbSelection,
+    directory: &SerdeBhdDirectory<'_>,
+    pointer: &str,
+    result: &mut Vec<(String, String)>,
+) {
+    for (i, entry) in directory.entries.iter().enumerate() {
+        let attrs = &entry.target.attrs;
+        match (&entry.source, attrs.type_) {
+            (
+                SerdeBhdSource::ApcbJson(_),
+                SerdeBhdDirectoryEntryType::Known(typ),
+            ) if is_selected(
+                selection,
+                typ,
+                attrs.instance,
+                attrs.sub_program,
+            ) =>
+            {
+                result.push((
+                    describe(typ, attrs.instance, attrs.sub_program),
+                    format!("{pointer}/entries/{i}/source/ApcbJson"),
+                ));
+            }
+            (SerdeBhdSource::SecondLevelDirectory(d), _) => {
+                config_apcb_pointers(
+                    selection,
+                    d,
+                    &format!(
+                        "{pointer}/entries/{i}/source/SecondLevelDirectory"
+                    ),
+                    result,
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn config_apcbs(
+    selection: &ApcbSelection,
+    config: &SerdeConfig<'_>,
+) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    match &config.bhd {
+        SerdeBhdDirectoryVariant::BhdDirectory(d) => {
+            config_apcb_pointers(selection, d, "/bhd/BhdDirectory", &mut result)
+        }
+        _ => {
+            eprintln!("WARNING: BhdComboDirectory is not supported");
+        }
+    }
+    result
+}
+
+/// Validates all the APCBs in CONFIG. That's mostly so we fail before
+/// writing anything.
+fn validate_config_apcbs(
+    directory: &SerdeBhdDirectory<'_>,
+    processor_generation: ProcessorGeneration,
+    abl_version: Option<u32>,
+) -> std::io::Result<()> {
+    for entry in directory.entries.iter() {
+        match &entry.source {
+            SerdeBhdSource::ApcbJson(apcb) => {
+                if !crate::generate_is_context_valid(processor_generation, apcb)
+                {
+                    return Err(std::io::Error::other(
+                        "APCB context is not valid for this processor generation",
+                    ));
+                }
+                apcb.validate(abl_version).map_err(apcb_to_io_error)?;
+            }
+            SerdeBhdSource::SecondLevelDirectory(d) => {
+                validate_config_apcbs(d, processor_generation, abl_version)?
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// Flash images
+
+struct ImageApcb {
+    description: String,
+    location: Location,
+    size: usize,
+}
+
+/// The selected APCBs (and the ABL version) of a flash image.
+struct ImageApcbFinder<'a> {
+    selection: &'a ApcbSelection,
+    abl_version: Option<u32>,
+    apcbs: Vec<ImageApcb>,
+}
+
+impl DirectoryVisitor for ImageApcbFinder<'_> {
+    fn psp_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        _name: &str,
+    ) {
+        if self.abl_version.is_none()
+            && matches!(entry.typ_or_err(), Ok(PspDirectoryEntryType::Abl0))
+            && let Some(body) = crate::read_payload(
+                storage,
+                directory.payload_beginning(entry),
+                entry.size(),
+            )
+        {
+            self.abl_version =
+                crate::abl_version(&mut std::io::Cursor::new(body));
+        }
+    }
+
+    fn bhd_entry(
+        &mut self,
+        _storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        _name: &str,
+    ) {
+        if let Ok(typ) = entry.typ_or_err()
+            && is_selected(
+                self.selection,
+                typ,
+                entry.instance(),
+                entry.sub_program(),
+            )
+            && let Ok(location) = directory.payload_beginning(entry)
+            && let Some(size) = entry.size()
+        {
+            self.apcbs.push(ImageApcb {
+                description: describe(
+                    typ,
+                    entry.instance(),
+                    entry.sub_program(),
+                ),
+                location,
+                size: size as usize,
+            });
+        }
+    }
+}
+
+/// Finds the selected APCBs in the flash image STORAGE (loaded from
+/// INPUT_FILENAME). Returns the processor generation, the ABL version and
+/// the APCBs.
+fn image_apcbs(
+    selection: &ApcbSelection,
+    storage: &FlashImage,
+    input_filename: &Path,
+) -> std::io::Result<(ProcessorGeneration, Option<u32>, Vec<ImageApcb>)> {
+    let efs_to_io_error = |e| {
+        std::io::Error::other(format!(
+            "EFS error: {e:?} in file {input_filename:?}"
+        ))
+    };
+    let amd_physical_mode_mmio_size =
+        crate::amd_physical_mode_mmio_size(storage)?;
+    let efs = Efs::load(storage, None, amd_physical_mode_mmio_size)
+        .map_err(efs_to_io_error)?;
+    let mut finder =
+        ImageApcbFinder { selection, abl_version: None, apcbs: Vec::new() };
+    DirectoryWalker::new(storage, amd_physical_mode_mmio_size)
+        .walk_efs(&efs, &mut finder)
+        .map_err(efs_to_io_error)?;
+    Ok((
+        crate::efs_processor_generation(&efs),
+        finder.abl_version,
+        finder.apcbs,
+    ))
+}
+
+/// Returns the serde representation of the APCB BODY (of a flash image for
+/// PROCESSOR_GENERATION).
+fn image_apcb_value(
+    mut body: Vec<u8>,
+    processor_generation: ProcessorGeneration,
+) -> std::io::Result<Value> {
+    if body.len() < Apcb::MAX_SIZE {
+        body.resize(Apcb::MAX_SIZE, 0xFF);
+    }
+    let apcb = Apcb::load(
+        std::borrow::Cow::Owned(body),
+        &ApcbIoOptions::default()
+            .with_context(crate::dump_default_context(processor_generation))
+            .build(),
+    )
+    .map_err(apcb_to_io_error)?;
+    serde_json::to_value(&apcb).map_err(std::io::Error::other)
+}
+
+/// Loads the APCB at IMAGE_APCB and returns its serde representation.
+fn load_image_apcb(
+    storage: &FlashImage,
+    image_apcb: &ImageApcb,
+    processor_generation: ProcessorGeneration,
+) -> std::io::Result<Value> {
+    let mut buffer = vec![0xFFu8; image_apcb.size];
+    storage
+        .read_exact(image_apcb.location, &mut buffer)
+        .map_err(|e| std::io::Error::other(format!("Flash error: {e:?}")))?;
+    image_apcb_value(buffer, processor_generation)
+}
+
+/// The bodies of the APCBs of a flash image, by the description of the
+/// APCB.
+#[derive(Default)]
+pub(crate) struct ImageApcbBodies {
+    bodies: Vec<(String, Vec<u8>)>,
+}
+
+impl DirectoryVisitor for ImageApcbBodies {
+    fn bhd_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        _name: &str,
+    ) {
+        if let Ok(typ) = entry.typ_or_err()
+            && is_selected(
+                &ApcbSelection::default(),
+                typ,
+                entry.instance(),
+                entry.sub_program(),
+            )
+            && let Some(body) = crate::read_payload(
+                storage,
+                directory.payload_beginning(entry),
+                entry.size(),
+            )
+        {
+            self.bodies.push((
+                describe(typ, entry.instance(), entry.sub_program()),
+                body,
+            ));
+        }
+    }
+}
+
+impl ImageApcbBodies {
+    /// Returns the tokens of each APCB (of a flash image for
+    /// PROCESSOR_GENERATION), by the description of the APCB.
+    pub(crate) fn tokens(
+        &self,
+        processor_generation: ProcessorGeneration,
+    ) -> std::io::Result<Vec<(String, Vec<Token>)>> {
+        self.bodies
+            .iter()
+            .map(|(description, body)| {
+                let apcb =
+                    image_apcb_value(body.clone(), processor_generation)?;
+                Ok((description.clone(), tokens(&apcb)))
+            })
+            .collect()
+    }
+}
+
+// Commands
+
+fn selected_one<T>(mut items: Vec<T>, what: &str) -> std::io::Result<T> {
+    match items.len() {
+        1 => Ok(items.remove(0)),
+        0 => Err(std::io::Error::other(format!("No APCB found in {what}"))),
+        _ => Err(std::io::Error::other(format!(
+            "Multiple APCBs found in {what}. Hint: Use --entry-type, --entry-instance or --sub-program"
+        ))),
+    }
+}
+
+/// Loads the selected APCBs and returns (description, serde representation)
+/// for each.
+fn load_apcbs(
+    selection: &ApcbSelection,
+) -> std::io::Result<Vec<(String, Value)>> {
+    match (&selection.efs_configuration_filename, &selection.input_filename) {
+        (Some(efs_configuration_filename), None) => {
+            let data = std::fs::read_to_string(efs_configuration_filename)?;
+            let config =
+                crate::parse_config(&data, efs_configuration_filename)?;
+            let config_value =
+                serde_json::to_value(&config).map_err(std::io::Error::other)?;
+            Ok(config_apcbs(selection, &config)
+                .into_iter()
+                .map(|(description, pointer)| {
+                    (
+                        description,
+                        config_value.pointer(&pointer).unwrap().clone(),
+                    )
+                })
+                .collect())
+        }
+        (None, Some(input_filename)) => {
+            let storage = FlashImage::load(input_filename)?;
+            let (processor_generation, _, image_apcbs_found) =
+                image_apcbs(selection, &storage, input_filename)?;
+            image_apcbs_found
+                .iter()
+                .map(|image_apcb| {
+                    Ok((
+                        image_apcb.description.clone(),
+                        load_image_apcb(
+                            &storage,
+                            image_apcb,
+                            processor_generation,
+                        )?,
+                    ))
+                })
+                .collect()
+        }
+        _ => Err(std::io::Error::other(
+            "Please specify exactly one of --config and --existing-file",
+        )),
+    }
+}
+
+fn set_in_config(
+    selection: &ApcbSelection,
+    efs_configuration_filename: &Path,
+    address: &TokenAddress,
+    kind: Option<&str>,
+    value: Value,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    let data = std::fs::read_to_string(efs_configuration_filename)?;
+    let config = crate::parse_config(&data, efs_configuration_filename)?;
+    let abl_version = config_abl_version(&config, &selection.blobdirs);
+    if abl_version.is_none() {
+        eprintln!(
+            "WARNING: Could not find out ABL version. Hint: Use --blobdir so the Abl0 blob can be found"
+        );
+    }
+    let (_, pointer) = selected_one(
+        config_apcbs(selection, &config),
+        &format!("{efs_configuration_filename:?}"),
+    )?;
+    let mut config_value =
+        serde_json::to_value(&config).map_err(std::io::Error::other)?;
+    set_token(
+        config_value.pointer_mut(&pointer).unwrap(),
+        address,
+        kind,
+        value,
+    )?;
+    let config = SerdeConfig::deserialize(&config_value).map_err(|e| {
+        std::io::Error::other(format!("Config error: {e} after change"))
+    })?;
+    if let SerdeBhdDirectoryVariant::BhdDirectory(d) = &config.bhd {
+        validate_config_apcbs(d, config.processor_generation, abl_version)?;
+    }
+    let text = crate::dump_serializer::to_string_pretty(&config)
+        .map_err(std::io::Error::other)?;
+    std::fs::write(output_filename, format!("{text}\n"))
+}
+
+fn set_in_image(
+    selection: &ApcbSelection,
+    input_filename: &Path,
+    address: &TokenAddress,
+    kind: Option<&str>,
+    value: Value,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    let storage = FlashImage::load(input_filename)?;
+    let (processor_generation, abl_version, image_apcbs_found) =
+        image_apcbs(selection, &storage, input_filename)?;
+    let image_apcb =
+        selected_one(image_apcbs_found, &format!("{input_filename:?}"))?;
+    let mut apcb_value =
+        load_image_apcb(&storage, &image_apcb, processor_generation)?;
+    set_token(&mut apcb_value, address, kind, value)?;
+    let apcb = Apcb::deserialize(&apcb_value).map_err(|e| {
+        std::io::Error::other(format!("APCB error: {e} after change"))
+    })?;
+    apcb.validate(abl_version).map_err(apcb_to_io_error)?;
+    let buffer = apcb.save_no_inc().map_err(apcb_to_io_error)?;
+    if buffer.len() > image_apcb.size {
+        return Err(std::io::Error::other(format!(
+            "The changed APCB ({} Byte) does not fit into the existing {} entry ({} Byte). Hint: Change the config instead",
+            buffer.len(),
+            image_apcb.description,
+            image_apcb.size
+        )));
+    }
+    // Only write once everything checked out--and never leave a
+    // half-written OUTPUT_FILENAME (which can be INPUT_FILENAME) behind.
+    let mut image = std::fs::read(input_filename)?;
+    let beginning = image_apcb.location as usize;
+    image[beginning..beginning + buffer.len()].copy_from_slice(&buffer);
+    let mut temporary_filename = output_filename.as_os_str().to_owned();
+    temporary_filename.push(".tmp");
+    std::fs::write(&temporary_filename, &image)?;
+    std::fs::rename(&temporary_filename, output_filename)
+}
+
+pub(crate) fn run(command: ApcbCommand) -> std::io::Result<()> {
+    match command {
+        ApcbCommand::List { selection } => {
+            for (description, apcb) in load_apcbs(&selection)? {
+                println!("{description}:");
+                for token in tokens(&apcb) {
+                    print_token(&token);
+                }
+            }
+            Ok(())
+        }
+        ApcbCommand::Get { selection, token: address } => {
+            let mut found = false;
+            for (description, apcb) in load_apcbs(&selection)? {
+                let all_tokens = tokens(&apcb);
+                for token in matching_tokens(&all_tokens, &address) {
+                    print!("{description}: ");
+                    print_token(token);
+                    found = true;
+                }
+            }
+            if found {
+                Ok(())
+            } else {
+                Err(std::io::Error::other(format!(
+                    "Token {} not found",
+                    address.name
+                )))
+            }
+        }
+        ApcbCommand::Set {
+            selection,
+            token: address,
+            value,
+            kind,
+            output_filename,
+        } => {
+            let value = parse_token_value(&value);
+            match (
+                &selection.efs_configuration_filename,
+                &selection.input_filename,
+            ) {
+                (Some(efs_configuration_filename), None) => set_in_config(
+                    &selection,
+                    efs_configuration_filename,
+                    &address,
+                    kind.as_deref(),
+                    value,
+                    output_filename
+                        .as_deref()
+                        .unwrap_or(efs_configuration_filename),
+                ),
+                (None, Some(input_filename)) => set_in_image(
+                    &selection,
+                    input_filename,
+                    &address,
+                    kind.as_deref(),
+                    value,
+                    output_filename.as_deref().unwrap_or(input_filename),
+                ),
+                _ => Err(std::io::Error::other(
+                    "Please specify exactly one of --config and --existing-file",
+                )),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_set_token() {
+    let mut apcb = serde_json::json!({
+        "entries": [
+            {
+                "header": {
+                    "group_id": 0x3000,
+                    "entry_id": 4,
+                    "instance_id": 0,
+                    "board_instance_mask": 0xffff,
+                },
+                "tokens": [
+                    { "Dword": { "AblSerialBaudRate": "_115200" } },
+                    { "Unknown": { "entry_id": "Dword", "tag": 0x1234, "value": 5 } },
+                ],
+            },
+        ],
+    });
+    let address = |name: &str| TokenAddress {
+        name: name.to_string(),
+        instance_id: None,
+        board_instance_mask: None,
+    };
+    set_token(
+        &mut apcb,
+        &address("AblSerialBaudRate"),
+        None,
+        parse_token_value("_9600"),
+    )
+    .unwrap();
+    set_token(&mut apcb, &address("0x00001234"), None, parse_token_value("7"))
+        .unwrap();
+    assert!(
+        set_token(&mut apcb, &address("FchConsoleOutMode"), None, Value::Null)
+            .is_err()
+    );
+    set_token(
+        &mut apcb,
+        &address("FchConsoleOutMode"),
+        Some("Dword"),
+        parse_token_value("0"),
+    )
+    .unwrap();
+    let result = tokens(&apcb);
+    assert_eq!(result.len(), 3);
+    assert_eq!(result[0].value, Value::String("_9600".to_string()));
+    assert_eq!(result[1].value, Value::from(7));
+    assert_eq!(result[2].name, "FchConsoleOutMode");
+    assert_eq!(result[2].value, Value::from(0));
+}
@@ -0,0 +1,343 @@
+//! Static check whether the PSP could boot an existing flash image: finds
+//! the EFH where the PSP of the image's processor generation looks for it,
+//! follows the directory pointers from there (like the PSP), checks the
+//! directory checksums, checks that the entries without which nothing boots
+//! are there and checks that the reset vector is where the x86 cores start.
+
+use amd_efs::flash::{FlashRead, Location};
+use amd_efs::{
+    BhdDirectory, BhdDirectoryEntry, BhdDirectoryEntryType, Efs,
+    ProcessorGeneration, PspDirectory, PspDirectoryEntry,
+};
+use amd_host_image_builder_config::{
+    SerdeBhdDirectoryEntryType, SerdePspDirectoryEntryType,
+};
+use std::path::Path;
+
+use crate::image_slot;
+use crate::images::FlashImage;
+use crate::lint::{
+    APCB_ENTRY_TYPES, MANDATORY_BHD_ENTRY_TYPES, mandatory_psp_entry_types,
+};
+use crate::static_config;
+use crate::{DirectoryVisitor, DirectoryWalker};
+
+const DIRECTORY_HEADER_SIZE: usize = 16;
+const PSP_DIRECTORY_ENTRY_SIZE: usize = 16;
+const BHD_DIRECTORY_ENTRY_SIZE: usize = 24;
+/// More entries than that means that the directory header is garbage.
+const MAX_DIRECTORY_ENTRIES: usize = 0x400;
+
+/// Size of the (real-mode) segment whose top the reset vector is at
+const RESET_SEGMENT_SIZE: u64 = 0x1_0000;
+/// Distance of the reset vector from the top of its segment
+const RESET_VECTOR_OFFSET: u64 = 0x10;
+
+struct BootChecker<'a> {
+    storage: &'a FlashImage,
+    processor_generation: ProcessorGeneration,
+    /// Types of the mandatory entries found (with payload)
+    psp_entry_types: Vec<SerdePspDirectoryEntryType>,
+    bhd_entry_types: Vec<SerdeBhdDirectoryEntryType>,
+    reset_image_count: usize,
+    problems: Vec<String>,
+}
+
+impl BootChecker<'_> {
+    /// Checks the checksum of the directory at BEGINNING, whose entries are
+    /// ENTRY_SIZE Bytes each.
+    fn check_checksum(
+        &mut self,
+        beginning: Location,
+        entry_size: usize,
+        name: &str,
+    ) {
+        let mut header = [0u8; DIRECTORY_HEADER_SIZE];
+        if self.storage.read_exact(beginning, &mut header).is_err() {
+            self.problems.push(format!(
+                "{name}: directory header at {beginning:#x} cannot be read"
+            ));
+            return;
+        }
+        let u32_at = |data: &[u8], offset: usize| {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+        };
+        let total_entries = u32_at(&header, 8) as usize;
+        if total_entries > MAX_DIRECTORY_ENTRIES {
+            self.problems.push(format!(
+                "{name}: directory header at {beginning:#x} says it has {total_entries} entries"
+            ));
+            return;
+        }
+        let mut directory =
+            vec![0u8; DIRECTORY_HEADER_SIZE + total_entries * entry_size];
+        if self.storage.read_exact(beginning, &mut directory).is_err() {
+            self.problems.push(format!(
+                "{name}: directory at {beginning:#x} cannot be read"
+            ));
+            return;
+        }
+        let checksum = u32_at(&directory, 4);
+        let expected_checksum = image_slot::fletcher32(&directory[8..]);
+        if checksum != expected_checksum {
+            self.problems.push(format!(
+                "{name}: directory checksum is {checksum:#x}, but should be {expected_checksum:#x}"
+            ));
+        }
+    }
+
+    /// Returns whether the payload (of DESCRIPTION) at BEGINNING with SIZE
+    /// is not empty and can be read. Otherwise, records the problem.
+    fn check_payload(
+        &mut self,
+        beginning: amd_efs::Result<Location>,
+        size: Option<u32>,
+        description: &str,
+    ) -> bool {
+        let problem = match (beginning, size) {
+            (Err(e), _) => format!("{description}: payload not found: {e:?}"),
+            (_, None | Some(0)) => format!("{description}: payload is empty"),
+            (Ok(beginning), Some(size)) => {
+                let mut body = vec![0u8; size as usize];
+                if self.storage.read_exact(beginning, &mut body).is_ok() {
+                    return true;
+                }
+                format!(
+                    "{description}: payload at {beginning:#x} (size {size:#x}) is not in the flash image"
+                )
+            }
+        };
+        self.problems.push(problem);
+        false
+    }
+
+    /// Checks that the reset image (of SIZE Bytes) that is copied to
+    /// DESTINATION in RAM ends at the top of a segment, so that the reset
+    /// vector is in it (see AMD pub 55758 sec. 4.3 item 4).
+    fn check_reset_image(
+        &mut self,
+        destination: Option<u64>,
+        size: u32,
+        description: &str,
+    ) {
+        let Some(destination) = destination else {
+            self.problems.push(format!(
+                "{description}: reset image has no ram_destination_address"
+            ));
+            return;
+        };
+        let end = destination + u64::from(size);
+        if u64::from(size) < RESET_VECTOR_OFFSET
+            || end % RESET_SEGMENT_SIZE != 0
+        {
+            self.problems.push(format!(
+                "{description}: reset image at {destination:#x}..{end:#x} does not contain the reset vector at the top of a 64 KiB segment"
+            ));
+        }
+    }
+
+    /// Checks that the mandatory entries were found.
+    fn check_mandatory_entries(&mut self) {
+        let generation = self.processor_generation;
+        for &typ in mandatory_psp_entry_types(generation) {
+            let typ = SerdePspDirectoryEntryType::from(typ);
+            if !self.psp_entry_types.contains(&typ) {
+                self.problems.push(format!(
+                    "{typ} entry is missing (the PSP of {generation:?} needs it)"
+                ));
+            }
+        }
+        for typ in MANDATORY_BHD_ENTRY_TYPES {
+            let typ = SerdeBhdDirectoryEntryType::from(typ);
+            if !self.bhd_entry_types.contains(&typ) {
+                self.problems.push(format!(
+                    "{typ} entry is missing (the PSP of {generation:?} needs it)"
+                ));
+            }
+        }
+        if !APCB_ENTRY_TYPES
+            .iter()
+            .any(|&x| self.bhd_entry_types.contains(&x.into()))
+        {
+            self.problems.push(format!(
+                "Apcb or ApcbBackup entry is missing (the PSP of {generation:?} needs it)"
+            ));
+        }
+        if self.reset_image_count == 0 {
+            self.problems
+                .push("Bios entry with reset_image is missing".to_string());
+        }
+    }
+}
+
+impl DirectoryVisitor for BootChecker<'_> {
+    fn psp_directory(&mut self, directory: &PspDirectory, name: &str) {
+        self.check_checksum(
+            directory.beginning(),
+            PSP_DIRECTORY_ENTRY_SIZE,
+            name,
+        );
+    }
+
+    fn psp_entry(
+        &mut self,
+        _storage: &FlashImage,
+        directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        name: &str,
+    ) {
+        let typ = SerdePspDirectoryEntryType::from(entry);
+        if mandatory_psp_entry_types(self.processor_generation)
+            .iter()
+            .any(|&x| typ == x)
+            && self.check_payload(
+                directory.payload_beginning(entry),
+                entry.size(),
+                &format!("{name}: {typ} (instance {})", entry.instance()),
+            )
+        {
+            self.psp_entry_types.push(typ);
+        }
+    }
+
+    fn bhd_directory(&mut self, directory: &BhdDirectory, name: &str) {
+        self.check_checksum(
+            directory.beginning(),
+            BHD_DIRECTORY_ENTRY_SIZE,
+            name,
+        );
+    }
+
+    fn bhd_entry(
+        &mut self,
+        _storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        name: &str,
+    ) {
+        let beginning = directory.payload_beginning(entry);
+        let typ = SerdeBhdDirectoryEntryType::from(entry);
+        let description = format!(
+            "{name}: {typ} (instance {}, sub_program {})",
+            entry.instance(),
+            entry.sub_program()
+        );
+        if typ == BhdDirectoryEntryType::Bios && entry.reset_image() {
+            let size = entry.size();
+            if self.check_payload(beginning, size, &description) {
+                self.check_reset_image(
+                    entry.destination_location(),
+                    size.unwrap_or(0),
+                    &description,
+                );
+                self.reset_image_count += 1;
+            }
+        } else if MANDATORY_BHD_ENTRY_TYPES
+            .iter()
+            .chain(APCB_ENTRY_TYPES.iter())
+            .any(|&x| typ == x)
+            && self.check_payload(beginning, entry.size(), &description)
+        {
+            self.bhd_entry_types.push(typ);
+        }
+    }
+
+    fn directory_error(&mut self, message: String) {
+        self.problems.push(message);
+    }
+}
+
+/// Returns the problems that would keep the PSP of PROCESSOR_GENERATION
+/// from booting the flash image STORAGE.
+fn boot_problems(
+    storage: &FlashImage,
+    amd_physical_mode_mmio_size: Option<u32>,
+    processor_generation: ProcessorGeneration,
+) -> Vec<String> {
+    let mut checker = BootChecker {
+        storage,
+        processor_generation,
+        psp_entry_types: Vec::new(),
+        bhd_entry_types: Vec::new(),
+        reset_image_count: 0,
+        problems: Vec::new(),
+    };
+    let mut walker = DirectoryWalker::new(storage, amd_physical_mode_mmio_size);
+    // That's the only place the PSP looks for the EFH.
+    let efh_beginning = static_config::EFH_BEGINNING(processor_generation);
+    match Efs::load(storage, Some(efh_beginning), amd_physical_mode_mmio_size)
+    {
+        Ok(efs) => {
+            match efs.psp_directory() {
+                Ok(directory) => walker.walk_psp_directory(
+                    &directory,
+                    "PSP directory",
+                    &mut checker,
+                ),
+                Err(e) => checker
+                    .problems
+                    .push(format!("PSP directory cannot be loaded: {e:?}")),
+            }
+            // On Genoa and later, the level 2 PSP directories can point to
+            // the BHD directories instead.
+            match efs.bhd_directory(None) {
+                Ok(directory) => walker.walk_bhd_directory(
+                    &directory,
+                    "BHD directory",
+                    &mut checker,
+                ),
+                Err(e) if walker.bhd_directory_count == 0 => checker
+                    .problems
+                    .push(format!("BHD directory cannot be loaded: {e:?}")),
+                Err(_) => {}
+            }
+            checker.check_mandatory_entries();
+        }
+        Err(e) => checker.problems.push(format!(
+            "EFH not found at {efh_beginning:#x}, where the PSP of {processor_generation:?} looks for it: {e:?}"
+        )),
+    }
+    checker.problems
+}
+
+/// Checks whether the PSP could boot the flash image INPUT_FILENAME and
+/// prints the problems that would keep it from doing so.
+pub(crate) fn check_boot(input_filename: &Path) -> std::io::Result<()> {
+    let storage = FlashImage::load(input_filename)?;
+    let amd_physical_mode_mmio_size =
+        crate::amd_physical_mode_mmio_size(&storage)?;
+    // The PSP knows its processor generation; here, it is the one of the
+    // EFH found wherever it is.
+    let problems = match Efs::load(&storage, None, amd_physical_mode_mmio_size)
+    {
+        Ok(efs) => match crate::compatible_processor_generation(&efs) {
+            Some(processor_generation) => {
+                let problems = boot_problems(
+                    &storage,
+                    amd_physical_mode_mmio_size,
+                    processor_generation,
+                );
+                if problems.is_empty() {
+                    println!(
+                        "{input_filename:?} looks bootable on {processor_generation:?}"
+                    );
+                    return Ok(());
+                }
+                problems
+            }
+            None => vec![
+                "EFH is not for any known processor generation".to_string(),
+            ],
+        },
+        Err(e) => vec![format!(
+            "No EFH found, so the processor generation is unknown: {e:?}"
+        )],
+    };
+    for problem in &problems {
+        println!("{problem}");
+    }
+    Err(std::io::Error::other(format!(
+        "{} problem(s) with booting found in {input_filename:?}",
+        problems.len()
+    )))
+}
@@ -0,0 +1,164 @@
+//! Comments for dumped configs, so that they can be reviewed without
+//! looking at the binaries: For each entry with a payload in flash, its
+//! size, flash range, SHA-256 and (if known) version and security patch
+//! level; and for each APCB token with a numeric value, that value in hex.
+//!
+//! The annotations are keyed by the JSON pointer of the value they
+//! belong to (see dump_serializer::to_string_pretty_annotated).
+
+use amd_efs::flash::FlashRead;
+use serde_json::Value;
+use sha2::Digest;
+use std::collections::BTreeMap;
+
+use crate::apcb_tokens;
+use crate::psp_blob::PspBlobHeader;
+
+/// Returns the annotations for CONFIG (the serde representation of a
+/// config that was dumped from STORAGE).
+pub(crate) fn dump_annotations<T: FlashRead>(
+    storage: &T,
+    config: &Value,
+) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
+    if let Some(directory) = config.pointer("/psp/PspDirectory") {
+        psp_directory_annotations(
+            storage,
+            directory,
+            "/psp/PspDirectory",
+            &mut result,
+        );
+    }
+    if let Some(directory) = config.pointer("/bhd/BhdDirectory") {
+        bhd_directory_annotations(
+            storage,
+            directory,
+            "/bhd/BhdDirectory",
+            &mut result,
+        );
+    }
+    result
+}
+
+fn psp_directory_annotations<T: FlashRead>(
+    storage: &T,
+    directory: &Value,
+    pointer: &str,
+    result: &mut BTreeMap<String, String>,
+) {
+    let entries = directory.get("entries").and_then(Value::as_array);
+    for (i, entry) in entries.into_iter().flatten().enumerate() {
+        let pointer = format!("{pointer}/entries/{i}");
+        let source = &entry["source"];
+        if let Some(d) = source.get("SecondLevelDirectory") {
+            psp_directory_annotations(
+                storage,
+                d,
+                &format!("{pointer}/source/SecondLevelDirectory"),
+                result,
+            );
+        } else if let Some(d) = source.pointer("/ImageSlotHeader/directory") {
+            psp_directory_annotations(
+                storage,
+                d,
+                &format!("{pointer}/source/ImageSlotHeader/directory"),
+                result,
+            );
+        } else if let Some(d) = source.get("BhdDirectory") {
+            bhd_directory_annotations(
+                storage,
+                d,
+                &format!("{pointer}/source/BhdDirectory"),
+                result,
+            );
+        }
+        if let Some(annotation) = payload_annotation(storage, &entry["target"])
+        {
+            result.insert(pointer, annotation);
+        }
+    }
+}
+
+fn bhd_directory_annotations<T: FlashRead>(
+    storage: &T,
+    directory: &Value,
+    pointer: &str,
+    result: &mut BTreeMap<String, String>,
+) {
+    let entries = directory.get("entries").and_then(Value::as_array);
+    for (i, entry) in entries.into_iter().flatten().enumerate() {
+        let pointer = format!("{pointer}/entries/{i}");
+        let source = &entry["source"];
+        if let Some(d) = source.get("SecondLevelDirectory") {
+            bhd_directory_annotations(
+                storage,
+                d,
+                &format!("{pointer}/source/SecondLevelDirectory"),
+                result,
+            );
+        } else if let Some(apcb) = source.get("ApcbJson") {
+            let apcb_pointer = format!("{pointer}/source/ApcbJson");
+            for token in apcb_tokens::tokens(apcb) {
+                if let Some(value) = token.value.as_u64() {
+                    result.insert(
+                        format!("{apcb_pointer}{}", token.pointer),
+                        format!("0x{value:x}"),
+                    );
+                }
+            }
+        }
+        if let Some(annotation) = payload_annotation(storage, &entry["target"])
+        {
+            result.insert(pointer, annotation);
+        }
+    }
+}
+
+/// Returns the annotation for the payload of the directory entry TARGET
+/// (if it has a payload in flash).
+fn payload_annotation<T: FlashRead>(
+    storage: &T,
+    target: &Value,
+) -> Option<String> {
+    let beginning = target.get("flash_location")?.as_u64()?;
+    let size = target.get("size")?.as_u64()?;
+    let mut lines = vec![format!(
+        "size 0x{size:x}, flash 0x{beginning:x}..0x{:x}",
+        beginning + size
+    )];
+    let mut payload = vec![0u8; usize::try_from(size).ok()?];
+    if storage.read_exact(beginning.try_into().ok()?, &mut payload).is_ok() {
+        let digest = sha2::Sha256::digest(&payload);
+        lines.push(format!(
+            "SHA-256 {}",
+            digest.iter().map(|x| format!("{x:02x}")).collect::<String>()
+        ));
+        if let Some(header) = PspBlobHeader::parse(&payload) {
+            lines.push(format!("SPL 0x{:x}", header.security_patch_level()));
+        }
+        match target.get("type").and_then(Value::as_str) {
+            Some(
+                "Abl0" | "Abl1" | "Abl2" | "Abl3" | "Abl4" | "Abl5" | "Abl6"
+                | "Abl7",
+            ) => {
+                if let Some(v) =
+                    crate::abl_version(&mut std::io::Cursor::new(&payload))
+                {
+                    lines.push(format!("ABL version 0x{v:x}"));
+                }
+            }
+            Some("SmuOffChipFirmware8" | "SmuOffChipFirmware12") => {
+                match crate::smu_version(&mut &payload[..]) {
+                    Some((0, s1, s2, s3)) => lines
+                        .push(format!("SMU firmware version {s1}.{s2}.{s3}")),
+                    Some((s0, s1, s2, s3)) => lines.push(format!(
+                        "SMU firmware version {s0}.{s1}.{s2}.{s3}"
+                    )),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(lines.join("\n"))
+}
@@ -1,8 +1,11 @@
 /** Serializer that can do the following:
 - Serialize to JSON5 with little noise
 - If a field was skipped, note on stderr what was skipped
+- Emit comments (annotations) before values, selected by JSON pointer
+- Write addresses and flash locations in hex
  */
 use serde::{Serialize, ser};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::io::Write;
 
@@ -181,10 +184,15 @@ pub struct Json5Serializer<W: Write> {
     /// So we have SerializeMap set expect_symbol and any fn serialize_* reset expect_symbol.
     /// serialize_str is then actually writing either a symbol or a string literal depending on the need.
     expect_symbol: bool,
+    /// JSON pointer (RFC 6901) segments of the value currently being
+    /// serialized.
+    pointer: Vec<String>,
+    /// JSON pointer -> comment to write before the value there.
+    annotations: BTreeMap<String, String>,
 }
 
 impl<W: Write> Json5Serializer<W> {
-    fn new(writer: W) -> Self {
+    fn new(writer: W, annotations: BTreeMap<String, String>) -> Self {
         Json5Serializer {
             writer,
             line_number: 1,
@@ -192,6 +200,8 @@ impl<W: Write> Json5Serializer<W> {
             indent: 2,
             current_indent: 0,
             expect_symbol: false,
+            pointer: Vec::<String>::new(),
+            annotations,
         }
     }
 
@@ -222,6 +232,47 @@ impl<W: Write> Json5Serializer<W> {
         self.path.pop();
         self.current_indent = self.current_indent.saturating_sub(self.indent);
     }
+
+    /// Returns whether the current (unsigned integer) value is an address,
+    /// flash location or bit mask, and should therefore be written in hex.
+    fn current_value_is_address(&self) -> bool {
+        self.pointer.last().is_some_and(|x| {
+            x.ends_with("location")
+                || x.ends_with("address")
+                || x == "unknown_bits"
+        })
+    }
+
+    fn write_unsigned(&mut self, v: u64) -> Result<(), Error> {
+        self.expect_symbol = false;
+        if self.current_value_is_address() {
+            write!(self.writer, "0x{:x}", v)?;
+        } else {
+            write!(self.writer, "{}", v)?;
+        }
+        Ok(())
+    }
+
+    /// If there's an annotation for the current pointer, writes it as
+    /// comment lines (each with indentation and newline).
+    fn write_annotation(&mut self) -> Result<(), Error> {
+        if self.annotations.is_empty() {
+            return Ok(());
+        }
+        let pointer = self
+            .pointer
+            .iter()
+            .map(|x| format!("/{}", x.replace('~', "~0").replace('/', "~1")))
+            .collect::<String>();
+        if let Some(annotation) = self.annotations.get(&pointer).cloned() {
+            for line in annotation.lines() {
+                self.write_indent()?;
+                writeln!(self.writer, "// {}", line)?;
+                self.increase_line_number();
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -299,27 +350,19 @@ impl<'a, W: Write> serde::Serializer for &'a mut Json5Serializer<W> {
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.expect_symbol = false;
-        write!(self.writer, "{}", v)?;
-        Ok(())
+        self.write_unsigned(v.into())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.expect_symbol = false;
-        write!(self.writer, "{}", v)?;
-        Ok(())
+        self.write_unsigned(v.into())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.expect_symbol = false;
-        write!(self.writer, "{}", v)?;
-        Ok(())
+        self.write_unsigned(v.into())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.expect_symbol = false;
-        write!(self.writer, "{}", v)?;
-        Ok(())
+        self.write_unsigned(v)
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
@@ -429,10 +472,13 @@ impl<'a, W: Write> serde::Serializer for &'a mut Json5Serializer<W> {
         writeln!(self.writer, "{{")?;
         self.increase_line_number();
         self.increase_indent(variant);
+        self.pointer.push(variant.to_string());
+        self.write_annotation()?;
         self.write_indent()?;
         self.serialize_symbol(variant)?;
         write!(self.writer, ": ")?;
         value.serialize(&mut *self)?;
+        self.pointer.pop();
         self.decrease_indent();
         writeln!(self.writer)?;
         self.increase_line_number();
@@ -509,6 +555,7 @@ impl<'a, W: Write> serde::Serializer for &'a mut Json5Serializer<W> {
 pub struct SerializeVec<'a, W: 'a + Write> {
     serializer: &'a mut Json5Serializer<W>,
     first: bool,
+    index: usize,
 }
 
 pub struct SerializeMap<'a, W: 'a + Write> {
@@ -519,6 +566,7 @@ pub struct SerializeMap<'a, W: 'a + Write> {
 pub struct SerializeTupleVariant<'a, W: 'a + Write> {
     serializer: &'a mut Json5Serializer<W>,
     first: bool,
+    index: usize,
 }
 
 pub struct SerializeStructVariant<'a, W: 'a + Write> {
@@ -539,8 +587,13 @@ impl<W: Write> ser::SerializeSeq for SerializeVec<'_, W> {
             self.serializer.increase_line_number();
         }
         self.first = false;
+        self.serializer.pointer.push(self.index.to_string());
+        self.index += 1;
+        self.serializer.write_annotation()?;
         self.serializer.write_indent()?;
-        value.serialize(&mut *self.serializer)
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.pointer.pop();
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -558,7 +611,7 @@ impl<'a, W: Write> SerializeVec<'a, W> {
         writeln!(serializer.writer, "[")?;
         serializer.increase_line_number();
         serializer.increase_indent("");
-        Ok(Self { serializer, first: true })
+        Ok(Self { serializer, first: true, index: 0 })
     }
 }
 
@@ -606,10 +659,16 @@ impl<W: Write> ser::SerializeTupleVariant for SerializeTupleVariant<'_, W> {
             write!(self.serializer.writer, ", ")?;
         }
         self.first = false;
-        value.serialize(&mut *self.serializer)
+        // Note: The fields are all on one line, so they can't be annotated.
+        self.serializer.pointer.push(self.index.to_string());
+        self.index += 1;
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.pointer.pop();
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.serializer.pointer.pop();
         self.serializer.decrease_indent();
         writeln!(self.serializer.writer)?;
         self.serializer.increase_line_number();
@@ -632,6 +691,8 @@ impl<'a, W: Write> SerializeStructVariant<'a, W> {
         writeln!(serializer.writer, "{{")?;
         serializer.increase_line_number();
         serializer.increase_indent(variant);
+        serializer.pointer.push(variant.to_string());
+        serializer.write_annotation()?;
         serializer.write_indent()?;
         serializer.serialize_symbol(variant)?;
         writeln!(serializer.writer, ": {{")?;
@@ -649,11 +710,13 @@ impl<'a, W: Write> SerializeTupleVariant<'a, W> {
         writeln!(serializer.writer, "{{")?;
         serializer.increase_line_number();
         serializer.increase_indent(variant);
+        serializer.pointer.push(variant.to_string());
+        serializer.write_annotation()?;
         serializer.write_indent()?;
         serializer.serialize_symbol(variant)?;
         write!(serializer.writer, ": [")?;
         serializer.increase_indent(variant);
-        Ok(Self { serializer, first: true })
+        Ok(Self { serializer, first: true, index: 0 })
     }
 }
 
@@ -671,6 +734,12 @@ impl<W: Write> ser::SerializeMap for SerializeMap<'_, W> {
             self.serializer.increase_line_number();
         }
         self.first = false;
+        self.serializer.pointer.push(match serde_json::to_value(key) {
+            Ok(serde_json::Value::String(key)) => key,
+            Ok(key) => key.to_string(),
+            Err(_) => String::new(),
+        });
+        self.serializer.write_annotation()?;
         self.serializer.write_indent()?;
 
         let old_expect_symbol = self.serializer.expect_symbol;
@@ -685,7 +754,9 @@ impl<W: Write> ser::SerializeMap for SerializeMap<'_, W> {
     where
         T: Serialize + ?Sized,
     {
-        value.serialize(&mut *self.serializer)
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.pointer.pop();
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -737,6 +808,8 @@ impl<W: Write> ser::SerializeStruct for SerializeMap<'_, W> {
             self.serializer.increase_line_number();
         }
         self.first = false;
+        self.serializer.pointer.push(key.to_string());
+        self.serializer.write_annotation()?;
         self.serializer.write_indent()?;
         self.serializer.serialize_symbol(key)?;
         write!(self.serializer.writer, ": ")?;
@@ -775,10 +848,14 @@ impl<W: Write> ser::SerializeStructVariant for SerializeStructVariant<'_, W> {
             self.serializer.increase_line_number();
         }
         self.first = false;
+        self.serializer.pointer.push(key.to_string());
+        self.serializer.write_annotation()?;
         self.serializer.write_indent()?;
         self.serializer.serialize_symbol(key)?;
         write!(self.serializer.writer, ": ")?;
-        value.serialize(&mut *self.serializer)
+        value.serialize(&mut *self.serializer)?;
+        self.serializer.pointer.pop();
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
@@ -787,6 +864,7 @@ impl<W: Write> ser::SerializeStructVariant for SerializeStructVariant<'_, W> {
         self.serializer.increase_line_number();
         self.serializer.write_indent()?;
         write!(self.serializer.writer, "}}")?;
+        self.serializer.pointer.pop();
         self.serializer.decrease_indent();
         writeln!(self.serializer.writer)?;
         self.serializer.increase_line_number();
@@ -811,7 +889,21 @@ where
     W: Write,
     T: ?Sized + Serialize,
 {
-    let mut serializer = Json5Serializer::new(writer);
+    to_writer_annotated(writer, value, BTreeMap::new())
+}
+
+/// Like to_writer, but writes the respective entry of ANNOTATIONS (JSON
+/// pointer -> text) as a comment before each value that has one.
+pub fn to_writer_annotated<W, T>(
+    writer: W,
+    value: &T,
+    annotations: BTreeMap<String, String>,
+) -> Result<(), Error>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Json5Serializer::new(writer, annotations);
     value.serialize(&mut serializer)?;
     Ok(())
 }
@@ -829,6 +921,19 @@ where
     Ok(result)
 }
 
+pub fn to_string_pretty_annotated<T>(
+    value: &T,
+    annotations: BTreeMap<String, String>,
+) -> Result<String, Error>
+where
+    T: Serialize,
+{
+    let mut buffer = Vec::new();
+    to_writer_annotated(&mut buffer, value, annotations)?;
+    let result = String::from_utf8(buffer).unwrap();
+    Ok(result)
+}
+
 #[test]
 fn test_struct() {
     use serde::Serialize;
@@ -846,3 +951,79 @@ fn test_struct() {
     assert!(!json5_string.contains('a'));
     assert!(json5_string.contains('b'));
 }
+
+#[test]
+fn test_annotations() {
+    use serde::Serialize;
+    #[derive(Serialize)]
+    enum Source {
+        Blob { size: u32 },
+    }
+    #[derive(Serialize)]
+    struct Entry {
+        source: Source,
+        tokens: BTreeMap<String, u32>,
+    }
+    #[derive(Serialize)]
+    struct Test {
+        entries: Vec<Entry>,
+    }
+
+    let test = Test {
+        entries: vec![Entry {
+            source: Source::Blob { size: 16 },
+            tokens: BTreeMap::from([("a/b".to_string(), 10)]),
+        }],
+    };
+    let annotations = BTreeMap::from([
+        ("/entries/0".to_string(), "first\nentry".to_string()),
+        ("/entries/0/source/Blob/size".to_string(), "0x10".to_string()),
+        ("/entries/0/tokens/a~1b".to_string(), "0xa".to_string()),
+    ]);
+    let json5_string = to_string_pretty_annotated(&test, annotations).unwrap();
+    assert_eq!(
+        json5_string,
+        r#"{
+  entries: [
+    // first
+    // entry
+    {
+      source: {
+        Blob: {
+          // 0x10
+          size: 16
+        }
+      },
+      tokens: {
+        // 0xa
+        "a/b": 10
+      }
+    }
+  ]
+}"#
+    );
+    let value: serde_json::Value = json5::from_str(&json5_string).unwrap();
+    assert_eq!(value["entries"][0]["tokens"]["a/b"], 10);
+}
+
+#[test]
+fn test_addresses_in_hex() {
+    use serde::Serialize;
+    #[derive(Serialize)]
+    struct Test {
+        flash_location: Option<u32>,
+        ram_destination_address: u64,
+        size: u32,
+    }
+
+    let test = Test {
+        flash_location: Some(0x3000),
+        ram_destination_address: 0x7600_0000,
+        size: 4096,
+    };
+    let json5_string = to_string_pretty(&test).unwrap();
+    assert_eq!(
+        json5_string,
+        "{\n  flash_location: 0x3000,\n  ram_destination_address: 0x76000000,\n  size: 4096\n}"
+    );
+}
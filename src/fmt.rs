@@ -0,0 +1,89 @@
+//! Rewrites configs in canonical form--that is, the way `dump` would write
+//! them: fields in a fixed order, addresses and flash locations in hex,
+//! and keys only quoted where necessary.
+//!
+//! Since the config is rewritten from its parsed form, comments would be
+//! lost, so configs with comments are refused.
+
+use std::path::{Path, PathBuf};
+
+/// Returns whether the JSON5 text DATA has a comment (outside of strings).
+/// Note: This only decides whether to refuse; json5 does the parsing.
+fn has_comments(data: &str) -> bool {
+    let mut chars = data.chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == '\\' {
+                    chars.next();
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '/' if matches!(chars.peek(), Some('/' | '*')) => {
+                    return true;
+                }
+                _ => {}
+            },
+        }
+    }
+    false
+}
+
+/// Returns the canonical form of the config in EFS_CONFIGURATION_FILENAME.
+fn canonical_config(
+    efs_configuration_filename: &Path,
+) -> std::io::Result<String> {
+    let data = std::fs::read_to_string(efs_configuration_filename)?;
+    if has_comments(&data) {
+        return Err(std::io::Error::other(format!(
+            "{efs_configuration_filename:?} has comments, which fmt would remove; refusing to rewrite it"
+        )));
+    }
+    let config = crate::parse_config(&data, efs_configuration_filename)?;
+    let text = crate::dump_serializer::to_string_pretty(&config)
+        .map_err(std::io::Error::other)?;
+    Ok(format!("{text}\n"))
+}
+
+/// Rewrites each of EFS_CONFIGURATION_FILENAMES in canonical form.
+/// If CHECK, leaves the files alone and instead fails if any of them is not
+/// in canonical form.
+pub(crate) fn fmt(
+    efs_configuration_filenames: &[PathBuf],
+    check: bool,
+) -> std::io::Result<()> {
+    let mut noncanonical_filenames = Vec::new();
+    for filename in efs_configuration_filenames {
+        let text = canonical_config(filename)?;
+        if std::fs::read_to_string(filename)? == text {
+            continue;
+        }
+        if check {
+            eprintln!("{filename:?} is not in canonical form");
+            noncanonical_filenames.push(filename);
+        } else {
+            std::fs::write(filename, text)?;
+        }
+    }
+    if noncanonical_filenames.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{} file(s) not in canonical form: {:?}",
+            noncanonical_filenames.len(),
+            noncanonical_filenames
+        )))
+    }
+}
+
+#[test]
+fn test_has_comments() {
+    assert!(!has_comments(r#"{ a: "http://example.com", b: '/*' }"#));
+    assert!(!has_comments(r#"{ a: "\"//" }"#));
+    assert!(has_comments("{ a: 1 // one\n}"));
+    assert!(has_comments("{ /* none */ }"));
+}
@@ -0,0 +1,132 @@
+//! Image Slot Headers (ISH).
+//!
+//! Instead of a single second-level PSP directory, Genoa and later can have
+//! two level 2 PSP directories ("A" and "B"). The first-level PSP directory
+//! then has one entry per slot, and the payload of that entry is an Image
+//! Slot Header that says where the level 2 directory is and how the PSP is
+//! supposed to choose between the slots.  The level 2 directory has an entry
+//! that points to the corresponding level 2 BHD directory.
+//!
+//! Entries with those types are matched by number since amd-efs doesn't
+//! know them (by name).
+
+use amd_efs::PspDirectoryEntry;
+use amd_efs::flash::Location;
+use zerocopy::IntoBytes;
+
+/// PSP directory entry type whose payload is the Image Slot Header of the
+/// level 2 A directory.
+pub(crate) const PSP_LEVEL_2A_DIRECTORY_ENTRY_TYPE: u8 = 0x48;
+/// PSP directory entry type whose payload is the Image Slot Header of the
+/// level 2 B directory.
+pub(crate) const PSP_LEVEL_2B_DIRECTORY_ENTRY_TYPE: u8 = 0x4a;
+/// PSP directory entry type (in a level 2 PSP directory) whose payload is
+/// the level 2 BHD directory.
+pub(crate) const BHD_LEVEL_2_DIRECTORY_ENTRY_TYPE: u8 = 0x49;
+
+pub(crate) const IMAGE_SLOT_HEADER_SIZE: usize = 32;
+
+/// Returns the raw type number of ENTRY.
+pub(crate) fn psp_entry_type(entry: &PspDirectoryEntry) -> u8 {
+    entry.as_bytes()[0]
+}
+
+/// Returns whether ENTRY's payload is an Image Slot Header.
+pub(crate) fn is_image_slot_entry(entry: &PspDirectoryEntry) -> bool {
+    matches!(
+        psp_entry_type(entry),
+        PSP_LEVEL_2A_DIRECTORY_ENTRY_TYPE | PSP_LEVEL_2B_DIRECTORY_ENTRY_TYPE
+    )
+}
+
+/// Fletcher-32 over the little-endian 16-bit words in DATA, the way the PSP
+/// checks the Image Slot Header (and directories).
+pub(crate) fn fletcher32(data: &[u8]) -> u32 {
+    let mut c0: u32 = 0xffff;
+    let mut c1: u32 = 0xffff;
+    // 359 is the largest number of words that can be summed up before c1
+    // overflows.
+    for block in data.chunks(2 * 359) {
+        for word in block.chunks_exact(2) {
+            c0 += u32::from(u16::from_le_bytes([word[0], word[1]]));
+            c1 += c0;
+        }
+        c0 = (c0 & 0xffff) + (c0 >> 16);
+        c1 = (c1 & 0xffff) + (c1 >> 16);
+    }
+    c0 = (c0 & 0xffff) + (c0 >> 16);
+    c1 = (c1 & 0xffff) + (c1 >> 16);
+    (c1 << 16) | c0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ImageSlotHeader {
+    pub(crate) boot_priority: u32,
+    pub(crate) update_retry_count: u32,
+    pub(crate) glitch_retry_count: u8,
+    /// Flash location of the level 2 PSP directory
+    pub(crate) pl2_location: Location,
+    pub(crate) psp_id: u32,
+    pub(crate) slot_max_size: u32,
+}
+
+impl ImageSlotHeader {
+    pub(crate) fn to_bytes(self) -> [u8; IMAGE_SLOT_HEADER_SIZE] {
+        let mut result = [0u8; IMAGE_SLOT_HEADER_SIZE];
+        result[4..8].copy_from_slice(&self.boot_priority.to_le_bytes());
+        result[8..12].copy_from_slice(&self.update_retry_count.to_le_bytes());
+        result[12] = self.glitch_retry_count;
+        // 13..16 are reserved.
+        result[16..20].copy_from_slice(&self.pl2_location.to_le_bytes());
+        result[20..24].copy_from_slice(&self.psp_id.to_le_bytes());
+        result[24..28].copy_from_slice(&self.slot_max_size.to_le_bytes());
+        // 28..32 are reserved.
+        let checksum = fletcher32(&result[4..]);
+        result[0..4].copy_from_slice(&checksum.to_le_bytes());
+        result
+    }
+
+    /// Parses the Image Slot Header in DATA. Returns None if DATA is too
+    /// short or the checksum is wrong.
+    pub(crate) fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.get(0..IMAGE_SLOT_HEADER_SIZE)?;
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+        };
+        if u32_at(0) != fletcher32(&data[4..]) {
+            return None;
+        }
+        Some(Self {
+            boot_priority: u32_at(4),
+            update_retry_count: u32_at(8),
+            glitch_retry_count: data[12],
+            pl2_location: u32_at(16),
+            psp_id: u32_at(20),
+            slot_max_size: u32_at(24),
+        })
+    }
+}
+
+#[test]
+fn test_image_slot_header_round_trip() {
+    let header = ImageSlotHeader {
+        boot_priority: 1,
+        update_retry_count: 2,
+        glitch_retry_count: 3,
+        pl2_location: 0x12_3000,
+        psp_id: 0xbc0c_0140,
+        slot_max_size: 0x40_0000,
+    };
+    let mut bytes = header.to_bytes();
+    assert_eq!(&bytes[16..20], &[0x00, 0x30, 0x12, 0x00]);
+    assert_eq!(ImageSlotHeader::from_bytes(&bytes), Some(header));
+    bytes[4] ^= 1;
+    assert_eq!(ImageSlotHeader::from_bytes(&bytes), None);
+    assert_eq!(ImageSlotHeader::from_bytes(&bytes[..31]), None);
+}
+
+#[test]
+fn test_fletcher32() {
+    assert_eq!(fletcher32(&[0u8; 28]), 0xffff_ffff);
+    assert_eq!(fletcher32(&[1, 0, 2, 0]), 0x0004_0003);
+}
@@ -0,0 +1,405 @@
+//! Semantic checks of configs--that is, mistakes that the schema can't
+//! catch and that `generate` only finds late (or not at all).
+//!
+//! All the findings are reported at once.
+
+use amd_efs::{
+    BhdDirectoryEntryType, ProcessorGeneration, PspDirectoryEntryType,
+};
+use amd_host_image_builder_config::{
+    SerdeBhdDirectory, SerdeBhdDirectoryEntryType, SerdeBhdDirectoryVariant,
+    SerdeBhdSource, SerdeConfig, SerdePspDirectory, SerdePspDirectoryEntryType,
+    SerdePspDirectoryVariant, SerdePspEntrySource, SerdePspEntrySourceValue,
+    SourceSpan,
+};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::static_config::ERASABLE_BLOCK_SIZE;
+
+/// PSP directory entry types without which the given processor generation
+/// does not boot.
+pub(crate) fn mandatory_psp_entry_types(
+    processor_generation: ProcessorGeneration,
+) -> &'static [PspDirectoryEntryType] {
+    use PspDirectoryEntryType as T;
+    match processor_generation {
+        ProcessorGeneration::Naples
+        | ProcessorGeneration::Rome
+        | ProcessorGeneration::Milan
+        | ProcessorGeneration::Genoa => &[
+            T::AmdPublicKey,
+            T::PspBootloader,
+            T::SmuOffChipFirmware8,
+            T::SmuOffChipFirmware12,
+            T::Abl0,
+        ],
+        ProcessorGeneration::Turin => &[
+            T::AmdPublicKey,
+            T::PspBootloader,
+            T::SmuOffChipFirmware8,
+            T::Abl0,
+        ],
+    }
+}
+
+/// BHD directory entry types without which no processor generation boots.
+/// Note: In addition, there has to be one of APCB_ENTRY_TYPES.
+pub(crate) const MANDATORY_BHD_ENTRY_TYPES: [BhdDirectoryEntryType; 2] = [
+    BhdDirectoryEntryType::PmuFirmwareInstructions,
+    BhdDirectoryEntryType::PmuFirmwareData,
+];
+
+/// BHD directory entry types of which at least one has to be there.
+pub(crate) const APCB_ENTRY_TYPES: [BhdDirectoryEntryType; 2] =
+    [BhdDirectoryEntryType::Apcb, BhdDirectoryEntryType::ApcbBackup];
+
+struct Linter<'b> {
+    efs_configuration_filename: &'b Path,
+    processor_generation: ProcessorGeneration,
+    abl_version: Option<u32>,
+    blobdirs: &'b [PathBuf],
+    findings: Vec<String>,
+    /// Description, source span, beginning and end of each payload with a
+    /// fixed flash location
+    fixed_payloads: Vec<(String, Option<SourceSpan>, u64, u64)>,
+    psp_types: Vec<SerdePspDirectoryEntryType>,
+    bhd_types: Vec<SerdeBhdDirectoryEntryType>,
+    bios_reset_entry: bool,
+}
+
+impl Linter<'_> {
+    /// Records the finding MESSAGE about the entry at SPAN (if known).
+    fn report(&mut self, span: Option<&SourceSpan>, message: &str) {
+        let filename = self.efs_configuration_filename;
+        self.findings.push(match span {
+            Some(span) => span.render(filename, message),
+            None => format!("{filename:?}: {message}"),
+        });
+    }
+
+    /// Returns the size of the blob BLOB_FILENAME, if it can be found.
+    fn blob_size(&self, blob_filename: &Path) -> Option<u64> {
+        let blob_filename =
+            crate::resolve_blob(self.blobdirs, blob_filename.into(), false)
+                .ok()?;
+        Some(std::fs::metadata(blob_filename).ok()?.len())
+    }
+
+    fn check_fixed_location(
+        &mut self,
+        description: &str,
+        span: Option<&SourceSpan>,
+        flash_location: Option<u32>,
+        size: Option<u64>,
+    ) {
+        let Some(beginning) = flash_location else {
+            return;
+        };
+        if beginning as usize % ERASABLE_BLOCK_SIZE != 0 {
+            self.report(span, &format!(
+                "{description}: flash_location 0x{beginning:x} is not aligned to the erasable block size 0x{ERASABLE_BLOCK_SIZE:x}"
+            ));
+        }
+        if let Some(size) = size {
+            let beginning = u64::from(beginning);
+            self.fixed_payloads.push((
+                description.to_string(),
+                span.cloned(),
+                beginning,
+                beginning + size,
+            ));
+        }
+    }
+
+    /// Checks the soft fuse chain VALUE of the entry DESCRIPTION (at SPAN).
+    fn check_soft_fuse_chain(
+        &mut self,
+        description: &str,
+        span: Option<&SourceSpan>,
+        value: &SerdePspEntrySourceValue,
+    ) {
+        match value {
+            SerdePspEntrySourceValue::PspSoftFuseChain(chain) => {
+                if let Err(e) = chain.to_u64() {
+                    self.report(span, &format!("{description}: {e}"));
+                }
+                for name in chain.invalid_fields(self.processor_generation) {
+                    self.report(span, &format!(
+                        "{description}: soft fuse {name} does not exist on {:?}",
+                        self.processor_generation
+                    ));
+                }
+            }
+            SerdePspEntrySourceValue::Unknown(x) => {
+                self.report(span, &format!(
+                    "{description}: soft fuse chain is given as the number {x:#x}; write it as {}",
+                    crate::soft_fuses::config_source_value(&(*x).into())
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    fn lint_psp_directory(
+        &mut self,
+        directory: &SerdePspDirectory<'_>,
+        name: &str,
+    ) {
+        let mut keys = HashSet::<String>::new();
+        for entry in directory.entries.iter() {
+            let attrs = &entry.target.attrs;
+            let key = format!(
+                "{} (instance {}, sub_program {}, rom_id {:?})",
+                attrs.type_, attrs.instance, attrs.sub_program, attrs.rom_id
+            );
+            let description = format!("{name}: {key}");
+            let span = entry.span.as_ref();
+            if !keys.insert(key) {
+                self.report(span, &format!("{description}: duplicate entry"));
+            }
+            self.psp_types.push(attrs.type_);
+            let blob = entry.target.blob.as_ref();
+            let size = match &entry.source {
+                SerdePspEntrySource::BlobFile(blob_filename) => {
+                    self.blob_size(blob_filename)
+                }
+                SerdePspEntrySource::SecondLevelDirectory(d) => {
+                    self.lint_psp_directory(
+                        d,
+                        &format!("{name} > second level PSP directory"),
+                    );
+                    None
+                }
+                SerdePspEntrySource::ImageSlotHeader(slot) => {
+                    self.lint_psp_directory(
+                        &slot.directory,
+                        &format!(
+                            "{name} > {} level 2 PSP directory",
+                            attrs.type_
+                        ),
+                    );
+                    None
+                }
+                SerdePspEntrySource::BhdDirectory(d) => {
+                    self.lint_bhd_directory(
+                        d,
+                        &format!("{name} > level 2 BHD directory"),
+                    );
+                    None
+                }
+                SerdePspEntrySource::Value(x) => {
+                    if attrs.type_
+                        == PspDirectoryEntryType::PspSoftFuseChain.into()
+                    {
+                        self.check_soft_fuse_chain(&description, span, x);
+                    }
+                    None
+                }
+            };
+            self.check_fixed_location(
+                &description,
+                span,
+                blob.and_then(|x| x.flash_location),
+                blob.and_then(|x| x.size).map(u64::from).or(size),
+            );
+        }
+    }
+
+    fn lint_bhd_directory(
+        &mut self,
+        directory: &SerdeBhdDirectory<'_>,
+        name: &str,
+    ) {
+        let mut keys = HashSet::<String>::new();
+        for entry in directory.entries.iter() {
+            let attrs = &entry.target.attrs;
+            let key = crate::bhd_entry_key(attrs);
+            let description = format!("{name}: {key}");
+            let span = entry.span.as_ref();
+            if !keys.insert(key) {
+                self.report(span, &format!("{description}: duplicate entry"));
+            }
+            self.bhd_types.push(attrs.type_);
+            if attrs.type_ == BhdDirectoryEntryType::Bios && attrs.reset_image {
+                self.bios_reset_entry = true;
+            }
+            let blob = entry.target.blob.as_ref();
+            let mut flash_location = blob.and_then(|x| x.flash_location);
+            let size = match &entry.source {
+                SerdeBhdSource::Implied => {
+                    if attrs.type_ != BhdDirectoryEntryType::Apob {
+                        self.report(span, &format!(
+                            "{description}: Implied source is only supported for Apob"
+                        ));
+                    }
+                    // AMD sometimes uses flash_location 0 to mean "No flash
+                    // location" (see generate).
+                    flash_location = flash_location.filter(|&x| x != 0);
+                    if flash_location.is_some() {
+                        self.report(span, &format!(
+                            "{description}: Implied source with a fixed flash_location"
+                        ));
+                    }
+                    None
+                }
+                SerdeBhdSource::BlobFile(blob_filename) => {
+                    self.blob_size(blob_filename)
+                }
+                SerdeBhdSource::ElfFile(_) => {
+                    // There's one entry per segment; so there is no single
+                    // place to put them.
+                    if flash_location.is_some() {
+                        self.report(span, &format!(
+                            "{description}: ElfFile source with a fixed flash_location"
+                        ));
+                    }
+                    if blob.and_then(|x| x.ram_destination_address).is_some() {
+                        self.report(span, &format!(
+                            "{description}: ElfFile source with a ram_destination_address (the ELF file has the addresses)"
+                        ));
+                    }
+                    None
+                }
+                SerdeBhdSource::ApcbJson(apcb) => {
+                    if !crate::generate_is_context_valid(
+                        self.processor_generation,
+                        apcb,
+                    ) {
+                        self.report(span, &format!(
+                            "{description}: APCB context is not valid for {:?}",
+                            self.processor_generation
+                        ));
+                    }
+                    if let Err(e) = apcb.validate(self.abl_version) {
+                        self.report(
+                            span,
+                            &format!(
+                                "{description}: APCB validation failed: {e:?}"
+                            ),
+                        );
+                    }
+                    None
+                }
+                SerdeBhdSource::SecondLevelDirectory(d) => {
+                    self.lint_bhd_directory(
+                        d,
+                        &format!("{name} > second level BHD directory"),
+                    );
+                    None
+                }
+            };
+            self.check_fixed_location(
+                &description,
+                span,
+                flash_location,
+                blob.and_then(|x| x.size).map(u64::from).or(size),
+            );
+        }
+    }
+
+    fn lint_overlaps(&mut self) {
+        let mut fixed_payloads = std::mem::take(&mut self.fixed_payloads);
+        fixed_payloads.sort_by_key(|(_, _, beginning, _)| *beginning);
+        for (i, (a, _, _, a_end)) in fixed_payloads.iter().enumerate() {
+            for (b, b_span, b_beginning, _) in &fixed_payloads[i + 1..] {
+                if b_beginning >= a_end {
+                    break;
+                }
+                self.report(b_span.as_ref(), &format!(
+                    "{a} and {b}: flash ranges overlap (0x{b_beginning:x}..0x{a_end:x})"
+                ));
+            }
+        }
+    }
+
+    fn lint_mandatory_entries(&mut self, reset_image_given: bool) {
+        for &typ in mandatory_psp_entry_types(self.processor_generation) {
+            if !self.psp_types.contains(&typ.into()) {
+                self.report(
+                    None,
+                    &format!(
+                        "Missing mandatory PSP entry {}",
+                        SerdePspDirectoryEntryType::from(typ)
+                    ),
+                );
+            }
+        }
+        for typ in MANDATORY_BHD_ENTRY_TYPES {
+            if !self.bhd_types.contains(&typ.into()) {
+                self.report(
+                    None,
+                    &format!(
+                        "Missing mandatory BHD entry {}",
+                        SerdeBhdDirectoryEntryType::from(typ)
+                    ),
+                );
+            }
+        }
+        if !APCB_ENTRY_TYPES.iter().any(|&x| self.bhd_types.contains(&x.into()))
+        {
+            self.report(None, "Missing mandatory BHD entry Apcb or ApcbBackup");
+        }
+        if !reset_image_given && !self.bios_reset_entry {
+            self.report(
+                None,
+                "Missing Bios entry with reset_image (and no reset image was specified using -r)",
+            );
+        }
+    }
+}
+
+/// Checks the config in EFS_CONFIGURATION_FILENAME and reports all the
+/// problems found. Fails if there are any.
+pub(crate) fn lint(
+    efs_configuration_filename: &Path,
+    blobdirs: &[PathBuf],
+    reset_image_given: bool,
+) -> std::io::Result<()> {
+    let data = std::fs::read_to_string(efs_configuration_filename)?;
+    let config: SerdeConfig<'_> =
+        crate::parse_config(&data, efs_configuration_filename)?;
+    let abl_version = crate::apcb_tokens::config_abl_version(&config, blobdirs);
+    if abl_version.is_none() {
+        eprintln!(
+            "WARNING: Could not find out ABL version. Hint: Use --blobdir so the Abl0 blob can be found"
+        );
+    }
+    let mut linter = Linter {
+        efs_configuration_filename,
+        processor_generation: config.processor_generation,
+        abl_version,
+        blobdirs,
+        findings: Vec::new(),
+        fixed_payloads: Vec::new(),
+        psp_types: Vec::new(),
+        bhd_types: Vec::new(),
+        bios_reset_entry: false,
+    };
+    match &config.psp {
+        SerdePspDirectoryVariant::PspDirectory(d) => {
+            linter.lint_psp_directory(d, "PSP directory")
+        }
+        _ => linter.report(None, "PSP combo directories are not supported"),
+    }
+    match &config.bhd {
+        SerdeBhdDirectoryVariant::BhdDirectory(d) => {
+            linter.lint_bhd_directory(d, "BHD directory")
+        }
+        _ => linter.report(None, "BHD combo directories are not supported"),
+    }
+    linter.lint_overlaps();
+    linter.lint_mandatory_entries(reset_image_given);
+
+    for finding in &linter.findings {
+        eprintln!("{finding}");
+    }
+    if linter.findings.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{} problem(s) found in {efs_configuration_filename:?}",
+            linter.findings.len()
+        )))
+    }
+}
@@ -6,18 +6,23 @@ use amd_efs::{
     ProcessorGeneration, PspDirectory, PspDirectoryEntry,
     PspDirectoryEntryType, PspDirectoryHeader, ValueOrLocation,
 };
+use amd_host_image_builder_config::SerdeApob;
+use amd_host_image_builder_config::SerdePolicyProfile;
 use amd_host_image_builder_config::SerdePspEntrySourceValue;
 use amd_host_image_builder_config::{
     Error, Result, SerdeBhdDirectory, SerdeBhdDirectoryEntry,
     SerdeBhdDirectoryEntryAttrs, SerdeBhdDirectoryEntryBlob,
-    SerdeBhdDirectoryVariant, SerdeBhdEntry, SerdeBhdSource, SerdePspDirectory,
-    SerdePspDirectoryEntry, SerdePspDirectoryEntryAttrs,
-    SerdePspDirectoryEntryBlob, SerdePspDirectoryVariant, SerdePspEntry,
-    SerdePspEntrySource, TryFromSerdeDirectoryEntryWithContext,
+    SerdeBhdDirectoryEntryType, SerdeBhdDirectoryVariant, SerdeBhdEntry,
+    SerdeBhdSource, SerdeImageSlot, SerdePspDirectory, SerdePspDirectoryEntry,
+    SerdePspDirectoryEntryAttrs, SerdePspDirectoryEntryBlob,
+    SerdePspDirectoryEntryType, SerdePspDirectoryVariant, SerdePspEntry,
+    SerdePspEntrySource, SourceSpan, TryFromSerdeDirectoryEntryWithContext,
+    render_json5_location,
 };
 use bytesize::ByteSize;
 use core::convert::TryFrom;
 use core::convert::TryInto;
+use sha2::Digest;
 use static_assertions::const_assert;
 use std::cmp::min;
 use std::collections::HashMap;
@@ -32,12 +37,46 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 use structopt::StructOpt;
+use zerocopy::IntoBytes;
 
 mod static_config;
 use amd_efs::allocators::{ArenaFlashAllocator, FlashAllocate};
 
 mod dump_serializer;
 
+mod dump_annotations;
+
+mod apcb_tokens;
+
+mod round_trip;
+
+mod fmt;
+
+mod lint;
+
+mod soft_fuses;
+
+mod signing;
+use signing::OemSigningKey;
+
+mod verify_signatures;
+
+mod policy;
+use policy::Policy;
+
+mod psp_blob;
+
+mod spl;
+
+mod check_boot;
+
+mod ram_layout;
+
+mod reset_image_elf;
+
+mod image_slot;
+use image_slot::ImageSlotHeader;
+
 use amd_efs::flash::{
     ErasableLocation, ErasableRange, FlashAlign, FlashRead, FlashWrite,
     Location,
@@ -78,20 +117,41 @@ fn test_valid_serde_psp_entry_source_value_deserialization() {
         serde_json::from_str::<SerdePspEntrySourceValue>(json).unwrap();
     if let SerdePspEntrySourceValue::PspSoftFuseChain(x) = result {
         assert_eq!(
-            x.spi_decoding(),
+            x.named_bits.spi_decoding(),
             PspSoftFuseChain32MiBSpiDecoding::UpperHalf
         );
         assert_eq!(
-            x.postcode_decoding(),
+            x.named_bits.postcode_decoding(),
             PspSoftFuseChainPostCodeDecoding::Lpc
         );
-        assert!(x.early_secure_debug_unlock());
-        assert!(!x.force_recovery_booting());
+        assert!(x.named_bits.early_secure_debug_unlock());
+        assert!(!x.named_bits.force_recovery_booting());
+        assert_eq!(x.unknown_bits, 0);
     } else {
         panic!("got the wrong SerdePspEntrySourceValue variant")
     }
 }
 
+#[test]
+fn test_psp_soft_fuse_chain_unknown_bits_round_trip() {
+    use amd_host_image_builder_config::SerdePspSoftFuseChain;
+    let unknown_bits = SerdePspSoftFuseChain::from(u64::MAX).unknown_bits;
+    assert_ne!(unknown_bits, 0);
+    let value = SerdePspEntrySourceValue::from_u64(
+        unknown_bits,
+        Ok(PspDirectoryEntryType::PspSoftFuseChain),
+    );
+    let json = serde_json::to_string(&value).unwrap();
+    assert!(json.contains("unknown_bits"));
+    let result =
+        serde_json::from_str::<SerdePspEntrySourceValue>(&json).unwrap();
+    assert!(matches!(result, SerdePspEntrySourceValue::PspSoftFuseChain(_)));
+    assert_eq!(
+        result.to_u64(Ok(PspDirectoryEntryType::PspSoftFuseChain)).unwrap(),
+        unknown_bits
+    );
+}
+
 #[test]
 fn test_invalid_string_deserialization() {
     let json = r#""x""#;
@@ -104,6 +164,26 @@ fn test_invalid_wrong_key_name() {
     assert!(serde_json::from_str::<SerdePspEntrySourceValue>(json).is_err());
 }
 
+#[test]
+fn test_unknown_entry_type_serde() {
+    let json = r#"{"type": 153, "instance": 2}"#;
+    let result =
+        serde_json::from_str::<SerdePspDirectoryEntryAttrs>(json).unwrap();
+    assert_eq!(result.type_, SerdePspDirectoryEntryType::Unknown(0x99));
+    assert_eq!(
+        serde_json::to_value(&result).unwrap()["type"],
+        serde_json::json!(153)
+    );
+
+    let json = r#"{"type": "Apob"}"#;
+    let result =
+        serde_json::from_str::<SerdeBhdDirectoryEntryAttrs>(json).unwrap();
+    assert_eq!(result.type_, BhdDirectoryEntryType::Apob);
+
+    let json = r#"{"type": 256}"#;
+    assert!(serde_json::from_str::<SerdeBhdDirectoryEntryAttrs>(json).is_err());
+}
+
 mod hole;
 use hole::Hole;
 
@@ -158,9 +238,15 @@ fn size_file(
 /// In case of error (file can't be read, version field not found, ...),
 /// returns None.
 fn abl_file_version(source_filename: &Path) -> Option<u32> {
-    // Note: This does work on Rome starting with Rome 1.0.0.a.
     let (file, _size) = size_file(source_filename, None).ok()?;
-    let mut source = BufReader::new(file);
+    abl_version(&mut BufReader::new(file))
+}
+
+/// Reads an ABL blob from SOURCE, finds the version field in there (if any)
+/// and returns its value.
+/// In case of error, returns None.
+fn abl_version<R: Read + Seek>(source: &mut R) -> Option<u32> {
+    // Note: This does work on Rome starting with Rome 1.0.0.a.
     let mut header: [u8; 0x110] = [0; 0x110];
     source.read_exact(&mut header).ok()?;
     let ver_raw = <[u8; 4]>::try_from(&header[0x60..0x64]).ok()?;
@@ -185,7 +271,13 @@ fn abl_file_version(source_filename: &Path) -> Option<u32> {
 /// returns None.
 fn smu_file_version(source_filename: &Path) -> Option<(u8, u8, u8, u8)> {
     let (file, _size) = size_file(source_filename, None).ok()?;
-    let mut source = BufReader::new(file);
+    smu_version(&mut BufReader::new(file))
+}
+
+/// Reads an SMU firmware blob from SOURCE, finds the version field in there
+/// (if any) and returns its value.
+/// In case of error, returns None.
+fn smu_version<R: Read>(source: &mut R) -> Option<(u8, u8, u8, u8)> {
     let mut header: [u8; 0x100] = [0; 0x100];
     source.read_exact(&mut header).ok()?;
     let ver_raw = <[u8; 4]>::try_from(&header[0x60..0x64]).ok()?;
@@ -354,6 +446,83 @@ fn bhd_directory_add_reset_image(
     Ok((entry, result))
 }
 
+/// Returns the contents of the loadable segments of the ELF file
+/// ELF_FILENAME, with their physical addresses. Segments that directly
+/// follow each other are merged. The part of a segment that is not in the
+/// file (.bss) is left out--it's up to the program to clear it.
+fn elf_segments(elf_filename: &Path) -> Result<Vec<(u64, Vec<u8>)>> {
+    let buffer = fs::read(elf_filename).map_err(Error::Io)?;
+    let goblin::Object::Elf(binary) = goblin::Object::parse(&buffer)
+        .map_err(|_| Error::IncompatibleExecutable)?
+    else {
+        return Err(Error::IncompatibleExecutable);
+    };
+    let mut result = Vec::<(u64, Vec<u8>)>::new();
+    for header in &binary.program_headers {
+        if header.p_type != goblin::elf::program_header::PT_LOAD
+            || header.p_filesz == 0
+        {
+            continue;
+        }
+        let chunk = buffer
+            .get(
+                header.p_offset as usize
+                    ..(header.p_offset + header.p_filesz) as usize,
+            )
+            .ok_or(Error::IncompatibleExecutable)?;
+        match result.last_mut() {
+            Some((address, body))
+                if *address + body.len() as u64 == header.p_paddr =>
+            {
+                body.extend_from_slice(chunk);
+            }
+            _ => result.push((header.p_paddr, chunk.to_vec())),
+        }
+    }
+    Ok(result)
+}
+
+/// The reset image given on the command line, with the entries that have to
+/// be next to it in each BHD directory it is added to.
+struct ResetImage {
+    entry: BhdDirectoryEntry,
+    body: Vec<u8>,
+    /// OemPublicKey and signature entries (if the reset image is signed)
+    signature_entries: Vec<(BhdDirectoryEntry, Vec<u8>)>,
+}
+
+impl ResetImage {
+    /// Returns the reset image entry and the entries that go with it.
+    fn raw_entries(&self) -> Vec<BhdRawDirectoryEntry> {
+        std::iter::once((self.entry, self.body.clone()))
+            .chain(self.signature_entries.iter().cloned())
+            .map(|(entry, body)| (entry, None, Some(body)))
+            .collect()
+    }
+
+    /// Returns whether adding the signature entries to a BHD directory
+    /// with RAW_ENTRIES would make it have two OemPublicKey entries.
+    fn clashes_with(&self, raw_entries: &[BhdRawDirectoryEntry]) -> bool {
+        !self.signature_entries.is_empty()
+            && raw_entries.iter().any(|(entry, _, _)| {
+                matches!(
+                    entry.typ_or_err(),
+                    Ok(BhdDirectoryEntryType::OemPublicKey)
+                )
+            })
+    }
+}
+
+/// Returns the error about an OemPublicKey entry in the config that is in
+/// the way of signing the reset image.
+fn oem_public_key_clash_error(
+    efs_configuration_filename: &Path,
+) -> std::io::Error {
+    std::io::Error::other(format!(
+        "{efs_configuration_filename:?} already has an OemPublicKey entry; remove it to sign the reset image with --oem-signing-key"
+    ))
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "amd-host-image-builder",
@@ -375,6 +544,26 @@ enum Opts {
         #[structopt(short = "r", long = "reset-image", parse(from_os_str))]
         reset_image_filename: Option<PathBuf>,
 
+        /// RSA private key (PEM) to sign the reset image with, for
+        /// Platform Secure Boot
+        #[structopt(long = "oem-signing-key", parse(from_os_str))]
+        oem_signing_key_filename: Option<PathBuf>,
+
+        /// Public key token of the --oem-signing-key, as certified by AMD,
+        /// for the OemPublicKey entry
+        #[structopt(long = "oem-public-key-token", parse(from_os_str))]
+        oem_public_key_token_filename: Option<PathBuf>,
+
+        /// Policy profile (production or development) the image has to
+        /// follow, instead of the policy of the config
+        #[structopt(long = "policy")]
+        policy_profile: Option<SerdePolicyProfile>,
+
+        /// Previous flash image (or SPL manifest) to warn about security
+        /// patch level rollback against
+        #[structopt(long = "previous-spl", parse(from_os_str))]
+        previous_spl_filename: Option<PathBuf>,
+
         #[structopt(short = "c", long = "config", parse(from_os_str))]
         efs_configuration_filename: PathBuf,
 
@@ -394,6 +583,119 @@ enum Opts {
             parse(from_os_str)
         )]
         blob_dump_dirname: Option<PathBuf>,
+
+        /// Name blobs by the SHA-256 of their contents and store identical
+        /// blobs only once
+        #[structopt(long = "content-addressed")]
+        content_addressed: bool,
+
+        /// Rebuild an ELF file from the reset image and write it to that
+        /// file
+        #[structopt(long = "reset-image-elf", parse(from_os_str))]
+        reset_image_elf_filename: Option<PathBuf>,
+
+        /// Check that the reset image was made from that ELF file
+        #[structopt(short = "r", long = "reset-image", parse(from_os_str))]
+        reset_image_filename: Option<PathBuf>,
+    },
+    Apcb {
+        #[structopt(subcommand)]
+        command: apcb_tokens::ApcbCommand,
+    },
+    /// Generates an image, dumps it, generates an image from the dump and
+    /// checks that both images are identical
+    RoundTrip {
+        #[structopt(
+            default_value = "32 MiB",
+            short = "s",
+            long = "output-size",
+            parse(try_from_str = ByteSize::from_str)
+        )]
+        output_size: ByteSize,
+
+        #[structopt(short = "r", long = "reset-image", parse(from_os_str))]
+        reset_image_filename: Option<PathBuf>,
+
+        #[structopt(short = "c", long = "config", parse(from_os_str))]
+        efs_configuration_filename: PathBuf,
+
+        #[structopt(short = "B", long = "blobdir", parse(from_os_str))]
+        blobdirs: Vec<PathBuf>,
+
+        /// Directory to keep the images and dumps in
+        #[structopt(short = "w", long = "work-directory", parse(from_os_str))]
+        work_dirname: PathBuf,
+
+        /// Dump with content-addressed blobs
+        #[structopt(long = "content-addressed")]
+        content_addressed: bool,
+
+        #[structopt(short = "v", long = "verbose")]
+        verbose: bool,
+    },
+    /// Rewrites configs in canonical form
+    Fmt {
+        /// Don't change the files; instead, fail if any of them is not in
+        /// canonical form
+        #[structopt(long = "check")]
+        check: bool,
+
+        #[structopt(parse(from_os_str))]
+        efs_configuration_filenames: Vec<PathBuf>,
+    },
+    /// Checks a config for mistakes that the schema does not catch
+    Lint {
+        #[structopt(short = "c", long = "config", parse(from_os_str))]
+        efs_configuration_filename: PathBuf,
+
+        #[structopt(short = "B", long = "blobdir", parse(from_os_str))]
+        blobdirs: Vec<PathBuf>,
+
+        /// Reset image that will be given to generate (only checked for
+        /// presence)
+        #[structopt(short = "r", long = "reset-image", parse(from_os_str))]
+        reset_image_filename: Option<PathBuf>,
+    },
+    /// Explains the PSP soft fuse chains of an existing flash image
+    ExplainSoftFuses {
+        #[structopt(short = "i", long = "existing-file", parse(from_os_str))]
+        input_filename: PathBuf,
+    },
+    /// Verifies the signatures of the payloads of an existing flash image
+    /// (or of the blobs of a config)
+    VerifySignatures {
+        #[structopt(short = "i", long = "existing-file", parse(from_os_str))]
+        input_filename: Option<PathBuf>,
+
+        #[structopt(short = "c", long = "config", parse(from_os_str))]
+        efs_configuration_filename: Option<PathBuf>,
+
+        #[structopt(short = "B", long = "blobdir", parse(from_os_str))]
+        blobdirs: Vec<PathBuf>,
+    },
+    /// Checks an existing flash image against a policy profile
+    Verify {
+        #[structopt(short = "i", long = "existing-file", parse(from_os_str))]
+        input_filename: PathBuf,
+
+        /// Policy profile (production or development)
+        #[structopt(long = "policy")]
+        policy_profile: SerdePolicyProfile,
+
+        /// ELF file of the reset image that is in the flash image (to check
+        /// its symbols)
+        #[structopt(short = "r", long = "reset-image", parse(from_os_str))]
+        reset_image_filename: Option<PathBuf>,
+
+        /// ELF symbol the reset image has to have in addition to the ones
+        /// of the policy profile
+        #[structopt(long = "reset-image-symbol")]
+        required_reset_image_symbols: Vec<String>,
+    },
+    /// Checks whether the PSP could boot an existing flash image
+    CheckBoot {
+        #[structopt(short = "i", long = "existing-file", parse(from_os_str))]
+        input_filename: PathBuf,
     },
 }
 
@@ -628,93 +930,233 @@ fn transfer_from_flash_to_io<T: FlashRead + FlashWrite>(
     }
 }
 
-fn create_dumpfile(
+/// Directory that `dump` writes blobs to: DIRNAME, relative to the dump
+/// directory ROOT (where config.efs.json5 goes).
+/// The config refers to blobs relative to ROOT, so the dump directory can be
+/// moved around and used as a blob directory for `generate`.
+/// If CONTENT_ADDRESSED is set, the blobs themselves are stored (once) in
+/// ROOT/blobs, named by the SHA-256 of their contents, and DIRNAME only
+/// contains symlinks to them.
+#[derive(Clone)]
+struct BlobDumpDirectory {
+    root: PathBuf,
+    dirname: PathBuf,
+    content_addressed: bool,
+}
+
+impl BlobDumpDirectory {
+    fn new(root: PathBuf, content_addressed: bool) -> Self {
+        Self { root, dirname: PathBuf::new(), content_addressed }
+    }
+
+    /// Returns the subdirectory NAME of this directory.
+    fn join(&self, name: impl AsRef<Path>) -> Self {
+        Self {
+            root: self.root.clone(),
+            dirname: self.dirname.join(name),
+            content_addressed: self.content_addressed,
+        }
+    }
+
+    /// Creates the subdirectory SECTION of this directory on disk.
+    fn create_section(&self, section: &str) {
+        fs::create_dir_all(self.root.join(&self.dirname).join(section))
+            .unwrap();
+    }
+}
+
+fn dumpfile_basename(
+    typ_string: &str,
+    instance: u8,
+    sub_program: u8,
+    rom_id: u8,
+) -> String {
+    // Most entries are on the first SPI chip, so only the others say so.
+    if rom_id == 0 {
+        format!("{}-i{:02x}-s{:02x}", typ_string, instance, sub_program)
+    } else {
+        format!(
+            "{}-i{:02x}-s{:02x}-r{:02x}",
+            typ_string, instance, sub_program, rom_id
+        )
+    }
+}
+
+/// Returns the path (relative to the dump directory) of the dump file
+/// BASENAME in SECTION of BLOB_DUMP_DIRNAME and remembers it in
+/// EXISTING_FILENAMES.  If another entry (with otherwise the same
+/// attributes) already got that path, adds a suffix to make it unique.
+fn unique_dumpfile_path(
+    existing_filenames: &mut HashSet<PathBuf>,
+    blob_dump_dirname: &BlobDumpDirectory,
+    section: &str,
+    basename: &str,
+) -> PathBuf {
+    let section_dirname = blob_dump_dirname.dirname.join(section);
+    let mut path = section_dirname.join(format!("{basename}.bin"));
+    let mut suffix = 1;
+    while existing_filenames.contains(&path) {
+        path = section_dirname.join(format!("{basename}-{suffix}.bin"));
+        suffix += 1;
+    }
+    existing_filenames.insert(path.clone());
+    path
+}
+
+/// Returns the target of a symlink in DIRNAME/SECTION (both relative to the
+/// dump directory) to PATH (also relative to the dump directory).
+fn dump_alias_target(dirname: &Path, path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    // One for SECTION.
+    for _ in 0..dirname.components().count() + 1 {
+        result.push("..");
+    }
+    result.push(path);
+    result
+}
+
+/// Writes the payload of an entry (SIZE bytes at BEGINNING in STORAGE) into
+/// the dump directory and returns its path relative to the dump directory.
+/// Content-addressed blobs get a symlink named like the dump file would
+/// have been named otherwise.
+#[allow(clippy::too_many_arguments)]
+fn dump_blob<T: FlashRead + FlashWrite>(
     existing_filenames: &mut HashSet<PathBuf>,
-    blob_dump_dirname: &PathBuf,
+    blob_dump_dirname: &BlobDumpDirectory,
     section: &str,
     typ_string: String,
     instance: u8,
     sub_program: u8,
-) -> (File, PathBuf) {
-    let mut path = PathBuf::new();
-    path.push(blob_dump_dirname);
-    path.push(section);
-    let basename = Path::new(&typ_string);
-    path.push(format!(
-        "{}-i{:02x}-s{:02x}.bin",
-        basename.display(),
-        instance,
-        sub_program,
-    ));
-    if existing_filenames.contains(&path) {
-        panic!(
-            "Refusing to create two files with the same name: {}",
-            path.display()
+    rom_id: u8,
+    storage: &T,
+    beginning: Location,
+    size: usize,
+) -> PathBuf {
+    let basename =
+        dumpfile_basename(&typ_string, instance, sub_program, rom_id);
+    if !blob_dump_dirname.content_addressed {
+        let path = unique_dumpfile_path(
+            existing_filenames,
+            blob_dump_dirname,
+            section,
+            &basename,
         );
+        let mut data_file = File::create(blob_dump_dirname.root.join(&path))
+            .expect("creation failed");
+        transfer_from_flash_to_io(storage, beginning, size, &mut data_file);
+        return path;
     }
-    existing_filenames.insert(path.clone());
-    (File::create(&path).expect("creation failed"), path)
+    let mut body = vec![0u8; size];
+    storage.read_exact(beginning, &mut body).unwrap();
+    let path = Path::new("blobs")
+        .join(format!("{:x}.bin", sha2::Sha256::digest(&body)));
+    let full_path = blob_dump_dirname.root.join(&path);
+    // Identical payloads are only stored once.
+    if !full_path.exists() {
+        fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        fs::write(&full_path, &body).expect("creation failed");
+    }
+
+    let alias = unique_dumpfile_path(
+        existing_filenames,
+        blob_dump_dirname,
+        section,
+        &basename,
+    );
+    let full_alias = blob_dump_dirname.root.join(&alias);
+    // In case something was dumped there before.
+    let _ = fs::remove_file(&full_alias);
+    let alias_target = dump_alias_target(&blob_dump_dirname.dirname, &path);
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(alias_target, &full_alias)
+        .expect("creation failed");
+    #[cfg(not(unix))]
+    fs::hard_link(&full_path, &full_alias).expect("creation failed");
+    path
+}
+
+#[test]
+fn test_dump_alias_target() {
+    assert_eq!(
+        dump_alias_target(Path::new(""), Path::new("blobs/ab.bin")),
+        Path::new("../blobs/ab.bin")
+    );
+    assert_eq!(
+        dump_alias_target(
+            Path::new("psp-level-2a-i00/bhd-level-2-i00"),
+            Path::new("blobs/ab.bin")
+        ),
+        Path::new("../../../blobs/ab.bin")
+    );
 }
 
 fn dump_psp_directory<T: FlashRead + FlashWrite>(
     storage: &T,
     psp_directory: &PspDirectory,
-    blob_dump_dirname: &Option<PathBuf>,
-) -> SerdePspDirectoryVariant {
+    amd_physical_mode_mmio_size: Option<u32>,
+    blob_dump_dirname: &Option<BlobDumpDirectory>,
+    context: ApcbContext,
+) -> SerdePspDirectoryVariant<'static> {
     if let Some(blob_dump_dirname) = &blob_dump_dirname {
-        let mut path = PathBuf::new();
-        path.push(blob_dump_dirname);
-        path.push("psp-default");
-        fs::create_dir_all(path).unwrap();
+        blob_dump_dirname.create_section("psp-default");
     }
     // TODO: Handle the other variant (PspComboDirectory)
     let mut blob_dump_filenames = HashSet::<PathBuf>::new();
     SerdePspDirectoryVariant::PspDirectory(SerdePspDirectory {
-        entries: psp_directory.entries().map_while(|e| -> Option<SerdePspEntry> {
-        if let Ok(typ) = e.typ_or_err() {
-            match typ {
-                PspDirectoryEntryType::SecondLevelDirectory => {
+        entries: psp_directory.entries().map(|e| -> SerdePspEntry {
+            if let Some(source) = dump_psp_level_2_entry_source(storage, psp_directory, &e, amd_physical_mode_mmio_size, blob_dump_dirname, context) {
+                return SerdePspEntry {
+                    source,
+                    target: serde_from_psp_entry(psp_directory, &e),
+                    span: None,
+                };
+            }
+            match e.typ_or_err() {
+                Ok(PspDirectoryEntryType::SecondLevelDirectory) => {
                     let payload_beginning =
                         psp_directory.payload_beginning(&e).unwrap();
                     let size = e.size().unwrap() as usize;
-                            let mut dir = [0u8; static_config::MAX_PSP_SECOND_LEVEL_DIRECTORY_SIZE];
-                            storage
-                                .read_exact(
-                                    payload_beginning,
-                                    &mut dir[0..size],
-                                )
-                                .unwrap();
+                    let mut dir = [0u8; static_config::MAX_PSP_SECOND_LEVEL_DIRECTORY_SIZE];
+                    storage
+                        .read_exact(
+                            payload_beginning,
+                            &mut dir[0..size],
+                        )
+                        .unwrap();
 
-                            let sub_psp_directory = PspDirectory::load(storage, payload_beginning, /*FIXME mode3 base*/0, /*FIXME mmio*/None).unwrap();
-                            let subdir = blob_dump_dirname.as_ref().map(|x| {
-                                    let mut t = x.clone();
-                                    t.push("psp-second-level");
-                                    t
-                            });
-                            let variant = dump_psp_directory(storage, &sub_psp_directory, &subdir);
-                            Some(SerdePspEntry {
-                                source: SerdePspEntrySource::SecondLevelDirectory(match variant {
-                                    SerdePspDirectoryVariant::PspDirectory(d) => d,
-                                    _ => {
-                                        panic!("???");
-                                    }
-                                }),
-                                target: serde_from_psp_entry(
-                                    psp_directory,
-                                    &e,
-                                ),
-                            })
-                        }
-            _ => {
+                    let sub_psp_directory = load_second_level_psp_directory(storage, payload_beginning, amd_physical_mode_mmio_size).unwrap();
+                    let subdir = blob_dump_dirname.as_ref().map(|x| x.join("psp-second-level"));
+                    let variant = dump_psp_directory(storage, &sub_psp_directory, amd_physical_mode_mmio_size, &subdir, context);
+                    SerdePspEntry {
+                        source: SerdePspEntrySource::SecondLevelDirectory(match variant {
+                            SerdePspDirectoryVariant::PspDirectory(d) => d,
+                            _ => {
+                                panic!("???");
+                            }
+                        }),
+                        target: serde_from_psp_entry(
+                            psp_directory,
+                            &e,
+                        ),
+                        span: None,
+                    }
+                }
+                typ_or_err => {
+            // Note: Entries with a type that amd-efs doesn't know are
+            // dumped (and regenerated) with the raw type number.
+            let typ = SerdePspDirectoryEntryType::from(&e);
+            if typ_or_err.is_err() {
+                eprintln!("WARNING: PSP entry with unknown type {typ} was dumped as-is {e:?}");
+            }
             let blob_export = match psp_directory.payload_beginning(&e) {
                Ok(beginning) => {
                    let typ_string = typ.to_string();
                    let size = e.size().unwrap() as usize;
                    if let Some(blob_dump_dirname) = blob_dump_dirname {
-                       let (data_file, path) = create_dumpfile(&mut blob_dump_filenames, blob_dump_dirname, "psp-default", typ_string, e.instance(), e.sub_program());
-                       Some((Some(data_file), path, beginning, size))
+                       let path = dump_blob(&mut blob_dump_filenames, blob_dump_dirname, "psp-default", typ_string, e.instance(), e.sub_program(), e.rom_id_or_err().unwrap() as u8, storage, beginning, size);
+                       Some((true, path, size))
                    } else {
-                       Some((None, Path::new("????").to_path_buf(), beginning, size))
+                       Some((false, Path::new("????").to_path_buf(), size))
                    }
                }
                Err(amd_efs::Error::DirectoryTypeMismatch) => {
@@ -725,14 +1167,14 @@ fn dump_psp_directory<T: FlashRead + FlashWrite>(
                }
             };
 
-            Some(SerdePspEntry {
+            SerdePspEntry {
                 source: match blob_export {
-                    Some((_, ref path, _beginning, _size)) => {
+                    Some((_, ref path, _size)) => {
                         SerdePspEntrySource::BlobFile(path.into())
                     }
                     None => {
                         let value = e.value().unwrap();
-                        SerdePspEntrySource::Value(SerdePspEntrySourceValue::from_u64(value, typ))
+                        SerdePspEntrySource::Value(SerdePspEntrySourceValue::from_u64(value, typ_or_err))
                     }
                 },
                 target: SerdePspDirectoryEntry {
@@ -746,19 +1188,13 @@ fn dump_psp_directory<T: FlashRead + FlashWrite>(
                         None => {
                            None
                         }
-                        Some((Some(mut data_file), ref _path, beginning, size)) => {
-                                transfer_from_flash_to_io(
-                                    storage,
-                                    beginning,
-                                    size,
-                                    &mut data_file,
-                                );
+                        Some((true, _, size)) => {
                             Some(SerdePspDirectoryEntryBlob {
                         flash_location: Some(psp_directory.payload_beginning(&e).unwrap()),
                         size: Some(size.try_into().unwrap()),
                     })
                     }
-                    Some((None, _, _, _)) => {
+                    Some((false, _, _)) => {
                             Some(SerdePspDirectoryEntryBlob {
                         flash_location: Some(psp_directory.payload_beginning(&e).unwrap()),
                         size: Some(e.size().unwrap()), // FIXME what if it doesn't apply?
@@ -766,22 +1202,98 @@ fn dump_psp_directory<T: FlashRead + FlashWrite>(
                     }
                 }
                 },
-            })}}
-        } else {
-            eprintln!("WARNING: PSP entry with unknown type was skipped {e:?}");
-            None
-        }
+                span: None,
+            }}}
         }).collect()
     })
 }
 
+/// If E is an entry that points to a level 2 directory (via an Image Slot
+/// Header or, for BHD, directly), dumps that directory and returns the
+/// source for E.  Otherwise (or if there's no valid directory there),
+/// returns None.
+fn dump_psp_level_2_entry_source<T: FlashRead + FlashWrite>(
+    storage: &T,
+    psp_directory: &PspDirectory,
+    e: &PspDirectoryEntry,
+    amd_physical_mode_mmio_size: Option<u32>,
+    blob_dump_dirname: &Option<BlobDumpDirectory>,
+    context: ApcbContext,
+) -> Option<SerdePspEntrySource<'static>> {
+    let typ = image_slot::psp_entry_type(e);
+    let subdir =
+        |name: String| blob_dump_dirname.as_ref().map(|x| x.join(name));
+    let payload_beginning = psp_directory.payload_beginning(e).ok()?;
+    if image_slot::is_image_slot_entry(e) {
+        let mut buf = [0u8; image_slot::IMAGE_SLOT_HEADER_SIZE];
+        storage.read_exact(payload_beginning, &mut buf).ok()?;
+        let Some(header) = ImageSlotHeader::from_bytes(&buf) else {
+            eprintln!(
+                "WARNING: PSP entry {e:?} has an invalid Image Slot Header; it was dumped as-is"
+            );
+            return None;
+        };
+        let directory = load_second_level_psp_directory(
+            storage,
+            header.pl2_location,
+            amd_physical_mode_mmio_size,
+        )
+        .ok()?;
+        let slot_name = if typ == image_slot::PSP_LEVEL_2A_DIRECTORY_ENTRY_TYPE
+        {
+            "a"
+        } else {
+            "b"
+        };
+        let variant = dump_psp_directory(
+            storage,
+            &directory,
+            amd_physical_mode_mmio_size,
+            &subdir(format!("psp-level-2{slot_name}-i{:02x}", e.instance())),
+            context,
+        );
+        let SerdePspDirectoryVariant::PspDirectory(directory) = variant else {
+            panic!("???");
+        };
+        Some(SerdePspEntrySource::ImageSlotHeader(SerdeImageSlot {
+            boot_priority: header.boot_priority,
+            update_retry_count: header.update_retry_count,
+            glitch_retry_count: header.glitch_retry_count,
+            psp_id: header.psp_id,
+            slot_max_size: header.slot_max_size,
+            directory_flash_location: Some(header.pl2_location),
+            directory,
+        }))
+    } else if typ == image_slot::BHD_LEVEL_2_DIRECTORY_ENTRY_TYPE {
+        let directory = load_second_level_bhd_directory(
+            storage,
+            payload_beginning,
+            amd_physical_mode_mmio_size,
+        )
+        .ok()?;
+        let variant = dump_bhd_directory(
+            storage,
+            &directory,
+            amd_physical_mode_mmio_size,
+            &subdir(format!("bhd-level-2-i{:02x}", e.instance())),
+            context,
+        );
+        let SerdeBhdDirectoryVariant::BhdDirectory(directory) = variant else {
+            panic!("???");
+        };
+        Some(SerdePspEntrySource::BhdDirectory(directory))
+    } else {
+        None
+    }
+}
+
 fn serde_from_bhd_entry(
     directory: &BhdDirectory,
     entry: &BhdDirectoryEntry,
 ) -> SerdeBhdDirectoryEntry {
     SerdeBhdDirectoryEntry {
         attrs: SerdeBhdDirectoryEntryAttrs {
-            type_: entry.typ_or_err().unwrap(),
+            type_: SerdeBhdDirectoryEntryType::from(entry),
             region_type: entry.region_type_or_err().unwrap(),
             reset_image: entry.reset_image_or_err().unwrap(),
             copy_image: entry.copy_image_or_err().unwrap(),
@@ -805,7 +1317,7 @@ fn serde_from_psp_entry(
 ) -> SerdePspDirectoryEntry {
     SerdePspDirectoryEntry {
         attrs: SerdePspDirectoryEntryAttrs {
-            type_: entry.typ_or_err().unwrap(),
+            type_: SerdePspDirectoryEntryType::from(entry),
             instance: entry.instance_or_err().unwrap(),
             sub_program: entry.sub_program_or_err().unwrap(),
             rom_id: entry.rom_id_or_err().unwrap(),
@@ -817,37 +1329,33 @@ fn serde_from_psp_entry(
     }
 }
 
-fn dump_bhd_directory<'a, T: FlashRead + FlashWrite>(
+fn dump_bhd_directory<T: FlashRead + FlashWrite>(
     storage: &T,
     bhd_directory: &BhdDirectory,
-    apcb_buffer_option: &mut Option<&'a mut [u8]>,
-    blob_dump_dirname: &Option<PathBuf>,
+    amd_physical_mode_mmio_size: Option<u32>,
+    blob_dump_dirname: &Option<BlobDumpDirectory>,
     context: ApcbContext,
-) -> SerdeBhdDirectoryVariant<'a> {
+) -> SerdeBhdDirectoryVariant<'static> {
     if let Some(blob_dump_dirname) = &blob_dump_dirname {
-        let mut path = PathBuf::new();
-        path.push(blob_dump_dirname);
-        path.push("bhd-default");
-        fs::create_dir_all(path).unwrap();
+        blob_dump_dirname.create_section("bhd-default");
     }
     let mut blob_dump_filenames = HashSet::<PathBuf>::new();
     SerdeBhdDirectoryVariant::BhdDirectory(SerdeBhdDirectory {
         entries: bhd_directory
             .entries()
-            .map_while(|entry| {
-                if let Ok(typ) = entry.typ_or_err() {
+            .map(|entry| {
                     let payload_beginning =
                         bhd_directory.payload_beginning(&entry).unwrap();
                     let size = entry.size().unwrap() as usize;
-                    match typ {
+                    match entry.typ_or_err() {
 
-                        BhdDirectoryEntryType::ApcbBackup
-                        | BhdDirectoryEntryType::Apcb
-                            if apcb_buffer_option.is_some() =>
+                        Ok(BhdDirectoryEntryType::ApcbBackup
+                        | BhdDirectoryEntryType::Apcb) =>
                         {
-                            let apcb_buffer = apcb_buffer_option
-                                .take()
-                                .expect("only one APCB");
+                            // Each APCB (there can be several, with
+                            // different instance or sub_program) gets its
+                            // own buffer.
+                            let mut apcb_buffer = vec![0xFFu8; Apcb::MAX_SIZE];
                             storage
                                 .read_exact(
                                     payload_beginning,
@@ -856,23 +1364,24 @@ fn dump_bhd_directory<'a, T: FlashRead + FlashWrite>(
                                 .unwrap();
 
                             let apcb = Apcb::load(
-                                std::borrow::Cow::Borrowed(
+                                std::borrow::Cow::Owned(
                                     apcb_buffer,
                                 ),
                                 &ApcbIoOptions::default().with_context(context).build(),
                             )
                             .unwrap();
                             apcb.validate(None).unwrap(); // TODO: abl0 version ?
-                            Some(SerdeBhdEntry {
+                            SerdeBhdEntry {
                                 source: SerdeBhdSource::ApcbJson(apcb),
                                 target: serde_from_bhd_entry(
                                     bhd_directory,
                                     &entry,
                                 ),
-                            })
+                                span: None,
+                            }
                         }
 
-                        BhdDirectoryEntryType::SecondLevelDirectory => {
+                        Ok(BhdDirectoryEntryType::SecondLevelDirectory) => {
                             let mut dir = [0u8; static_config::MAX_BHD_SECOND_LEVEL_DIRECTORY_SIZE];
                             storage
                                 .read_exact(
@@ -881,14 +1390,10 @@ fn dump_bhd_directory<'a, T: FlashRead + FlashWrite>(
                                 )
                                 .unwrap();
 
-                            let sub_bhd_directory = BhdDirectory::load(storage, payload_beginning, /*FIXME mode3 base*/0, /*FIXME mmio*/None).unwrap();
-                            let subdir = blob_dump_dirname.as_ref().map(|x| {
-                                let mut t = x.clone();
-                                t.push("bhd-second-level");
-                                t
-                            });
-                            let variant = dump_bhd_directory(storage, &sub_bhd_directory, apcb_buffer_option, &subdir, context); //SerdeBhdDirectoryVariant
-                            Some(SerdeBhdEntry {
+                            let sub_bhd_directory = load_second_level_bhd_directory(storage, payload_beginning, amd_physical_mode_mmio_size).unwrap();
+                            let subdir = blob_dump_dirname.as_ref().map(|x| x.join("bhd-second-level"));
+                            let variant = dump_bhd_directory(storage, &sub_bhd_directory, amd_physical_mode_mmio_size, &subdir, context); //SerdeBhdDirectoryVariant
+                            SerdeBhdEntry {
                                 source: SerdeBhdSource::SecondLevelDirectory(match variant {
                                     SerdeBhdDirectoryVariant::BhdDirectory(d) => d,
                                     _ => {
@@ -899,33 +1404,44 @@ fn dump_bhd_directory<'a, T: FlashRead + FlashWrite>(
                                     bhd_directory,
                                     &entry,
                                 ),
-                            })
+                                span: None,
+                            }
                         }
-                        BhdDirectoryEntryType::Apob => Some(SerdeBhdEntry {
+                        Ok(BhdDirectoryEntryType::Apob) => SerdeBhdEntry {
                             source: SerdeBhdSource::Implied,
                             target: serde_from_bhd_entry(
                                     bhd_directory,
                                     &entry,
-                            )
-                        }),
-                        typ => Some(SerdeBhdEntry {
+                            ),
+                            span: None,
+                        },
+                        typ_or_err => {
+                        // Note: Entries with a type that amd-efs doesn't
+                        // know are dumped (and regenerated) with the raw
+                        // type number.
+                        let typ = SerdeBhdDirectoryEntryType::from(&entry);
+                        if typ_or_err.is_err() {
+                            eprintln!(
+                                "WARNING: BHD entry with unknown type {typ} was dumped as-is {:?}",
+                                entry
+                            );
+                        }
+                        SerdeBhdEntry {
                             source: if let Some(blob_dump_dirname) =
                                 &blob_dump_dirname
                             {
                                 let typ_string = typ.to_string();
-                                let (mut data_file, path) = create_dumpfile(
+                                let path = dump_blob(
                                     &mut blob_dump_filenames,
                                     blob_dump_dirname,
                                     "bhd-default",
                                     typ_string,
                                     entry.instance(),
                                     entry.sub_program(),
-                                );
-                                transfer_from_flash_to_io(
+                                    entry.rom_id_or_err().unwrap() as u8,
                                     storage,
                                     payload_beginning,
                                     size,
-                                    &mut data_file,
                                 );
                                 SerdeBhdSource::BlobFile(path)
                             } else {
@@ -935,15 +1451,9 @@ fn dump_bhd_directory<'a, T: FlashRead + FlashWrite>(
                                 bhd_directory,
                                 &entry,
                             ),
-                        }),
+                            span: None,
+                        }}
                     }
-                } else {
-                    eprintln!(
-                        "WARNING: BHD entry with unknown type was skipped {:?}",
-                        entry
-                    );
-                    None
-                }
             })
             .collect(),
     })
@@ -1025,96 +1535,502 @@ fn generate_is_context_valid(
     )
 }
 
-fn dump(
-    image_filename: &Path,
-    blob_dump_dirname: Option<PathBuf>,
-) -> std::io::Result<()> {
-    let filename = image_filename;
-    let storage = FlashImage::load(filename)?;
+/// Returns the size of the MMIO window that the physical addresses in the
+/// existing image STORAGE refer to (if any).
+/// The window ends at 4 GiB and is at most 16 MiB, so in bigger images only
+/// the first 16 MiB can be addressed physically.
+fn amd_physical_mode_mmio_size(
+    storage: &FlashImage,
+) -> std::io::Result<Option<u32>> {
     let filesize = storage.file_size()?;
-    let amd_physical_mode_mmio_size =
-        if filesize <= 0x100_0000 { Some(filesize as u32) } else { None };
-    let efs = Efs::load(&storage, None, amd_physical_mode_mmio_size).unwrap();
-    let generation = [
-        ProcessorGeneration::Turin,
-        ProcessorGeneration::Genoa,
-        ProcessorGeneration::Milan,
-    ]
-    .iter()
-    .find(|&generation| efs.compatible_with_processor_generation(*generation))
-    .expect("only Milan, Genoa and Turin are supported for dumping right now");
-    let mut apcb_buffer = [0xFFu8; Apcb::MAX_SIZE];
-    let mut apcb_buffer_option = Some(&mut apcb_buffer[..]);
-    let psp_main_directory_flash_location =
-        Some(efs.psp_directory().unwrap().beginning());
-    let bhd_main_directory_flash_location =
-        Some(efs.bhd_directory(None).unwrap().beginning());
+    Ok(Some(min(filesize, 0x100_0000) as u32))
+}
 
-    let psp = dump_psp_directory(
-        &storage,
-        &efs.psp_directory().unwrap(),
-        &blob_dump_dirname,
-    );
+/// Loads the second-level PSP directory at BEGINNING.
+/// Entries in it that use address mode 3 are relative to BEGINNING.
+/// Physical addresses refer to the same MMIO window as in the main directory.
+fn load_second_level_psp_directory<T: FlashRead>(
+    storage: &T,
+    beginning: Location,
+    amd_physical_mode_mmio_size: Option<u32>,
+) -> amd_efs::Result<PspDirectory> {
+    PspDirectory::load(
+        storage,
+        beginning,
+        beginning,
+        amd_physical_mode_mmio_size,
+    )
+}
 
-    let bhd = dump_bhd_directory(
-        &storage,
-        &efs.bhd_directory(None).unwrap(),
-        &mut apcb_buffer_option,
-        &blob_dump_dirname,
-        dump_default_context(*generation),
-    );
+/// Loads the second-level BHD directory at BEGINNING.
+/// See load_second_level_psp_directory.
+fn load_second_level_bhd_directory<T: FlashRead>(
+    storage: &T,
+    beginning: Location,
+    amd_physical_mode_mmio_size: Option<u32>,
+) -> amd_efs::Result<BhdDirectory> {
+    BhdDirectory::load(
+        storage,
+        beginning,
+        beginning,
+        amd_physical_mode_mmio_size,
+    )
+}
 
-    let config = SerdeConfig {
-        processor_generation: *generation,
-        spi_mode_bulldozer: efs.spi_mode_bulldozer().unwrap(),
-        spi_mode_zen_naples: efs.spi_mode_zen_naples().unwrap(),
-        spi_mode_zen_rome: efs.spi_mode_zen_rome().unwrap(),
-        espi0_configuration: efs.espi0_configuration().unwrap(),
-        espi1_configuration: efs.espi1_configuration().unwrap(),
-        psp_main_directory_flash_location,
-        bhd_main_directory_flash_location,
-        // TODO: psp_directory or psp_combo_directory
-        psp,
+/// What a walk over the directories of a flash image (see DirectoryWalker)
+/// does with each directory and each entry. NAME says where the directory
+/// is, like "PSP directory > second level PSP directory".
+trait DirectoryVisitor {
+    /// Called for each PSP directory, before its entries.
+    fn psp_directory(&mut self, _directory: &PspDirectory, _name: &str) {}
+
+    /// Called for each entry of a PSP directory that doesn't point to
+    /// another directory.
+    fn psp_entry(
+        &mut self,
+        _storage: &FlashImage,
+        _directory: &PspDirectory,
+        _entry: &PspDirectoryEntry,
+        _name: &str,
+    ) {
+    }
+
+    /// Called for each BHD directory, before its entries.
+    fn bhd_directory(&mut self, _directory: &BhdDirectory, _name: &str) {}
+
+    /// Called for each entry of a BHD directory that doesn't point to
+    /// another directory.
+    fn bhd_entry(
+        &mut self,
+        _storage: &FlashImage,
+        _directory: &BhdDirectory,
+        _entry: &BhdDirectoryEntry,
+        _name: &str,
+    ) {
+    }
+
+    /// Called for each BHD directory, after its entries.
+    fn bhd_directory_end(&mut self, _name: &str) {}
+
+    /// Called for each directory that an entry points to, but that cannot
+    /// be loaded.
+    fn directory_error(&mut self, message: String) {
+        eprintln!("WARNING: {message}");
+    }
+}
+
+/// Several visitors that share one walk over the directories of a flash
+/// image. Each directory that cannot be loaded is warned about once rather
+/// than by each visitor.
+struct Visitors<'a>(Vec<&'a mut dyn DirectoryVisitor>);
+
+impl DirectoryVisitor for Visitors<'_> {
+    fn psp_directory(&mut self, directory: &PspDirectory, name: &str) {
+        for visitor in &mut self.0 {
+            visitor.psp_directory(directory, name);
+        }
+    }
+
+    fn psp_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        name: &str,
+    ) {
+        for visitor in &mut self.0 {
+            visitor.psp_entry(storage, directory, entry, name);
+        }
+    }
+
+    fn bhd_directory(&mut self, directory: &BhdDirectory, name: &str) {
+        for visitor in &mut self.0 {
+            visitor.bhd_directory(directory, name);
+        }
+    }
+
+    fn bhd_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        name: &str,
+    ) {
+        for visitor in &mut self.0 {
+            visitor.bhd_entry(storage, directory, entry, name);
+        }
+    }
+
+    fn bhd_directory_end(&mut self, name: &str) {
+        for visitor in &mut self.0 {
+            visitor.bhd_directory_end(name);
+        }
+    }
+}
+
+/// Walks the directories of a flash image like the PSP does: the image
+/// slots, level 2 BHD directories (entry type 0x49) and second-level
+/// directories that PSP directories point to, and the second-level
+/// directories that BHD directories point to.
+struct DirectoryWalker<'a> {
+    storage: &'a FlashImage,
+    amd_physical_mode_mmio_size: Option<u32>,
+    /// Number of BHD directories walked so far
+    bhd_directory_count: usize,
+}
+
+impl<'a> DirectoryWalker<'a> {
+    fn new(
+        storage: &'a FlashImage,
+        amd_physical_mode_mmio_size: Option<u32>,
+    ) -> Self {
+        Self { storage, amd_physical_mode_mmio_size, bhd_directory_count: 0 }
+    }
+
+    fn walk_psp_directory(
+        &mut self,
+        directory: &PspDirectory,
+        name: &str,
+        visitor: &mut impl DirectoryVisitor,
+    ) {
+        visitor.psp_directory(directory, name);
+        for entry in directory.entries() {
+            let beginning = directory.payload_beginning(&entry);
+            let typ = SerdePspDirectoryEntryType::from(&entry);
+            let description = format!("{typ} (instance {})", entry.instance());
+            if image_slot::is_image_slot_entry(&entry) {
+                let mut buf = [0u8; image_slot::IMAGE_SLOT_HEADER_SIZE];
+                let header = beginning.ok().and_then(|beginning| {
+                    self.storage.read_exact(beginning, &mut buf).ok()?;
+                    ImageSlotHeader::from_bytes(&buf)
+                });
+                let Some(header) = header else {
+                    visitor.directory_error(format!(
+                        "{name}: {description}: Image Slot Header is invalid"
+                    ));
+                    continue;
+                };
+                match load_second_level_psp_directory(
+                    self.storage,
+                    header.pl2_location,
+                    self.amd_physical_mode_mmio_size,
+                ) {
+                    Ok(subdirectory) => self.walk_psp_directory(
+                        &subdirectory,
+                        &format!("{name} > {description}"),
+                        visitor,
+                    ),
+                    Err(e) => visitor.directory_error(format!(
+                        "{name}: {description}: level 2 PSP directory cannot be loaded: {e:?}"
+                    )),
+                }
+            } else if image_slot::psp_entry_type(&entry)
+                == image_slot::BHD_LEVEL_2_DIRECTORY_ENTRY_TYPE
+            {
+                match beginning.and_then(|beginning| {
+                    load_second_level_bhd_directory(
+                        self.storage,
+                        beginning,
+                        self.amd_physical_mode_mmio_size,
+                    )
+                }) {
+                    Ok(subdirectory) => self.walk_bhd_directory(
+                        &subdirectory,
+                        &format!("{name} > level 2 BHD directory"),
+                        visitor,
+                    ),
+                    Err(e) => visitor.directory_error(format!(
+                        "{name}: level 2 BHD directory cannot be loaded: {e:?}"
+                    )),
+                }
+            } else if typ == PspDirectoryEntryType::SecondLevelDirectory {
+                match beginning.and_then(|beginning| {
+                    load_second_level_psp_directory(
+                        self.storage,
+                        beginning,
+                        self.amd_physical_mode_mmio_size,
+                    )
+                }) {
+                    Ok(subdirectory) => self.walk_psp_directory(
+                        &subdirectory,
+                        &format!("{name} > second level PSP directory"),
+                        visitor,
+                    ),
+                    Err(e) => visitor.directory_error(format!(
+                        "{name}: second level PSP directory cannot be loaded: {e:?}"
+                    )),
+                }
+            } else {
+                visitor.psp_entry(self.storage, directory, &entry, name);
+            }
+        }
+    }
+
+    fn walk_bhd_directory(
+        &mut self,
+        directory: &BhdDirectory,
+        name: &str,
+        visitor: &mut impl DirectoryVisitor,
+    ) {
+        self.bhd_directory_count += 1;
+        visitor.bhd_directory(directory, name);
+        for entry in directory.entries() {
+            if matches!(
+                entry.typ_or_err(),
+                Ok(BhdDirectoryEntryType::SecondLevelDirectory)
+            ) {
+                match directory.payload_beginning(&entry).and_then(
+                    |beginning| {
+                        load_second_level_bhd_directory(
+                            self.storage,
+                            beginning,
+                            self.amd_physical_mode_mmio_size,
+                        )
+                    },
+                ) {
+                    Ok(subdirectory) => self.walk_bhd_directory(
+                        &subdirectory,
+                        &format!("{name} > second level BHD directory"),
+                        visitor,
+                    ),
+                    Err(e) => visitor.directory_error(format!(
+                        "{name}: second level BHD directory cannot be loaded: {e:?}"
+                    )),
+                }
+            } else {
+                visitor.bhd_entry(self.storage, directory, &entry, name);
+            }
+        }
+        visitor.bhd_directory_end(name);
+    }
+
+    /// Walks the PSP directory and the BHD directory of EFS. On Genoa and
+    /// later, the level 2 PSP directories can point to the BHD directories
+    /// instead, so the BHD directory is only needed if there are none.
+    fn walk_efs<T: FlashRead + FlashWrite>(
+        &mut self,
+        efs: &Efs<T>,
+        visitor: &mut impl DirectoryVisitor,
+    ) -> amd_efs::Result<()> {
+        self.walk_psp_directory(
+            &efs.psp_directory()?,
+            "PSP directory",
+            visitor,
+        );
+        match efs.bhd_directory(None) {
+            Ok(directory) => {
+                self.walk_bhd_directory(&directory, "BHD directory", visitor)
+            }
+            Err(e) if self.bhd_directory_count == 0 => return Err(e),
+            Err(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Walks the directories of the flash image INPUT_FILENAME with VISITOR
+/// (see DirectoryWalker) and returns the processor generation of the image.
+fn walk_image_directories(
+    input_filename: &Path,
+    visitor: &mut impl DirectoryVisitor,
+) -> std::io::Result<ProcessorGeneration> {
+    let efs_to_io_error = |e| {
+        std::io::Error::other(format!(
+            "EFS error: {e:?} in file {input_filename:?}"
+        ))
+    };
+    let storage = FlashImage::load(input_filename)?;
+    let amd_physical_mode_mmio_size = amd_physical_mode_mmio_size(&storage)?;
+    let efs = Efs::load(&storage, None, amd_physical_mode_mmio_size)
+        .map_err(efs_to_io_error)?;
+    DirectoryWalker::new(&storage, amd_physical_mode_mmio_size)
+        .walk_efs(&efs, visitor)
+        .map_err(efs_to_io_error)?;
+    Ok(efs_processor_generation(&efs))
+}
+
+/// Returns the payload (of SIZE Bytes) at BEGINNING in STORAGE, if it can
+/// be read.
+fn read_payload(
+    storage: &FlashImage,
+    beginning: amd_efs::Result<Location>,
+    size: Option<u32>,
+) -> Option<Vec<u8>> {
+    let mut body = vec![0u8; size? as usize];
+    storage.read_exact(beginning.ok()?, &mut body).ok()?;
+    Some(body)
+}
+
+/// Finds out which processor generation the existing EFS is for, if any.
+fn compatible_processor_generation<T: FlashRead + FlashWrite>(
+    efs: &Efs<T>,
+) -> Option<ProcessorGeneration> {
+    // Newest first, so that a Milan image isn't mistaken for a Rome image.
+    [
+        ProcessorGeneration::Turin,
+        ProcessorGeneration::Genoa,
+        ProcessorGeneration::Milan,
+        ProcessorGeneration::Rome,
+        ProcessorGeneration::Naples,
+    ]
+    .into_iter()
+    .find(|&generation| efs.compatible_with_processor_generation(generation))
+}
+
+/// Finds out which processor generation the existing EFS is for.
+fn efs_processor_generation<T: FlashRead + FlashWrite>(
+    efs: &Efs<T>,
+) -> ProcessorGeneration {
+    compatible_processor_generation(efs)
+        .expect("EFS is not compatible with any known processor generation")
+}
+
+fn dump(
+    image_filename: &Path,
+    blob_dump_dirname: Option<PathBuf>,
+    content_addressed: bool,
+) -> std::io::Result<()> {
+    let filename = image_filename;
+    let storage = FlashImage::load(filename)?;
+    let amd_physical_mode_mmio_size = amd_physical_mode_mmio_size(&storage)?;
+    let blob_dump_directory = blob_dump_dirname
+        .clone()
+        .map(|x| BlobDumpDirectory::new(x, content_addressed));
+    let efs = Efs::load(&storage, None, amd_physical_mode_mmio_size).unwrap();
+    let generation = efs_processor_generation(&efs);
+    let psp_main_directory_flash_location =
+        Some(efs.psp_directory().unwrap().beginning());
+    let bhd_main_directory_flash_location =
+        Some(efs.bhd_directory(None).unwrap().beginning());
+
+    let psp = dump_psp_directory(
+        &storage,
+        &efs.psp_directory().unwrap(),
+        amd_physical_mode_mmio_size,
+        &blob_dump_directory,
+        dump_default_context(generation),
+    );
+
+    let bhd = dump_bhd_directory(
+        &storage,
+        &efs.bhd_directory(None).unwrap(),
+        amd_physical_mode_mmio_size,
+        &blob_dump_directory,
+        dump_default_context(generation),
+    );
+
+    // Only dump the SPI mode fields that the processor generation actually
+    // uses--the others are often garbage and would make the config invalid.
+    let (spi_mode_bulldozer, spi_mode_zen_naples, spi_mode_zen_rome) =
+        match generation {
+            ProcessorGeneration::Naples => {
+                (None, efs.spi_mode_zen_naples().unwrap(), None)
+            }
+            ProcessorGeneration::Rome | ProcessorGeneration::Milan => {
+                (None, None, efs.spi_mode_zen_rome().unwrap())
+            }
+            ProcessorGeneration::Genoa | ProcessorGeneration::Turin => {
+                (efs.spi_mode_bulldozer().unwrap(), None, None)
+            }
+        };
+    let (espi0_configuration, espi1_configuration) = match generation {
+        ProcessorGeneration::Naples
+        | ProcessorGeneration::Rome
+        | ProcessorGeneration::Milan => (None, None),
+        ProcessorGeneration::Genoa | ProcessorGeneration::Turin => (
+            efs.espi0_configuration().unwrap(),
+            efs.espi1_configuration().unwrap(),
+        ),
+    };
+
+    let mut blobs = spl::ImageBlobs::default();
+    let mut footprints =
+        ram_layout::ImageFootprints::new(static_config::APOB_SIZE(generation));
+    DirectoryWalker::new(&storage, amd_physical_mode_mmio_size)
+        .walk_efs(&efs, &mut Visitors(vec![&mut blobs, &mut footprints]))
+        .map_err(|e| {
+            std::io::Error::other(format!(
+                "EFS error: {e:?} in file {filename:?}"
+            ))
+        })?;
+
+    let config = SerdeConfig {
+        processor_generation: generation,
+        spi_mode_bulldozer,
+        spi_mode_zen_naples,
+        spi_mode_zen_rome,
+        espi0_configuration,
+        espi1_configuration,
+        psp_main_directory_flash_location,
+        bhd_main_directory_flash_location,
+        // TODO: psp_directory or psp_combo_directory
+        psp,
         // TODO: bhd_directory or bhd_combo_directory
         bhd,
+        // The Apob entries are dumped as they are. The image does not say
+        // anything else about the APOB.
+        apob: None,
+        policy: None,
     };
+    let annotations = dump_annotations::dump_annotations(
+        &storage,
+        &serde_json::to_value(&config).unwrap(),
+    );
+    let config =
+        dump_serializer::to_string_pretty_annotated(&config, annotations)
+            .unwrap();
     if let Some(blob_dump_dirname) = &blob_dump_dirname {
         let mut path = PathBuf::new();
         path.push(blob_dump_dirname);
         path.push("config.efs.json5");
         use std::io::Write;
         let mut file = File::create(&path).expect("creation failed");
-        writeln!(
-            file,
-            "{}",
-            dump_serializer::to_string_pretty(&config).unwrap()
-        )?;
+        writeln!(file, "{}", config)?;
     } else {
-        println!("{}", dump_serializer::to_string_pretty(&config).unwrap());
+        println!("{}", config);
+    }
+    // The config can go to stdout, so these go to stderr.
+    for (description, spl) in blobs.spls() {
+        eprintln!("Info: {description}: SPL {spl:#x}");
+    }
+    for problem in blobs.problems(None, false)? {
+        eprintln!("WARNING: {problem}");
+    }
+    for problem in footprints.problems(false) {
+        eprintln!("WARNING: {problem}");
     }
     Ok(())
 }
 
-struct PspDirectoryContents {
+struct PspDirectoryContents<'a> {
     abl_version: Option<u32>,
     unique_smu_versions: HashMap<u8, Option<(u8, u8, u8, u8)>>, // sub_program -> smu_version
     address_mode: AddressMode,
     second_level_directory_template:
-        Option<(SerdePspDirectory, Option<SerdePspDirectoryEntryBlob>)>,
+        Option<(SerdePspDirectory<'a>, Option<SerdePspDirectoryEntryBlob>)>,
+    /// Image slots, their entries and where they are in the config
+    image_slot_templates:
+        Vec<(SerdeImageSlot<'a>, SerdePspDirectoryEntry, Option<SourceSpan>)>,
+    /// Level 2 BHD directory, its entry and where it is in the config
+    bhd_directory_template: Option<(
+        SerdeBhdDirectory<'a>,
+        SerdePspDirectoryEntry,
+        Option<SourceSpan>,
+    )>,
     raw_entries: Vec<(PspDirectoryEntry, Option<Location>, Option<Vec<u8>>)>,
 }
 
 type VersionedSmuEntry =
     HashMap<Option<(u8, u8, u8, u8)>, Vec<PspDirectoryEntry>>;
 
-fn prepare_psp_directory_contents(
+fn prepare_psp_directory_contents<'a>(
     processor_generation: ProcessorGeneration,
-    serde_psp_directory: SerdePspDirectory,
+    serde_psp_directory: SerdePspDirectory<'a>,
     resolve_blob: impl Fn(
         PathBuf,
     ) -> std::prelude::v1::Result<PathBuf, std::io::Error>,
-    _efs_configuration_filename: &Path,
-) -> PspDirectoryContents {
+    efs_configuration_filename: &Path,
+) -> std::io::Result<PspDirectoryContents<'a>> {
     let mut abl_version: Option<u32> = None;
     let mut abl_version_found = false;
     let mut smu_versions: HashMap<u8, VersionedSmuEntry> = HashMap::new();
@@ -1131,7 +2047,12 @@ fn prepare_psp_directory_contents(
     let psp_directory_address_mode = AddressMode::EfsRelativeOffset;
     let mut psp_second_level_directory_template =
         Option::<(SerdePspDirectory, Option<SerdePspDirectoryEntryBlob>)>::None;
-    let psp_raw_entries = serde_psp_directory.entries.into_iter().flat_map(|entry| {
+    let mut image_slot_templates = Vec::new();
+    let mut bhd_directory_template = None;
+    let psp_raw_entries = serde_psp_directory.entries.into_iter().map(|entry| -> std::io::Result<Vec<PspRawDirectoryEntry>> {
+                let entry_error = |message: &str| {
+                    config_entry_error(efs_configuration_filename, entry.span.as_ref(), message)
+                };
                 let mut raw_entry = PspDirectoryEntry::try_from_with_context(
                     psp_directory_address_mode,
                     &entry.target
@@ -1144,8 +2065,14 @@ fn prepare_psp_directory_contents(
                     SerdePspEntrySource::Value(x) => {
                         // FIXME: assert!(blob_slot_settings.is_none()); fails for some reason
                         // DirectoryRelativeOffset is the one that can always be overridden
-                        raw_entry.set_source(AddressMode::DirectoryRelativeOffset, ValueOrLocation::Value(x.to_u64(raw_entry.typ_or_err()).unwrap())).unwrap();
-                        vec![(raw_entry, None, None)]
+                        if let SerdePspEntrySourceValue::PspSoftFuseChain(chain) = &x {
+                            for name in chain.invalid_fields(processor_generation) {
+                                eprintln!("WARNING: PSP soft fuse chain field {name} does not exist on {processor_generation:?}");
+                            }
+                        }
+                        let value = x.to_u64(raw_entry.typ_or_err()).map_err(|e| entry_error(&e.to_string()))?;
+                        raw_entry.set_source(AddressMode::DirectoryRelativeOffset, ValueOrLocation::Value(value)).unwrap();
+                        Ok(vec![(raw_entry, None, None)])
                     }
                     SerdePspEntrySource::BlobFile(
                         blob_filename,
@@ -1153,8 +2080,10 @@ fn prepare_psp_directory_contents(
                         let flash_location =
                             blob_slot_settings.as_ref().and_then(|x| x.flash_location);
                         let x: Option<Location> = flash_location;
-                        let blob_filename = resolve_blob(blob_filename.to_path_buf()).unwrap();
-                        let body = std::fs::read(&blob_filename).unwrap();
+                        let blob_filename = resolve_blob(blob_filename.to_path_buf())?;
+                        let body = std::fs::read(&blob_filename)?;
+                        psp_blob::check_entry(&body, Some(image_slot::psp_entry_type(&raw_entry)), None)
+                            .map_err(|e| entry_error(&e))?;
                         raw_entry.set_size(Some(body.len().try_into().unwrap()));
 
                         match raw_entry.typ_or_err() {
@@ -1173,7 +2102,7 @@ fn prepare_psp_directory_contents(
                                 }
                                 // For now, we do not support different ABL versions in the same image.
                                 if new_abl_version != abl_version {
-                                    panic!("different ABL versions in the same flash are unsupported")
+                                    return Err(entry_error("Different ABL versions in the same flash are unsupported"));
                                 }
                             }
                             // FIXME also check Mp5Firmware versions.
@@ -1189,20 +2118,37 @@ fn prepare_psp_directory_contents(
                             _ => {
                             }
                         }
-                        vec![(raw_entry, x, Some(body))]
+                        Ok(vec![(raw_entry, x, Some(body))])
                     }
                     SerdePspEntrySource::SecondLevelDirectory(d) => {
                         // It is impossible to just create an active PspDirectory here since:
                         // - Allocation of its location has not been done yet
                         // - So we don't know where the directory is going to be.
                         // - But there can be entries in that new directory that are directory-relative.
-                        assert!(psp_second_level_directory_template.is_none());
+                        if psp_second_level_directory_template.is_some() {
+                            return Err(entry_error("There can only be one second level PSP directory"));
+                        }
                         psp_second_level_directory_template = Some((d, entry.target.blob));
-                        vec![]
+                        Ok(vec![])
+                    }
+                    // Same for those.
+                    SerdePspEntrySource::ImageSlotHeader(slot) => {
+                        image_slot_templates.push((slot, entry.target, entry.span.clone()));
+                        Ok(vec![])
+                    }
+                    SerdePspEntrySource::BhdDirectory(d) => {
+                        if bhd_directory_template.is_some() {
+                            return Err(entry_error("There can only be one level 2 BHD directory"));
+                        }
+                        bhd_directory_template = Some((d, entry.target, entry.span.clone()));
+                        Ok(vec![])
                     }
                 }
             })
-            .collect::<Vec<(PspDirectoryEntry, Option<Location>, Option<Vec<u8>>)>>()
+            .collect::<std::io::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<PspRawDirectoryEntry>>()
     ;
 
     // Allow only one SMU version per sub_program
@@ -1229,13 +2175,15 @@ fn prepare_psp_directory_contents(
             unique_smu_versions.insert(*sub_program, *version);
         }
     }
-    PspDirectoryContents {
+    Ok(PspDirectoryContents {
         abl_version,
         unique_smu_versions,
         address_mode: psp_directory_address_mode,
         second_level_directory_template: psp_second_level_directory_template,
+        image_slot_templates,
+        bhd_directory_template,
         raw_entries: psp_raw_entries,
-    }
+    })
 }
 
 struct BhdDirectoryContents<'a> {
@@ -1255,7 +2203,7 @@ fn prepare_bhd_directory_contents<'a>(
     ) -> std::prelude::v1::Result<PathBuf, std::io::Error>,
     efs_configuration_filename: &Path,
     _abl_version: Option<u32>,
-) -> BhdDirectoryContents<'a> {
+) -> std::io::Result<BhdDirectoryContents<'a>> {
     let mut custom_bios_reset_entry: bool = false;
     let apcb_to_io_error = |e| {
         std::io::Error::new(
@@ -1272,10 +2220,26 @@ fn prepare_bhd_directory_contents<'a>(
         SerdeBhdDirectory<'_>,
         Option<SerdeBhdDirectoryEntryBlob>,
     )> = None;
+    // The segments of an ElfFile are entries with the instances after the
+    // one of the ElfFile entry. Those must not be other entries.
+    let entry_keys = serde_bhd_directory
+        .entries
+        .iter()
+        .map(|entry| bhd_entry_key(&entry.target.attrs))
+        .collect::<Vec<_>>();
+    let mut elf_segment_keys = HashSet::<String>::new();
     let bhd_raw_entries = serde_bhd_directory
         .entries
         .into_iter()
-        .flat_map(|entry| {
+        .enumerate()
+        .map(|(index, entry)| -> std::io::Result<Vec<BhdRawDirectoryEntry>> {
+            let entry_error = |message: &str| {
+                config_entry_error(
+                    efs_configuration_filename,
+                    entry.span.as_ref(),
+                    message,
+                )
+            };
             let mut raw_entry = BhdDirectoryEntry::try_from_with_context(
                 bhd_directory_address_mode,
                 &entry.target,
@@ -1283,7 +2247,11 @@ fn prepare_bhd_directory_contents<'a>(
             .unwrap();
             if let Ok(BhdDirectoryEntryType::Bios) = raw_entry.typ_or_err() {
                 if raw_entry.reset_image() {
-                    assert!(!custom_bios_reset_entry);
+                    if custom_bios_reset_entry {
+                        return Err(entry_error(
+                            "There can only be one Bios entry with reset_image",
+                        ));
+                    }
                     custom_bios_reset_entry = true;
                 }
             }
@@ -1300,70 +2268,512 @@ fn prepare_bhd_directory_contents<'a>(
             // done by try_from: raw_entry.set_size(size);
             match source {
                 SerdeBhdSource::Implied => {
-                    assert_eq!(entry.target.attrs.type_, BhdDirectoryEntryType::Apob,
-                        "Implied supports is only supported for Apob, not {typ}. Are you sure you want to do that?",
-                        typ = entry.target.attrs.type_);
-                    assert!(flash_location.is_none(),
-                        "You specified a fixed flash location for {typ} but it has an Implied source. What does that mean?",
-                        typ = entry.target.attrs.type_);
-                    custom_apob = Some(raw_entry.destination_location().expect("destination address"));
+                    let typ = entry.target.attrs.type_;
+                    if typ != BhdDirectoryEntryType::Apob {
+                        return Err(entry_error(&format!(
+                            "Implied supports is only supported for Apob, not {typ}. Are you sure you want to do that?"
+                        )));
+                    }
+                    if flash_location.is_some() {
+                        return Err(entry_error(&format!(
+                            "You specified a fixed flash location for {typ} but it has an Implied source. What does that mean?"
+                        )));
+                    }
+                    let Some(destination_location) =
+                        raw_entry.destination_location()
+                    else {
+                        return Err(entry_error(&format!(
+                            "You specified no ram_destination_address for {typ}"
+                        )));
+                    };
+                    custom_apob = Some(destination_location);
                     raw_entry.set_size(Some(0));
-                    vec![(raw_entry, None, None)]
+                    Ok(vec![(raw_entry, None, None)])
                 }
                 SerdeBhdSource::BlobFile(blob_filename) => {
-                    assert_ne!(entry.target.attrs.type_, BhdDirectoryEntryType::Apob,
-                        "You specified a Blob for Apob? What does that mean?");
-                    let blob_filename = resolve_blob(blob_filename).unwrap();
-                    let body = std::fs::read(blob_filename).unwrap();
+                    if entry.target.attrs.type_ == BhdDirectoryEntryType::Apob {
+                        return Err(entry_error(
+                            "You specified a Blob for Apob? What does that mean?",
+                        ));
+                    }
+                    let blob_filename = resolve_blob(blob_filename)?;
+                    let body = std::fs::read(blob_filename)?;
+                    psp_blob::check_entry(
+                        &body,
+                        None,
+                        Some(entry.target.attrs.compressed),
+                    )
+                    .map_err(|e| entry_error(&e))?;
                     raw_entry.set_size(Some(body.len().try_into().unwrap()));
-                    vec![(raw_entry, flash_location, Some(body))]
+                    Ok(vec![(raw_entry, flash_location, Some(body))])
+                }
+                SerdeBhdSource::ElfFile(elf_filename) => {
+                    if flash_location.is_some()
+                        || raw_entry.destination_location().is_some()
+                    {
+                        return Err(entry_error(
+                            "The flash_location and ram_destination_address of the entries of an ElfFile are those of its segments; please don't specify them",
+                        ));
+                    }
+                    let elf_filename = resolve_blob(elf_filename)?;
+                    let segments = elf_segments(&elf_filename).map_err(|e| {
+                        entry_error(&format!("{elf_filename:?}: {e}"))
+                    })?;
+                    segments
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, (ram_destination_address, body))| -> std::io::Result<BhdRawDirectoryEntry> {
+                            let mut attrs = entry.target.attrs.clone();
+                            attrs.copy_image = true;
+                            // instance is 4 bits.
+                            attrs.instance = u8::try_from(i)
+                                .ok()
+                                .and_then(|i| attrs.instance.checked_add(i))
+                                .filter(|&instance| instance < 16)
+                                .ok_or_else(|| {
+                                    entry_error(&format!(
+                                        "{elf_filename:?} has too many segments"
+                                    ))
+                                })?;
+                            let key = bhd_entry_key(&attrs);
+                            if entry_keys
+                                .iter()
+                                .enumerate()
+                                .any(|(j, x)| j != index && *x == key)
+                                || !elf_segment_keys.insert(key.clone())
+                            {
+                                return Err(entry_error(&format!(
+                                    "Segment {i} of {elf_filename:?} would be {key}, which is another entry of the directory. Hint: Change the instance of one of them"
+                                )));
+                            }
+                            let target = SerdeBhdDirectoryEntry {
+                                attrs,
+                                blob: Some(SerdeBhdDirectoryEntryBlob {
+                                    flash_location: None,
+                                    size: Some(body.len().try_into().unwrap()),
+                                    ram_destination_address: Some(
+                                        ram_destination_address,
+                                    ),
+                                }),
+                            };
+                            let raw_entry =
+                                BhdDirectoryEntry::try_from_with_context(
+                                    bhd_directory_address_mode,
+                                    &target,
+                                )
+                                .map_err(|e| entry_error(&e.to_string()))?;
+                            Ok((raw_entry, None, Some(body)))
+                        })
+                        .collect()
                 }
                 SerdeBhdSource::ApcbJson(apcb) => {
-                    assert!(generate_is_context_valid(processor_generation, &apcb));
+                    if !generate_is_context_valid(processor_generation, &apcb) {
+                        return Err(entry_error(&format!(
+                            "APCB context is not valid for {processor_generation:?}"
+                        )));
+                    }
                     // Note: We need to do this
                     // manually because validation
                     // needs ABL_VERSION.
-                    apcb.validate(None)
-                        .map_err(apcb_to_io_error)
-                        .unwrap();
-                    let buf =
-                        apcb.save_no_inc().map_err(apcb_to_io_error).unwrap();
+                    apcb.validate(None).map_err(|e| {
+                        entry_error(&format!("APCB error: {e:?}"))
+                    })?;
+                    let buf = apcb.save_no_inc().map_err(apcb_to_io_error)?;
                     let bufref = buf.as_ref();
                     if raw_entry.size().is_none() {
                         raw_entry
                             .set_size(Some(bufref.len().try_into().unwrap()));
                     };
 
-                    vec![(raw_entry, None, Some(buf.into_owned()))]
+                    Ok(vec![(raw_entry, None, Some(buf.into_owned()))])
                 }
                 SerdeBhdSource::SecondLevelDirectory(d) => {
                     // It is impossible to just create an active BhdDirectory here since:
                     // - Allocation of its location has not been done yet
                     // - So we don't know where the directory is going to be.
                     // - But there can be entries in that new directory that are directory-relative.
-                    assert!(bhd_second_level_directory_template.is_none());
+                    if bhd_second_level_directory_template.is_some() {
+                        return Err(entry_error(
+                            "There can only be one second level BHD directory",
+                        ));
+                    }
                     bhd_second_level_directory_template =
                         Some((d, blob_slot_settings));
-                    vec![]
+                    Ok(vec![])
                 }
             }
         })
-        .collect::<Vec<(BhdDirectoryEntry, Option<Location>, Option<Vec<u8>>)>>(
-        );
-    BhdDirectoryContents {
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<BhdRawDirectoryEntry>>();
+    Ok(BhdDirectoryContents {
         address_mode: bhd_directory_address_mode,
         custom_apob,
         second_level_directory_template: bhd_second_level_directory_template,
         raw_entries: bhd_raw_entries,
         custom_bios_reset_entry,
+    })
+}
+
+/// Returns what tells BHD directory entries with ATTRS apart, for example
+/// "Bios (instance 1, sub_program 0, rom_id SpiCs1)".
+fn bhd_entry_key(attrs: &SerdeBhdDirectoryEntryAttrs) -> String {
+    format!(
+        "{} (instance {}, sub_program {}, rom_id {:?})",
+        attrs.type_, attrs.instance, attrs.sub_program, attrs.rom_id
+    )
+}
+
+/// APOB entry (at RAM_DESTINATION_ADDRESS) for BHD directories that don't
+/// specify one.
+fn default_apob_entry(ram_destination_address: u64) -> BhdDirectoryEntry {
+    BhdDirectoryEntry::new_payload(
+        AddressMode::PhysicalAddress,
+        BhdDirectoryEntryType::Apob,
+        Some(0),
+        Some(ValueOrLocation::PhysicalAddress(0)),
+        Some(ram_destination_address),
+    )
+    .unwrap()
+}
+
+/// Returns the APOB RAM destination address and size that APOB (from the
+/// config) says, with the defaults of PROCESSOR_GENERATION for what it
+/// doesn't say.
+fn effective_apob(
+    processor_generation: ProcessorGeneration,
+    apob: Option<&SerdeApob>,
+) -> (u64, u64) {
+    (
+        apob.and_then(|x| x.ram_destination_address).unwrap_or(
+            static_config::APOB_RAM_DESTINATION_ADDRESS(processor_generation),
+        ),
+        apob.and_then(|x| x.size)
+            .unwrap_or(static_config::APOB_SIZE(processor_generation)),
+    )
+}
+
+/// Creates the level 2 directories that entries of the PSP directory
+/// CONTENTS point to--that is, the PSP directories of the image slots and
+/// the BHD directory--and adds those entries to CONTENTS.
+/// The level 2 PSP directories themselves are written right away.
+/// Returns the entries of the new directories, the payloads of which still
+/// need to be written.
+#[allow(clippy::too_many_arguments)]
+fn create_psp_level_2_directories<T: FlashRead + FlashWrite>(
+    processor_generation: ProcessorGeneration,
+    contents: &mut PspDirectoryContents<'_>,
+    reset_image: Option<&ResetImage>,
+    default_apob: BhdDirectoryEntry,
+    resolve_blob: impl Fn(PathBuf) -> std::io::Result<PathBuf> + Copy,
+    efs_configuration_filename: &Path,
+    storage: &FlashImage,
+    allocator: &mut impl FlashAllocate,
+    efs: &mut Efs<T>,
+    output_filename: &Path,
+) -> std::io::Result<(Vec<PspRawDirectoryEntry>, Vec<BhdRawDirectoryEntry>)> {
+    let filename = output_filename;
+    let flash_to_io_error = |e: amd_efs::flash::Error| {
+        std::io::Error::other(format!(
+            "Flash error: {e:?} in file {filename:?}"
+        ))
+    };
+    let efs_to_io_error = |e: amd_efs::Error| {
+        std::io::Error::other(format!(
+            "Config error: {e:?} in file {efs_configuration_filename:?}"
+        ))
+    };
+    let mut psp_payload_raw_entries = Vec::<PspRawDirectoryEntry>::new();
+    let mut bhd_payload_raw_entries = Vec::<BhdRawDirectoryEntry>::new();
+
+    if let Some((bhd_template, target, span)) =
+        contents.bhd_directory_template.take()
+    {
+        let entry_error = |message: &str| {
+            config_entry_error(
+                efs_configuration_filename,
+                span.as_ref(),
+                message,
+            )
+        };
+        let BhdDirectoryContents {
+            address_mode: bhd_directory_address_mode,
+            custom_apob,
+            second_level_directory_template: bhd_second_level_directory_template,
+            raw_entries: mut bhd_raw_entries,
+            custom_bios_reset_entry,
+        } = prepare_bhd_directory_contents(
+            processor_generation,
+            bhd_template,
+            resolve_blob,
+            efs_configuration_filename,
+            contents.abl_version,
+        )?;
+        if bhd_second_level_directory_template.is_some() {
+            return Err(entry_error(
+                "Level 2 BHD directories cannot have a second level directory",
+            ));
+        }
+        if custom_apob.is_none() {
+            bhd_raw_entries.push((default_apob, None, None));
+        }
+        match (reset_image, custom_bios_reset_entry) {
+            (Some(reset_image), false) => {
+                if reset_image.clashes_with(&bhd_raw_entries) {
+                    return Err(oem_public_key_clash_error(
+                        efs_configuration_filename,
+                    ));
+                }
+                bhd_raw_entries.extend(reset_image.raw_entries());
+            }
+            (None, true) => {}
+            (Some(_), true) => {
+                return Err(entry_error(
+                    "It's impossible to use both a Bios type Reset entry in a level 2 BHD directory and a (Bios) Reset image on the command line",
+                ));
+            }
+            (None, false) => {
+                return Err(entry_error(
+                    "Without a Bios Reset entry in the level 2 BHD directory, the target will not boot from it. Hint: Please Specify '-r', or add an entry with type 'Bios' to it",
+                ));
+            }
+        }
+        let (
+            mut bhd_directory,
+            bhd_directory_range,
+            bhd_first_payload_range_beginning,
+        ) = create_bhd_directory(
+            BhdDirectoryHeader::SECOND_LEVEL_COOKIE,
+            target.blob.as_ref().and_then(|b| b.flash_location),
+            &mut bhd_raw_entries,
+            bhd_directory_address_mode,
+            storage,
+            allocator,
+            efs,
+            output_filename,
+        )?;
+        let bhd_directory_blob = bhd_directory
+            .save(
+                storage.erasable_block_size(),
+                &bhd_directory_range,
+                bhd_first_payload_range_beginning.unwrap(),
+            )
+            .map_err(efs_to_io_error)?;
+        let mut raw_entry = PspDirectoryEntry::try_from_with_context(
+            contents.address_mode,
+            &target,
+        )
+        .map_err(|e| entry_error(&e.to_string()))?;
+        if image_slot::psp_entry_type(&raw_entry)
+            != image_slot::BHD_LEVEL_2_DIRECTORY_ENTRY_TYPE
+        {
+            return Err(entry_error(
+                "BhdDirectory is only supported for entries of type 0x49",
+            ));
+        }
+        raw_entry.set_size(Some(bhd_directory_blob.len() as u32));
+        contents.raw_entries.push((
+            raw_entry,
+            Some(Location::from(bhd_directory_range.beginning)),
+            Some(bhd_directory_blob),
+        ));
+        bhd_payload_raw_entries.append(&mut bhd_raw_entries);
     }
+
+    for (slot, target, span) in
+        std::mem::take(&mut contents.image_slot_templates)
+    {
+        let entry_error = |message: &str| {
+            config_entry_error(
+                efs_configuration_filename,
+                span.as_ref(),
+                message,
+            )
+        };
+        let mut level_2_contents = prepare_psp_directory_contents(
+            processor_generation,
+            slot.directory,
+            resolve_blob,
+            efs_configuration_filename,
+        )?;
+        if level_2_contents.second_level_directory_template.is_some()
+            || !level_2_contents.image_slot_templates.is_empty()
+        {
+            return Err(entry_error(
+                "Level 2 PSP directories cannot have further levels",
+            ));
+        }
+        match (contents.abl_version, level_2_contents.abl_version) {
+            (Some(a), Some(b)) if a != b => {
+                return Err(entry_error(
+                    "Different ABL versions in the PSP directory and in its image slot directory are not supported",
+                ));
+            }
+            (None, b) => contents.abl_version = b,
+            _ => {}
+        }
+        let (mut level_2_psp_payload_raw_entries, mut level_2_bhd_raw_entries) =
+            create_psp_level_2_directories(
+                processor_generation,
+                &mut level_2_contents,
+                reset_image,
+                default_apob,
+                resolve_blob,
+                efs_configuration_filename,
+                storage,
+                allocator,
+                efs,
+                output_filename,
+            )?;
+        let (
+            mut level_2_directory,
+            level_2_directory_range,
+            level_2_first_payload_range_beginning,
+        ) = create_psp_directory(
+            PspDirectoryHeader::SECOND_LEVEL_COOKIE,
+            slot.directory_flash_location,
+            &mut level_2_contents.raw_entries,
+            level_2_contents.address_mode,
+            storage,
+            allocator,
+            efs,
+            output_filename,
+        )?;
+        let level_2_directory_blob = level_2_directory
+            .save(
+                storage.erasable_block_size(),
+                &level_2_directory_range,
+                level_2_first_payload_range_beginning.unwrap(),
+            )
+            .map_err(efs_to_io_error)?;
+        storage
+            .erase_and_write_blocks(
+                level_2_directory_range.beginning,
+                &level_2_directory_blob,
+            )
+            .map_err(flash_to_io_error)?;
+
+        let header = ImageSlotHeader {
+            boot_priority: slot.boot_priority,
+            update_retry_count: slot.update_retry_count,
+            glitch_retry_count: slot.glitch_retry_count,
+            pl2_location: Location::from(level_2_directory_range.beginning),
+            psp_id: slot.psp_id,
+            slot_max_size: slot.slot_max_size,
+        };
+        let mut raw_entry = PspDirectoryEntry::try_from_with_context(
+            contents.address_mode,
+            &target,
+        )
+        .map_err(|e| entry_error(&e.to_string()))?;
+        if !image_slot::is_image_slot_entry(&raw_entry) {
+            return Err(entry_error(
+                "ImageSlotHeader is only supported for entries of type 0x48 (A) or 0x4a (B)",
+            ));
+        }
+        raw_entry.set_size(Some(image_slot::IMAGE_SLOT_HEADER_SIZE as u32));
+        contents.raw_entries.push((
+            raw_entry,
+            target.blob.as_ref().and_then(|b| b.flash_location),
+            Some(header.to_bytes().to_vec()),
+        ));
+        psp_payload_raw_entries.append(&mut level_2_contents.raw_entries);
+        psp_payload_raw_entries.append(&mut level_2_psp_payload_raw_entries);
+        bhd_payload_raw_entries.append(&mut level_2_bhd_raw_entries);
+    }
+    Ok((psp_payload_raw_entries, bhd_payload_raw_entries))
 }
 
+/// Parses DATA (the contents of EFS_CONFIGURATION_FILENAME) as a JSON5
+/// config. Remembers where each directory entry is so that errors can
+/// point there.
+fn parse_config<'a>(
+    data: &'a str,
+    efs_configuration_filename: &Path,
+) -> std::io::Result<SerdeConfig<'a>> {
+    let json5_to_io_error = |e: json5::Error| match e {
+        json5::Error::Message { ref msg, location: Some(ref location) } => {
+            std::io::Error::other(render_json5_location(
+                efs_configuration_filename,
+                data,
+                location,
+                &format!("JSON5 error: {msg}"),
+            ))
+        }
+        json5::Error::Message { ref msg, location: None } => {
+            std::io::Error::other(format!(
+                "JSON5 error: {msg} in file {efs_configuration_filename:?}"
+            ))
+        }
+    };
+    let mut config: SerdeConfig<'a> =
+        json5::from_str(data).map_err(json5_to_io_error)?;
+    config.locate_entries();
+    Ok(config)
+}
+
+/// Error MESSAGE about the entry at SPAN in EFS_CONFIGURATION_FILENAME
+/// (if known).
+fn config_entry_error(
+    efs_configuration_filename: &Path,
+    span: Option<&SourceSpan>,
+    message: &str,
+) -> std::io::Error {
+    std::io::Error::other(match span {
+        Some(span) => span.render(efs_configuration_filename, message),
+        None => format!("{message} in file {efs_configuration_filename:?}"),
+    })
+}
+
+/// Finds the blob file BLOB_FILENAME. Absolute names are used as-is.
+/// Relative names are searched in BLOBDIRS, in order.
+fn resolve_blob(
+    blobdirs: &[PathBuf],
+    blob_filename: PathBuf,
+    verbose: bool,
+) -> std::io::Result<PathBuf> {
+    if blob_filename.has_root() {
+        if blob_filename.exists() {
+            Ok(blob_filename)
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "Blob read error: Could not find file {blob_filename:?}",
+                ),
+            ))
+        }
+    } else {
+        for blobdir in blobdirs {
+            let fullname = blobdir.join(&blob_filename);
+            if fullname.exists() {
+                if verbose {
+                    eprintln!("Info: Using blob {fullname:?}");
+                }
+                return Ok(fullname);
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "Blob read error: Could not find file {blob_filename:?} \
+(neither directly nor in any of the directories {blobdirs:?})",
+            ),
+        ))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn generate(
     output_filename: &Path,
     image_size: u32,
     efs_configuration_filename: &Path,
     reset_image_filename: &Option<PathBuf>,
+    oem_signing_key_filename: &Option<PathBuf>,
+    oem_public_key_token_filename: &Option<PathBuf>,
+    policy_profile: Option<SerdePolicyProfile>,
+    previous_spl_filename: &Option<PathBuf>,
     blobdirs: Vec<PathBuf>,
     verbose: bool,
 ) -> std::io::Result<()> {
@@ -1383,19 +2793,6 @@ fn generate(
             ),
         )
     };
-    let json5_to_io_error = |e: json5::Error| match e {
-        json5::Error::Message { ref msg, ref location } => std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!(
-                "JSON5 error: {msg} in file {:?} at {}",
-                efs_configuration_filename,
-                match location {
-                    None => "unknown location".to_owned(),
-                    Some(x) => format!("{x:?}"),
-                }
-            ),
-        ),
-    };
     let amd_host_image_builder_config_error_to_io_error =
         |e: amd_host_image_builder_config::Error| {
             std::io::Error::new(
@@ -1408,35 +2805,7 @@ fn generate(
         };
     let blobdirs = &blobdirs;
     let resolve_blob = |blob_filename: PathBuf| -> std::io::Result<PathBuf> {
-        if blob_filename.has_root() {
-            if blob_filename.exists() {
-                Ok(blob_filename)
-            } else {
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!(
-                        "Blob read error: Could not find file {blob_filename:?}",
-                    ),
-                ))
-            }
-        } else {
-            for blobdir in blobdirs {
-                let fullname = blobdir.join(&blob_filename);
-                if fullname.exists() {
-                    if verbose {
-                        eprintln!("Info: Using blob {fullname:?}");
-                    }
-                    return Ok(fullname);
-                }
-            }
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Blob read error: Could not find file {blob_filename:?} \
-(neither directly nor in any of the directories {blobdirs:?})",
-                ),
-            ))
-        }
+        resolve_blob(blobdirs, blob_filename, verbose)
     };
 
     const ERASABLE_BLOCK_SIZE: usize = static_config::ERASABLE_BLOCK_SIZE;
@@ -1446,8 +2815,7 @@ fn generate(
     storage.erase()?;
     let path = Path::new(&efs_configuration_filename);
     let data = std::fs::read_to_string(path)?;
-    let config: SerdeConfig =
-        json5::from_str(&data).map_err(json5_to_io_error)?;
+    let config = parse_config(&data, efs_configuration_filename)?;
 
     let SerdeConfig {
         processor_generation,
@@ -1460,7 +2828,23 @@ fn generate(
         bhd_main_directory_flash_location,
         psp,
         bhd,
+        apob,
+        policy,
     } = config;
+    let (apob_ram_destination_address, apob_size) =
+        effective_apob(processor_generation, apob.as_ref());
+    let default_apob = default_apob_entry(apob_ram_destination_address);
+    let policy = Policy::select(policy_profile, policy.as_ref())?;
+    if let (Some(policy), Some(reset_image_filename)) =
+        (&policy, reset_image_filename)
+    {
+        // Fail before anything is written.
+        policy::report(
+            policy.profile(),
+            &policy.reset_image_problems(reset_image_filename)?,
+            reset_image_filename,
+        )?;
+    }
     let host_processor_generation = processor_generation;
     let mut allocator = ArenaFlashAllocator::new(
         crate::static_config::EFH_BEGINNING(host_processor_generation),
@@ -1494,13 +2878,47 @@ fn generate(
     efs.set_espi0_configuration(espi0_configuration);
     efs.set_espi1_configuration(espi1_configuration);
 
-    let PspDirectoryContents {
-        abl_version,
-        unique_smu_versions: smu_versions,
-        address_mode: psp_directory_address_mode,
-        second_level_directory_template: psp_second_level_directory_template,
-        raw_entries: mut psp_raw_entries,
-    } = prepare_psp_directory_contents(
+    // Level 2 BHD directories (see below) need the reset image, too.
+    let reset_image = match reset_image_filename {
+        Some(reset_image_filename) => {
+            let (entry, body) =
+                bhd_directory_add_reset_image(reset_image_filename)
+                    .map_err(amd_host_image_builder_config_error_to_io_error)?;
+            let signature_entries = match (
+                oem_signing_key_filename,
+                oem_public_key_token_filename,
+            ) {
+                (
+                    Some(oem_signing_key_filename),
+                    Some(oem_public_key_token_filename),
+                ) => OemSigningKey::load(
+                    oem_signing_key_filename,
+                    oem_public_key_token_filename,
+                )?
+                .reset_image_entries(&body)
+                .map_err(amd_host_image_builder_config_error_to_io_error)?,
+                (None, None) => Vec::new(),
+                _ => {
+                    return Err(std::io::Error::other(
+                        "--oem-signing-key and --oem-public-key-token need each other",
+                    ));
+                }
+            };
+            Some(ResetImage { entry, body, signature_entries })
+        }
+        None => {
+            if oem_signing_key_filename.is_some()
+                || oem_public_key_token_filename.is_some()
+            {
+                return Err(std::io::Error::other(
+                    "--oem-signing-key needs a reset image (-r) to sign",
+                ));
+            }
+            None
+        }
+    };
+
+    let mut psp_contents = prepare_psp_directory_contents(
         processor_generation,
         match psp {
             SerdePspDirectoryVariant::PspDirectory(serde_psp_directory) => {
@@ -1512,7 +2930,32 @@ fn generate(
         },
         resolve_blob,
         efs_configuration_filename,
-    );
+    )?;
+    // Payloads of the entries of level 2 directories. Those are not in
+    // psp_raw_entries or bhd_raw_entries until the main directories are
+    // created.
+    let (mut psp_level_2_raw_entries, mut bhd_level_2_raw_entries) =
+        create_psp_level_2_directories(
+            processor_generation,
+            &mut psp_contents,
+            reset_image.as_ref(),
+            default_apob,
+            resolve_blob,
+            efs_configuration_filename,
+            &storage,
+            &mut allocator,
+            &mut efs,
+            output_filename,
+        )?;
+    let PspDirectoryContents {
+        abl_version,
+        unique_smu_versions: smu_versions,
+        address_mode: psp_directory_address_mode,
+        second_level_directory_template: psp_second_level_directory_template,
+        image_slot_templates: _,
+        bhd_directory_template: _,
+        raw_entries: mut psp_raw_entries,
+    } = psp_contents;
     // Since we need to store the pointer to the second-level directory
     // inside the first-level directory, do the second-level directory first.
 
@@ -1525,18 +2968,36 @@ fn generate(
         psp_second_level_directory_container_blob,
     )) = psp_second_level_directory_template
     {
+        let mut psp_second_level_contents = prepare_psp_directory_contents(
+            processor_generation,
+            psp_second_level_directory_template,
+            resolve_blob,
+            efs_configuration_filename,
+        )?;
+        let (mut psp_raw_entries_below, mut bhd_raw_entries_below) =
+            create_psp_level_2_directories(
+                processor_generation,
+                &mut psp_second_level_contents,
+                reset_image.as_ref(),
+                default_apob,
+                resolve_blob,
+                efs_configuration_filename,
+                &storage,
+                &mut allocator,
+                &mut efs,
+                output_filename,
+            )?;
+        psp_level_2_raw_entries.append(&mut psp_raw_entries_below);
+        bhd_level_2_raw_entries.append(&mut bhd_raw_entries_below);
         let PspDirectoryContents {
             abl_version: psp_second_level_abl_version,
             unique_smu_versions: psp_second_level_smu_versions,
             address_mode: psp_second_level_directory_address_mode,
             second_level_directory_template: psp_third_level_directory_template,
+            image_slot_templates: _,
+            bhd_directory_template: _,
             raw_entries: mut psp_second_level_raw_entries,
-        } = prepare_psp_directory_contents(
-            processor_generation,
-            psp_second_level_directory_template,
-            resolve_blob,
-            efs_configuration_filename,
-        );
+        } = psp_second_level_contents;
         assert!(psp_third_level_directory_template.is_none());
         if psp_second_level_abl_version != abl_version {
             panic!(
@@ -1667,7 +3128,7 @@ fn generate(
                 resolve_blob,
                 efs_configuration_filename,
                 abl_version,
-            )
+            )?
         }
         _ => {
             todo!();
@@ -1675,34 +3136,20 @@ fn generate(
     };
 
     if custom_apob.is_none() {
-        let apob_entry = BhdDirectoryEntry::new_payload(
-            AddressMode::PhysicalAddress,
-            BhdDirectoryEntryType::Apob,
-            Some(0),
-            Some(ValueOrLocation::PhysicalAddress(0)),
-            Some(0x400_0000),
-        )
-        .unwrap();
-        bhd_raw_entries.push((apob_entry, None, None));
+        bhd_raw_entries.push((default_apob, None, None));
     }
 
-    let reset_image_entry = if let Some(reset_image_filename) =
-        reset_image_filename
-    {
+    let reset_image = if let Some(reset_image) = reset_image {
         if custom_bios_reset_entry {
             panic!(
                 "It's impossible to use both a Bios type Reset entry in the config file and a (Bios) Reset image on the command line"
             );
         }
-        let (reset_image_entry, reset_image_body) =
-            bhd_directory_add_reset_image(reset_image_filename)
-                .map_err(amd_host_image_builder_config_error_to_io_error)?;
-        bhd_raw_entries.push((
-            reset_image_entry,
-            None,
-            Some(reset_image_body.clone()),
-        ));
-        Some((reset_image_entry, reset_image_body))
+        if reset_image.clashes_with(&bhd_raw_entries) {
+            return Err(oem_public_key_clash_error(efs_configuration_filename));
+        }
+        bhd_raw_entries.extend(reset_image.raw_entries());
+        Some(reset_image)
     } else {
         if !custom_bios_reset_entry {
             panic!(
@@ -1736,29 +3183,38 @@ fn generate(
             resolve_blob,
             efs_configuration_filename,
             abl_version, // second level?
-        );
+        )?;
         assert!(
             bhd_second_level_custom_bios_reset_entry == custom_bios_reset_entry
         );
-        if let Some((reset_image_entry, reset_image_body)) = reset_image_entry {
-            // If such a reset_image_entry exists, we will eventually add the
+        if bhd_second_level_custom_apob.is_none() {
+            bhd_second_level_raw_entries.push((default_apob, None, None));
+        }
+        if let Some(reset_image) = &reset_image {
+            // If such a reset_image exists, we will eventually add the
             // same reset image payload to both directories.
             // That means the apob cannot be possibly different.
-            if bhd_second_level_custom_apob != custom_apob {
-                panic!(
-                    "When adding the same reset image to both the main BHD directory and the second level BHD directory, then you cannot have different APOB locations. But two different APOB locations were requested: {:?} vs {:?}",
-                    custom_apob, bhd_second_level_custom_apob
-                );
+            let apob_location = |custom_apob: Option<u64>| {
+                custom_apob.unwrap_or(apob_ram_destination_address)
+            };
+            if apob_location(bhd_second_level_custom_apob)
+                != apob_location(custom_apob)
+            {
+                return Err(std::io::Error::other(format!(
+                    "{efs_configuration_filename:?}: the main BHD directory and the second level BHD directory get the same reset image, so they cannot have different APOB locations, but {:#x} and {:#x} were requested",
+                    apob_location(custom_apob),
+                    apob_location(bhd_second_level_custom_apob)
+                )));
             }
-            bhd_second_level_raw_entries.push((
-                reset_image_entry,
-                None,
-                Some(reset_image_body),
-            ));
+            if reset_image.clashes_with(&bhd_second_level_raw_entries) {
+                return Err(oem_public_key_clash_error(
+                    efs_configuration_filename,
+                ));
+            }
+            bhd_second_level_raw_entries.extend(reset_image.raw_entries());
         }
 
         assert!(bhd_third_level_directory_template.is_none());
-        // FIXME assert!(bhd_second_level_custom_apob);
         let (
             mut bhd_second_level_directory,
             bhd_second_level_directory_range,
@@ -1838,6 +3294,9 @@ fn generate(
 
     // ============================== Payloads =========================
 
+    psp_raw_entries.append(&mut psp_level_2_raw_entries);
+    bhd_raw_entries.append(&mut bhd_level_2_raw_entries);
+
     for (raw_entry, _, blob_body) in psp_raw_entries {
         //eprintln!("PSP entry {:?}", raw_entry);
         if let Some(blob_body) = blob_body {
@@ -1878,9 +3337,67 @@ fn generate(
         }
     }
 
+    let mut blobs = spl::ImageBlobs::default();
+    let mut footprints = ram_layout::ImageFootprints::new(apob_size);
+    let mut policy_entries = policy.as_ref().map(Policy::image_entries);
+    let mut visitors = Visitors(vec![&mut blobs, &mut footprints]);
+    if let Some(policy_entries) = &mut policy_entries {
+        visitors.0.push(policy_entries);
+    }
+    let processor_generation =
+        walk_image_directories(output_filename, &mut visitors)?;
+
+    let problems = blobs.problems(previous_spl_filename.as_deref(), verbose)?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        fs::remove_file(output_filename)?;
+        return Err(std::io::Error::other(format!(
+            "{} blob(s) below the minimum SPL of the SPL table",
+            problems.len()
+        )));
+    }
+
+    let problems = footprints.problems(verbose);
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        fs::remove_file(output_filename)?;
+        return Err(std::io::Error::other(format!(
+            "{} overlap(s) of RAM destinations",
+            problems.len()
+        )));
+    }
+
+    if let (Some(policy), Some(policy_entries)) = (policy, policy_entries) {
+        let problems = policy.image_problems(
+            &policy_entries,
+            processor_generation,
+            reset_image_filename.as_deref(),
+        )?;
+        if !problems.is_empty() {
+            // Don't leave an image around that violates the policy.
+            fs::remove_file(output_filename)?;
+        }
+        policy::report(policy.profile(), &problems, output_filename)?;
+    }
+
     Ok(())
 }
 
+fn image_size(output_size: ByteSize) -> std::io::Result<u32> {
+    match u32::try_from(output_size.as_u64()).expect("Size <= 32 MiB") {
+        0x100_0000 => Ok(0x100_0000),
+        0x200_0000 => Ok(0x200_0000),
+        _ => Err(std::io::Error::other(format!(
+            "unsupported output size {}",
+            output_size
+        ))),
+    }
+}
+
 fn run() -> std::io::Result<()> {
     let compat_args = std::env::args().collect::<Vec<String>>();
     // Older versions of amd-host-image-builder didn't have subcommands since
@@ -1894,33 +3411,107 @@ fn run() -> std::io::Result<()> {
         Opts::from_args()
     };
     match opts {
-        Opts::Dump { input_filename, blob_dump_dirname } => {
-            dump(&input_filename, blob_dump_dirname)
+        Opts::Dump {
+            input_filename,
+            blob_dump_dirname,
+            content_addressed,
+            reset_image_elf_filename,
+            reset_image_filename,
+        } => {
+            dump(&input_filename, blob_dump_dirname, content_addressed)?;
+            if let Some(reset_image_elf_filename) = reset_image_elf_filename {
+                reset_image_elf::write_elf(
+                    &input_filename,
+                    &reset_image_elf_filename,
+                )?;
+            }
+            if let Some(reset_image_filename) = reset_image_filename {
+                reset_image_elf::compare(
+                    &input_filename,
+                    &reset_image_filename,
+                )?;
+            }
+            Ok(())
         }
+        Opts::Apcb { command } => apcb_tokens::run(command),
         Opts::Generate {
             output_filename,
             output_size,
             efs_configuration_filename,
             reset_image_filename,
+            oem_signing_key_filename,
+            oem_public_key_token_filename,
+            policy_profile,
+            previous_spl_filename,
             blobdirs,
             verbose,
         } => generate(
             &output_filename,
-            match u32::try_from(output_size.as_u64()).expect("Size <= 32 MiB") {
-                0x100_0000 => 0x100_0000,
-                0x200_0000 => 0x200_0000,
-                _ => {
-                    return Err(std::io::Error::other(format!(
-                        "unsupported output size {}",
-                        output_size
-                    )));
-                }
-            },
+            image_size(output_size)?,
+            &efs_configuration_filename,
+            &reset_image_filename,
+            &oem_signing_key_filename,
+            &oem_public_key_token_filename,
+            policy_profile,
+            &previous_spl_filename,
+            blobdirs,
+            verbose,
+        ),
+        Opts::RoundTrip {
+            output_size,
+            reset_image_filename,
+            efs_configuration_filename,
+            blobdirs,
+            work_dirname,
+            content_addressed,
+            verbose,
+        } => round_trip::round_trip(
             &efs_configuration_filename,
+            image_size(output_size)?,
             &reset_image_filename,
             blobdirs,
+            &work_dirname,
+            content_addressed,
             verbose,
         ),
+        Opts::Fmt { check, efs_configuration_filenames } => {
+            fmt::fmt(&efs_configuration_filenames, check)
+        }
+        Opts::Lint {
+            efs_configuration_filename,
+            blobdirs,
+            reset_image_filename,
+        } => lint::lint(
+            &efs_configuration_filename,
+            &blobdirs,
+            reset_image_filename.is_some(),
+        ),
+        Opts::ExplainSoftFuses { input_filename } => {
+            soft_fuses::explain_soft_fuses(&input_filename)
+        }
+        Opts::VerifySignatures {
+            input_filename,
+            efs_configuration_filename,
+            blobdirs,
+        } => verify_signatures::verify_signatures(
+            input_filename.as_deref(),
+            efs_configuration_filename.as_deref(),
+            &blobdirs,
+        ),
+        Opts::Verify {
+            input_filename,
+            policy_profile,
+            reset_image_filename,
+            required_reset_image_symbols,
+        } => policy::verify(
+            &input_filename,
+            policy_profile,
+            reset_image_filename.as_deref(),
+            required_reset_image_symbols,
+        ),
+        Opts::CheckBoot { input_filename } => {
+            check_boot::check_boot(&input_filename)
+        }
     }
 }
 
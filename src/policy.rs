@@ -0,0 +1,508 @@
+//! Policy profiles: checks that an image only has what its purpose allows.
+//! A production image, for example, must not have the secure debug unlock
+//! image and token, must have its payloads signed and must not enable the
+//! console.
+//!
+//! The config (or the command line) only says which profile applies; the
+//! rules of each profile are here.
+
+use amd_efs::{
+    BhdDirectory, BhdDirectoryEntry, BhdDirectoryEntryType,
+    ProcessorGeneration, PspDirectory, PspDirectoryEntry,
+    PspDirectoryEntryType,
+};
+use amd_host_image_builder_config::{
+    SerdeBhdDirectoryEntryType, SerdePolicy, SerdePolicyProfile,
+    SerdePspDirectoryEntryType, SerdePspSoftFuseChain,
+};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::DirectoryVisitor;
+use crate::apcb_tokens::ImageApcbBodies;
+use crate::images::FlashImage;
+use crate::verify_signatures::{ImagePayloads, image_signature_problems};
+
+struct Rules {
+    /// PSP directory entry types that must not be in the image
+    forbidden_psp_entry_types: &'static [PspDirectoryEntryType],
+    /// BHD directory entry types that must not be in the image
+    forbidden_bhd_entry_types: &'static [BhdDirectoryEntryType],
+    /// Whether all the signed payloads must verify and the reset image must
+    /// be signed
+    require_signatures: bool,
+    /// Soft fuse fields and the values they must have
+    required_soft_fuses: &'static [(&'static str, bool)],
+    /// ELF symbols that the reset image must have
+    required_reset_image_symbols: &'static [&'static str],
+    /// ELF symbols that the reset image must not have
+    forbidden_reset_image_symbols: &'static [&'static str],
+    /// APCB tokens of the console and the values (as JSON) they may have
+    allowed_apcb_console_settings:
+        &'static [(&'static str, &'static [&'static str])],
+}
+
+const PRODUCTION_RULES: Rules = Rules {
+    // Those unlock debugging. AmdSecureDebugKey, on the other hand, is only
+    // the public key that the PSP checks unlock tokens against.
+    forbidden_psp_entry_types: &[
+        PspDirectoryEntryType::PspEarlySecureUnlockDebugImage,
+        PspDirectoryEntryType::PspTokenUnlockData,
+    ],
+    forbidden_bhd_entry_types: &[],
+    require_signatures: true,
+    required_soft_fuses: &[("secure_debug_unlock", false)],
+    // Those are only in an ELF file, so a raw reset image (of which nothing
+    // is known) is not accepted.
+    required_reset_image_symbols: &["__sloader", "__eloader"],
+    // bldb (the loader debugger) has the symbols above, too; that's its
+    // marker.
+    forbidden_reset_image_symbols: &["__bldb"],
+    allowed_apcb_console_settings: &[
+        ("FchConsoleOutMode", &["\"Disabled\""]),
+        ("FchConsoleOutBasicEnable", &["0"]),
+    ],
+};
+
+const DEVELOPMENT_RULES: Rules = Rules {
+    forbidden_psp_entry_types: &[],
+    forbidden_bhd_entry_types: &[],
+    require_signatures: false,
+    required_soft_fuses: &[],
+    required_reset_image_symbols: &[],
+    forbidden_reset_image_symbols: &[],
+    allowed_apcb_console_settings: &[],
+};
+
+/// The entries of a flash image, as far as policies are concerned (see
+/// Policy::image_entries).
+#[derive(Default)]
+pub(crate) struct ImageEntries {
+    /// Description, type and (for soft fuse chains) value
+    psp_entries: Vec<(String, SerdePspDirectoryEntryType, Option<u64>)>,
+    bhd_entries: Vec<(String, SerdeBhdDirectoryEntryType)>,
+    /// Description and body of each reset image
+    reset_images: Vec<(String, Vec<u8>)>,
+    /// All the payloads, if the signatures are checked
+    payloads: Option<ImagePayloads>,
+    apcbs: ImageApcbBodies,
+}
+
+impl DirectoryVisitor for ImageEntries {
+    fn psp_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        name: &str,
+    ) {
+        let typ = SerdePspDirectoryEntryType::from(entry);
+        let description =
+            format!("{name}: {typ} (instance {})", entry.instance());
+        let value = if typ == PspDirectoryEntryType::PspSoftFuseChain {
+            entry.value().ok()
+        } else {
+            None
+        };
+        self.psp_entries.push((description, typ, value));
+        if let Some(payloads) = &mut self.payloads {
+            payloads.psp_entry(storage, directory, entry, name);
+        }
+    }
+
+    fn bhd_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        name: &str,
+    ) {
+        let typ = SerdeBhdDirectoryEntryType::from(entry);
+        let description = format!(
+            "{name}: {typ} (instance {}, sub_program {})",
+            entry.instance(),
+            entry.sub_program()
+        );
+        self.bhd_entries.push((description.clone(), typ));
+        if entry.reset_image()
+            && let Some(body) = crate::read_payload(
+                storage,
+                directory.payload_beginning(entry),
+                Some(entry.size().unwrap_or(0)),
+            )
+        {
+            self.reset_images.push((description, body));
+        }
+        if let Some(payloads) = &mut self.payloads {
+            payloads.bhd_entry(storage, directory, entry, name);
+        }
+        self.apcbs.bhd_entry(storage, directory, entry, name);
+    }
+}
+
+pub(crate) struct Policy {
+    profile: SerdePolicyProfile,
+    rules: &'static Rules,
+    /// ELF symbols that the reset image must have in addition to the ones
+    /// of the profile
+    required_reset_image_symbols: Vec<String>,
+}
+
+impl Policy {
+    pub(crate) fn new(
+        profile: SerdePolicyProfile,
+        required_reset_image_symbols: Vec<String>,
+    ) -> Self {
+        let rules = match profile {
+            SerdePolicyProfile::Production => &PRODUCTION_RULES,
+            SerdePolicyProfile::Development => &DEVELOPMENT_RULES,
+        };
+        Self { profile, rules, required_reset_image_symbols }
+    }
+
+    /// Returns the policy that applies to an image: PROFILE (given on the
+    /// command line) or the policy of the config, CONFIG_POLICY.
+    pub(crate) fn select(
+        profile: Option<SerdePolicyProfile>,
+        config_policy: Option<&SerdePolicy>,
+    ) -> std::io::Result<Option<Self>> {
+        let profile = match (profile, config_policy) {
+            (Some(profile), Some(config_policy))
+                if profile != config_policy.profile =>
+            {
+                return Err(std::io::Error::other(format!(
+                    "--policy {profile:?} contradicts the policy {:?} of the config",
+                    config_policy.profile
+                )));
+            }
+            (Some(profile), _) => profile,
+            (None, Some(config_policy)) => config_policy.profile,
+            (None, None) => return Ok(None),
+        };
+        let required_reset_image_symbols = config_policy
+            .map(|x| x.required_reset_image_symbols.clone())
+            .unwrap_or_default();
+        Ok(Some(Self::new(profile, required_reset_image_symbols)))
+    }
+
+    pub(crate) fn profile(&self) -> SerdePolicyProfile {
+        self.profile
+    }
+
+    fn has_reset_image_rules(&self) -> bool {
+        !self.rules.required_reset_image_symbols.is_empty()
+            || !self.rules.forbidden_reset_image_symbols.is_empty()
+            || !self.required_reset_image_symbols.is_empty()
+    }
+
+    /// Returns the problems with the reset image RESET_IMAGE_FILENAME (an
+    /// ELF file, if the policy has any say).
+    pub(crate) fn reset_image_problems(
+        &self,
+        reset_image_filename: &Path,
+    ) -> std::io::Result<Vec<String>> {
+        if !self.has_reset_image_rules() {
+            return Ok(Vec::new());
+        }
+        let buffer = std::fs::read(reset_image_filename)?;
+        let Ok(goblin::Object::Elf(binary)) = goblin::Object::parse(&buffer)
+        else {
+            return Ok(vec![format!(
+                "{reset_image_filename:?}: reset image is not an ELF file, so it has no symbols"
+            )]);
+        };
+        let profile = self.profile;
+        let missing = self
+            .rules
+            .required_reset_image_symbols
+            .iter()
+            .copied()
+            .chain(self.required_reset_image_symbols.iter().map(|x| x.as_str()))
+            .filter(|&name| crate::elf_symbol(&binary, name).is_none())
+            .map(|name| {
+                format!(
+                    "{reset_image_filename:?}: reset image does not have the symbol {name}"
+                )
+            });
+        let forbidden = self
+            .rules
+            .forbidden_reset_image_symbols
+            .iter()
+            .filter(|&&name| crate::elf_symbol(&binary, name).is_some())
+            .map(|name| {
+                format!(
+                    "{reset_image_filename:?}: reset image has the symbol {name}, which is not allowed by policy {profile:?}"
+                )
+            });
+        Ok(missing.chain(forbidden).collect())
+    }
+
+    /// Returns the problems with the types of ENTRIES and with the soft
+    /// fuses in them.
+    fn entry_problems(
+        &self,
+        entries: &ImageEntries,
+    ) -> std::io::Result<Vec<String>> {
+        let profile = self.profile;
+        let mut problems = Vec::new();
+        for (description, typ, value) in &entries.psp_entries {
+            if self.rules.forbidden_psp_entry_types.iter().any(|x| typ == x) {
+                problems.push(format!(
+                    "{description}: not allowed by policy {profile:?}"
+                ));
+            }
+            if let Some(value) = value {
+                let fields = serde_json::to_value(
+                    SerdePspSoftFuseChain::from(*value).named_bits,
+                )
+                .map_err(std::io::Error::other)?;
+                for (name, expected) in self.rules.required_soft_fuses {
+                    if fields.get(name) != Some(&Value::Bool(*expected)) {
+                        problems.push(format!(
+                            "{description}: policy {profile:?} requires {name} to be {expected}"
+                        ));
+                    }
+                }
+            }
+        }
+        for (description, typ) in &entries.bhd_entries {
+            if self.rules.forbidden_bhd_entry_types.iter().any(|x| typ == x) {
+                problems.push(format!(
+                    "{description}: not allowed by policy {profile:?}"
+                ));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Returns what a walk over the directories of a flash image needs to
+    /// collect for image_problems.
+    pub(crate) fn image_entries(&self) -> ImageEntries {
+        ImageEntries {
+            payloads: self
+                .rules
+                .require_signatures
+                .then(ImagePayloads::default),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the problems with the ENTRIES (see image_entries) of a flash
+    /// image for PROCESSOR_GENERATION. If RESET_IMAGE_FILENAME is given,
+    /// that's the reset image the flash image is supposed to have.
+    pub(crate) fn image_problems(
+        &self,
+        entries: &ImageEntries,
+        processor_generation: ProcessorGeneration,
+        reset_image_filename: Option<&Path>,
+    ) -> std::io::Result<Vec<String>> {
+        let profile = self.profile;
+        let mut problems = self.entry_problems(entries)?;
+        if let Some(payloads) = &entries.payloads {
+            problems.extend(image_signature_problems(payloads, true));
+        }
+        match reset_image_filename {
+            Some(reset_image_filename) => {
+                problems
+                    .extend(self.reset_image_problems(reset_image_filename)?);
+                let (_, body) =
+                    crate::bhd_directory_add_reset_image(reset_image_filename)
+                        .map_err(|e| {
+                            std::io::Error::other(format!(
+                                "{reset_image_filename:?}: {e}"
+                            ))
+                        })?;
+                for (description, reset_image) in &entries.reset_images {
+                    if *reset_image != body {
+                        problems.push(format!(
+                            "{description}: is not the reset image {reset_image_filename:?}"
+                        ));
+                    }
+                }
+            }
+            None => {
+                if self.has_reset_image_rules()
+                    && !entries.reset_images.is_empty()
+                {
+                    eprintln!(
+                        "WARNING: The symbols of the reset image can only be checked with -r"
+                    );
+                }
+            }
+        }
+        for (description, tokens) in
+            entries.apcbs.tokens(processor_generation)?
+        {
+            for (name, allowed) in self.rules.allowed_apcb_console_settings {
+                for token in tokens.iter().filter(|x| x.name == *name) {
+                    let is_allowed = allowed.iter().any(|x| {
+                        serde_json::from_str::<Value>(x).ok().as_ref()
+                            == Some(&token.value)
+                    });
+                    if !is_allowed {
+                        problems.push(format!(
+                            "{description}: token {name} is {}, but policy {profile:?} only allows {}",
+                            token.value,
+                            allowed.join(" or ")
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(problems)
+    }
+}
+
+/// Prints PROBLEMS (with the policy PROFILE) and fails if there are any.
+pub(crate) fn report(
+    profile: SerdePolicyProfile,
+    problems: &[String],
+    source: &Path,
+) -> std::io::Result<()> {
+    for problem in problems {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{} problem(s) with policy {profile:?} found in {source:?}",
+            problems.len()
+        )))
+    }
+}
+
+/// Checks the flash image INPUT_FILENAME against the policy PROFILE (with
+/// the additional REQUIRED_RESET_IMAGE_SYMBOLS). RESET_IMAGE_FILENAME is the
+/// ELF file of the reset image that is in the flash image, if known.
+pub(crate) fn verify(
+    input_filename: &Path,
+    profile: SerdePolicyProfile,
+    reset_image_filename: Option<&Path>,
+    required_reset_image_symbols: Vec<String>,
+) -> std::io::Result<()> {
+    let policy = Policy::new(profile, required_reset_image_symbols);
+    let mut entries = policy.image_entries();
+    let processor_generation =
+        crate::walk_image_directories(input_filename, &mut entries)?;
+    let problems = policy.image_problems(
+        &entries,
+        processor_generation,
+        reset_image_filename,
+    )?;
+    report(profile, &problems, input_filename)
+}
+
+/// Returns the PSP directory entries of the shipped config etc/FILENAME as
+/// the policy sees them--after PREPARE has changed the config.
+#[cfg(test)]
+fn shipped_config_entries(
+    filename: &str,
+    prepare: impl FnOnce(&mut Vec<Value>),
+) -> ImageEntries {
+    use amd_host_image_builder_config::{
+        SerdeConfig, SerdePspDirectoryVariant, SerdePspEntrySource,
+        SerdePspEntrySourceValue,
+    };
+    let filename = Path::new("etc").join(filename);
+    let mut config: Value =
+        json5::from_str(&std::fs::read_to_string(&filename).unwrap()).unwrap();
+    prepare(
+        config
+            .pointer_mut("/psp/PspDirectory/entries")
+            .and_then(Value::as_array_mut)
+            .unwrap(),
+    );
+    let data = config.to_string();
+    let config: SerdeConfig<'_> =
+        crate::parse_config(&data, &filename).unwrap();
+    let SerdePspDirectoryVariant::PspDirectory(directory) = config.psp else {
+        panic!("{filename:?} has no PSP directory");
+    };
+    let psp_entries = directory
+        .entries
+        .iter()
+        .map(|entry| {
+            let typ = entry.target.attrs.type_;
+            let value = match &entry.source {
+                SerdePspEntrySource::Value(
+                    SerdePspEntrySourceValue::PspSoftFuseChain(x),
+                ) => Some(x.to_u64().unwrap()),
+                _ => None,
+            };
+            (typ.to_string(), typ, value)
+        })
+        .collect();
+    ImageEntries { psp_entries, ..Default::default() }
+}
+
+#[test]
+fn test_production_rules() {
+    let policy = Policy::new(SerdePolicyProfile::Production, Vec::new());
+    // The shipped configs are for development: they unlock debugging.
+    let entries =
+        shipped_config_entries("milan-gimlet-b-1.0.0.g.efs.json5", |_| {});
+    assert_eq!(
+        policy.entry_problems(&entries).unwrap(),
+        vec![
+            "PspSoftFuseChain: policy Production requires secure_debug_unlock to be false",
+            "PspEarlySecureUnlockDebugImage: not allowed by policy Production",
+            "PspTokenUnlockData: not allowed by policy Production",
+        ]
+    );
+
+    // Without that, they are production configs (and keep their
+    // AmdSecureDebugKey).
+    let entries =
+        shipped_config_entries("milan-gimlet-b-1.0.0.g.efs.json5", |entries| {
+            entries.retain(|entry| {
+                !matches!(
+                    entry.pointer("/target/type").and_then(Value::as_str),
+                    Some(
+                        "PspEarlySecureUnlockDebugImage" | "PspTokenUnlockData"
+                    )
+                )
+            });
+            for entry in entries.iter_mut() {
+                if let Some(x) = entry
+                    .pointer_mut("/source/Value/PspSoftFuseChain")
+                    .and_then(Value::as_object_mut)
+                {
+                    x.insert("secure_debug_unlock".into(), Value::Bool(false));
+                }
+            }
+        });
+    assert!(entries.psp_entries.iter().any(|(_, typ, _)| {
+        *typ == PspDirectoryEntryType::AmdSecureDebugKey
+    }));
+    assert_eq!(policy.entry_problems(&entries).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn test_reset_image_rules() {
+    let policy = Policy::new(SerdePolicyProfile::Production, Vec::new());
+    let data_dirname = Path::new("tests").join("data");
+    let raw_filename = data_dirname.join("test").join("test.blob");
+    let problems = policy.reset_image_problems(&raw_filename).unwrap();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("reset image is not an ELF file"));
+
+    let bldb_filename = data_dirname.join("policy").join("bldb.elf");
+    assert_eq!(
+        policy.reset_image_problems(&bldb_filename).unwrap(),
+        vec![format!(
+            "{bldb_filename:?}: reset image has the symbol __bldb, which is not allowed by policy Production"
+        )]
+    );
+
+    let policy = Policy::new(
+        SerdePolicyProfile::Development,
+        vec!["__bldb".to_string(), "__missing".to_string()],
+    );
+    assert_eq!(
+        policy.reset_image_problems(&bldb_filename).unwrap(),
+        vec![format!(
+            "{bldb_filename:?}: reset image does not have the symbol __missing"
+        )]
+    );
+}
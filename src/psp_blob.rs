@@ -0,0 +1,141 @@
+//! The header (of 0x100 Bytes) that PSP blobs start with. It says how much
+//! of the blob is signed and by which key, and which security patch level
+//! (SPL) the blob has. The signature follows the signed part.
+//!
+//! It also says what kind of firmware the blob is and whether it is
+//! encrypted or compressed, which `check_entry` compares to the directory
+//! entry the blob is configured for.
+
+const HEADER_SIZE: usize = 0x100;
+const MAGIC: &[u8] = b"$PS1";
+const MAGIC_OFFSET: usize = 0x10;
+/// Offset of the size of the signed part (after the header)
+const SIZE_SIGNED_OFFSET: usize = 0x14;
+/// Offset of the flag that says whether the contents are encrypted
+const ENCRYPTED_OFFSET: usize = 0x18;
+/// Offset of the flag that says whether the blob is signed
+const SIGNED_OFFSET: usize = 0x30;
+/// Offset of the id of the signing key
+const SIGNING_KEY_ID_OFFSET: usize = 0x38;
+/// Offset of the flag that says whether the contents are compressed
+const COMPRESSED_OFFSET: usize = 0x48;
+/// Offset of the security patch level
+const SPL_OFFSET: usize = 0x4c;
+/// Offset of the firmware type (the directory entry type the blob is for),
+/// or 0 if the header does not say
+const FIRMWARE_TYPE_OFFSET: usize = 0x5c;
+/// Offset of the size of the entire blob (including signature and
+/// padding), or 0 if the header does not say
+const ROM_SIZE_OFFSET: usize = 0x6c;
+
+pub(crate) struct PspBlobHeader<'a> {
+    header: &'a [u8],
+}
+
+impl<'a> PspBlobHeader<'a> {
+    /// Returns the header of the blob BODY, if it has one.
+    pub(crate) fn parse(body: &'a [u8]) -> Option<Self> {
+        let header = body.get(..HEADER_SIZE)?;
+        if &header[MAGIC_OFFSET..MAGIC_OFFSET + MAGIC.len()] != MAGIC {
+            return None;
+        }
+        Some(Self { header })
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.header[offset..offset + 4].try_into().unwrap())
+    }
+
+    pub(crate) fn is_signed(&self) -> bool {
+        self.u32_at(SIGNED_OFFSET) == 1
+    }
+
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.u32_at(ENCRYPTED_OFFSET) == 1
+    }
+
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.u32_at(COMPRESSED_OFFSET) == 1
+    }
+
+    pub(crate) fn firmware_type(&self) -> Option<u8> {
+        Some(self.header[FIRMWARE_TYPE_OFFSET]).filter(|&x| x != 0)
+    }
+
+    /// Returns the size of the entire blob, if the header says.
+    pub(crate) fn rom_size(&self) -> Option<usize> {
+        Some(self.u32_at(ROM_SIZE_OFFSET) as usize).filter(|&x| x != 0)
+    }
+
+    /// Returns the offset of the end of the signed part (that is, of the
+    /// signature) in the blob.
+    pub(crate) fn signed_end(&self) -> usize {
+        HEADER_SIZE + self.u32_at(SIZE_SIGNED_OFFSET) as usize
+    }
+
+    pub(crate) fn signing_key_id(&self) -> [u8; 16] {
+        self.header[SIGNING_KEY_ID_OFFSET..SIGNING_KEY_ID_OFFSET + 16]
+            .try_into()
+            .unwrap()
+    }
+
+    pub(crate) fn security_patch_level(&self) -> u32 {
+        self.u32_at(SPL_OFFSET)
+    }
+
+    /// Returns the part of BODY (the blob this is the header of) after the
+    /// header, up to the signature.
+    pub(crate) fn contents<'b>(&self, body: &'b [u8]) -> Option<&'b [u8]> {
+        body.get(HEADER_SIZE..self.signed_end())
+    }
+}
+
+/// Checks that the blob BODY (if it has a header) is what the directory
+/// entry says it is: firmware of the entry type TYP, not shorter than its
+/// header says, and encrypted or compressed only if signed (the PSP does
+/// not decrypt or decompress unauthenticated blobs). COMPRESSED is the
+/// compression flag of the entry, if the entry has one.
+/// TYP is only given for PSP directory entries: the firmware type in the
+/// header is a PSP directory entry type, and BHD directory entry types are
+/// different numbers.
+pub(crate) fn check_entry(
+    body: &[u8],
+    typ: Option<u8>,
+    compressed: Option<bool>,
+) -> Result<(), String> {
+    let Some(header) = PspBlobHeader::parse(body) else {
+        return Ok(());
+    };
+    if let Some(typ) = typ
+        && let Some(firmware_type) = header.firmware_type()
+        && firmware_type != typ
+    {
+        return Err(format!(
+            "Blob header says it is firmware of type {firmware_type:#x}, but the entry has type {typ:#x}. Are the blobs swapped?"
+        ));
+    }
+    let size = header.rom_size().unwrap_or(0).max(header.signed_end());
+    if body.len() < size {
+        return Err(format!(
+            "Blob is truncated: its header says it has {size:#x} Bytes, but it only has {:#x} Bytes",
+            body.len()
+        ));
+    }
+    if !header.is_signed() {
+        if header.is_encrypted() {
+            return Err("Blob is encrypted but not signed".to_string());
+        }
+        if header.is_compressed() {
+            return Err("Blob is compressed but not signed".to_string());
+        }
+    }
+    if let Some(compressed) = compressed
+        && compressed != header.is_compressed()
+    {
+        return Err(format!(
+            "Entry has compressed: {compressed}, but the blob header says the blob is {}compressed",
+            if header.is_compressed() { "" } else { "not " }
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! Where in DRAM the BHD entries end up. Entries with a
+//! ram_destination_address (the reset image, other copy-image entries and
+//! the APOB, which the ABL writes there) must not overlap, or one of them
+//! overwrites the other before the x86 cores start.
+
+use amd_efs::{BhdDirectory, BhdDirectoryEntry, BhdDirectoryEntryType};
+use amd_host_image_builder_config::SerdeBhdDirectoryEntryType;
+
+use crate::DirectoryVisitor;
+use crate::images::FlashImage;
+
+/// Part of DRAM that an entry occupies.
+struct Footprint {
+    description: String,
+    beginning: u64,
+    end: u64,
+}
+
+/// The RAM destinations of the entries of each BHD directory of a flash
+/// image.
+pub(crate) struct ImageFootprints {
+    apob_size: u64,
+    /// Footprints of the entries of the BHD directories being walked
+    /// (innermost last)
+    open_directories: Vec<Vec<Footprint>>,
+    /// For each BHD directory, its name and the footprints of its entries
+    directories: Vec<(String, Vec<Footprint>)>,
+}
+
+impl DirectoryVisitor for ImageFootprints {
+    fn bhd_directory(&mut self, _directory: &BhdDirectory, _name: &str) {
+        self.open_directories.push(Vec::new());
+    }
+
+    fn bhd_entry(
+        &mut self,
+        _storage: &FlashImage,
+        _directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        _name: &str,
+    ) {
+        let typ = SerdeBhdDirectoryEntryType::from(entry);
+        let Some(beginning) = entry.destination_location() else {
+            return;
+        };
+        // The APOB entry has no payload; the ABL creates the APOB.
+        let size = if typ == BhdDirectoryEntryType::Apob {
+            self.apob_size
+        } else {
+            u64::from(entry.size().unwrap_or(0))
+        };
+        if size == 0 {
+            return;
+        }
+        if let Some(footprints) = self.open_directories.last_mut() {
+            footprints.push(Footprint {
+                description: format!(
+                    "{typ} (instance {}, sub_program {})",
+                    entry.instance(),
+                    entry.sub_program()
+                ),
+                beginning,
+                end: beginning.saturating_add(size),
+            });
+        }
+    }
+
+    fn bhd_directory_end(&mut self, name: &str) {
+        if let Some(footprints) = self.open_directories.pop() {
+            self.directories.push((name.to_string(), footprints));
+        }
+    }
+}
+
+impl ImageFootprints {
+    /// The APOB is assumed to take APOB_SIZE Bytes.
+    pub(crate) fn new(apob_size: u64) -> Self {
+        Self {
+            apob_size,
+            open_directories: Vec::new(),
+            directories: Vec::new(),
+        }
+    }
+
+    /// Checks that the RAM destinations of the entries of each BHD
+    /// directory do not overlap, and returns the overlaps. If VERBOSE,
+    /// prints the RAM destination of each entry.
+    /// Entries of different directories are not compared, since the PSP
+    /// only uses one of them (and they often have the same reset image).
+    pub(crate) fn problems(&self, verbose: bool) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, footprints) in &self.directories {
+            for (i, a) in footprints.iter().enumerate() {
+                if verbose {
+                    println!(
+                        "{name}: {}: RAM {:#x}..{:#x}",
+                        a.description, a.beginning, a.end
+                    );
+                }
+                for b in &footprints[i + 1..] {
+                    if a.beginning < b.end && b.beginning < a.end {
+                        problems.push(format!(
+                            "{name}: {} (RAM {:#x}..{:#x}) overlaps {} (RAM {:#x}..{:#x})",
+                            a.description,
+                            a.beginning,
+                            a.end,
+                            b.description,
+                            b.beginning,
+                            b.end
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+}
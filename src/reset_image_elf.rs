@@ -0,0 +1,217 @@
+//! Reconstruction of the reset image ELF file from the reset image entry of
+//! an existing flash image. That entry has the loaded segments flattened
+//! into one payload (see bhd_directory_add_reset_image), with the holes
+//! between them filled with zeros. Long runs of zeros are split off again so
+//! that they become holes between segments.
+
+use amd_efs::{BhdDirectory, BhdDirectoryEntry, BhdDirectoryEntryType};
+use std::path::Path;
+
+use crate::DirectoryVisitor;
+use crate::images::FlashImage;
+
+/// Runs of zeros at least that long are holes (not part of any segment).
+const MIN_HOLE_SIZE: usize = 0x1000;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The first reset image (with RAM destination address) of a flash image.
+/// The walk gets to the level 2 BHD directories of the image slots (which
+/// the PSP prefers) before the main BHD directory; generate puts the same
+/// reset image into all of them anyway.
+#[derive(Default)]
+struct ResetImageFinder {
+    /// RAM destination address and payload of the reset image
+    reset_image: Option<(u64, Vec<u8>)>,
+}
+
+impl DirectoryVisitor for ResetImageFinder {
+    fn bhd_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        _name: &str,
+    ) {
+        if self.reset_image.is_none()
+            && matches!(entry.typ_or_err(), Ok(BhdDirectoryEntryType::Bios))
+            && entry.reset_image()
+            && let Some(destination) = entry.destination_location()
+            && let Some(body) = crate::read_payload(
+                storage,
+                directory.payload_beginning(entry),
+                entry.size(),
+            )
+        {
+            self.reset_image = Some((destination, body));
+        }
+    }
+}
+
+/// Returns the RAM destination address and the payload of the reset image
+/// in the flash image INPUT_FILENAME.
+fn image_reset_image(input_filename: &Path) -> std::io::Result<(u64, Vec<u8>)> {
+    let mut finder = ResetImageFinder::default();
+    crate::walk_image_directories(input_filename, &mut finder)?;
+    finder.reset_image.ok_or_else(|| {
+        std::io::Error::other(format!(
+            "{input_filename:?} has no reset image (with ram_destination_address)"
+        ))
+    })
+}
+
+/// Splits BODY (which is at DESTINATION in RAM) into segments, leaving out
+/// runs of at least MIN_HOLE_SIZE zeros.
+fn segments(destination: u64, body: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut result = Vec::new();
+    let mut beginning = 0;
+    let mut offset = 0;
+    while offset < body.len() {
+        if body[offset] != 0 {
+            offset += 1;
+            continue;
+        }
+        let zeros = body[offset..].iter().take_while(|&&x| x == 0).count();
+        if zeros >= MIN_HOLE_SIZE {
+            if offset > beginning {
+                result.push((
+                    destination + beginning as u64,
+                    &body[beginning..offset],
+                ));
+            }
+            beginning = offset + zeros;
+        }
+        offset += zeros;
+    }
+    if body.len() > beginning {
+        result.push((destination + beginning as u64, &body[beginning..]));
+    }
+    result
+}
+
+/// Returns an x86-64 executable ELF file with SEGMENTS (address and
+/// contents) and the entry point ENTRY.
+fn elf_file(segments: &[(u64, &[u8])], entry: u64) -> Vec<u8> {
+    let program_headers_size = PROGRAM_HEADER_SIZE * segments.len();
+    let mut result = Vec::new();
+    result.extend_from_slice(b"\x7fELF");
+    // 64 bit, little endian, version 1, System V ABI
+    result.extend_from_slice(&[2, 1, 1, 0]);
+    result.extend_from_slice(&[0u8; 8]);
+    result.extend_from_slice(&goblin::elf::header::ET_EXEC.to_le_bytes());
+    result.extend_from_slice(&goblin::elf::header::EM_X86_64.to_le_bytes());
+    result.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    result.extend_from_slice(&entry.to_le_bytes());
+    result.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    result.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    result.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    result.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    result.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    result.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+    result.extend_from_slice(&64u16.to_le_bytes()); // e_shentsize
+    result.extend_from_slice(&0u16.to_le_bytes()); // e_shnum
+    result.extend_from_slice(&0u16.to_le_bytes()); // e_shstrndx
+    assert_eq!(result.len(), ELF_HEADER_SIZE);
+    let mut offset = (ELF_HEADER_SIZE + program_headers_size) as u64;
+    for (address, contents) in segments {
+        let size = contents.len() as u64;
+        result.extend_from_slice(
+            &goblin::elf::program_header::PT_LOAD.to_le_bytes(),
+        );
+        result.extend_from_slice(
+            &(goblin::elf::program_header::PF_R
+                | goblin::elf::program_header::PF_W
+                | goblin::elf::program_header::PF_X)
+                .to_le_bytes(),
+        );
+        result.extend_from_slice(&offset.to_le_bytes());
+        result.extend_from_slice(&address.to_le_bytes()); // p_vaddr
+        result.extend_from_slice(&address.to_le_bytes()); // p_paddr
+        result.extend_from_slice(&size.to_le_bytes()); // p_filesz
+        result.extend_from_slice(&size.to_le_bytes()); // p_memsz
+        result.extend_from_slice(&1u64.to_le_bytes()); // p_align
+        offset += size;
+    }
+    for (_, contents) in segments {
+        result.extend_from_slice(contents);
+    }
+    result
+}
+
+/// Rebuilds the reset image of the flash image INPUT_FILENAME as an ELF
+/// file and writes that to OUTPUT_FILENAME. Its entry point is the reset
+/// vector (0x10 Bytes below the end).
+pub(crate) fn write_elf(
+    input_filename: &Path,
+    output_filename: &Path,
+) -> std::io::Result<()> {
+    let (destination, body) = image_reset_image(input_filename)?;
+    let end = destination + body.len() as u64;
+    let segments = segments(destination, &body);
+    std::fs::write(
+        output_filename,
+        elf_file(&segments, end.saturating_sub(0x10)),
+    )?;
+    for (address, contents) in &segments {
+        eprintln!(
+            "Info: Reset image segment 0x{address:x}..0x{:x}",
+            address + contents.len() as u64
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the reset image in the flash image INPUT_FILENAME is the one
+/// that generate makes from the ELF file RESET_IMAGE_FILENAME, Byte for
+/// Byte.
+pub(crate) fn compare(
+    input_filename: &Path,
+    reset_image_filename: &Path,
+) -> std::io::Result<()> {
+    let (destination, body) = image_reset_image(input_filename)?;
+    let (entry, expected_body) = crate::bhd_directory_add_reset_image(
+        reset_image_filename,
+    )
+    .map_err(|e| {
+        std::io::Error::other(format!("{e} in file {reset_image_filename:?}"))
+    })?;
+    let expected_destination = entry.destination_location();
+    if expected_destination != Some(destination) {
+        return Err(std::io::Error::other(format!(
+            "The reset image in {input_filename:?} is at 0x{destination:x}, but {reset_image_filename:?} is at {expected_destination:x?}"
+        )));
+    }
+    if let Some(offset) =
+        body.iter().zip(&expected_body).position(|(a, b)| a != b)
+    {
+        return Err(std::io::Error::other(format!(
+            "The reset image in {input_filename:?} differs from {reset_image_filename:?} at address 0x{:x}",
+            destination + offset as u64
+        )));
+    }
+    if body.len() != expected_body.len() {
+        return Err(std::io::Error::other(format!(
+            "The reset image in {input_filename:?} has 0x{:x} Bytes, but {reset_image_filename:?} has 0x{:x} Bytes",
+            body.len(),
+            expected_body.len()
+        )));
+    }
+    eprintln!(
+        "Info: The reset image in {input_filename:?} matches {reset_image_filename:?}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_segments() {
+    let mut body = vec![0u8; 0x3010];
+    body[0] = 1;
+    body[0x1000] = 2;
+    body[0x300f] = 3;
+    assert_eq!(
+        segments(0x1_0000, &body),
+        vec![(0x1_0000, &body[0..0x1001]), (0x1_300f, &body[0x300f..])]
+    );
+    assert_eq!(segments(0x1_0000, &[0u8; 0x1000]), vec![]);
+}
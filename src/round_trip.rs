@@ -0,0 +1,322 @@
+//! Round-trip check: generate an image from a config, dump that image (with
+//! blobs), generate again from the dumped config and compare the two images.
+//!
+//! If the images are not byte-identical, the regenerated image is dumped as
+//! well and the two dumped configs are compared entry by entry, so the
+//! resulting error says which directory entries differ.
+
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Reads a config written by `dump` as a generic JSON value.
+fn load_dumped_config(filename: &Path) -> std::io::Result<Value> {
+    let data = fs::read_to_string(filename)?;
+    json5::from_str(&data).map_err(|e| {
+        std::io::Error::other(format!(
+            "JSON5 error: {e} in dumped file {filename:?}"
+        ))
+    })
+}
+
+/// Describes the directory entry ENTRY for humans, e.g.
+/// "bhd[3] (Apcb, instance 0, sub_program 0)".
+fn entry_description(path: &str, index: usize, entry: &Value) -> String {
+    let target = &entry["target"];
+    let mut result = format!("{path}[{index}]");
+    if let Some(typ) = target["type"].as_str() {
+        result.push_str(&format!(" ({typ}"));
+        if let Some(instance) = target["instance"].as_u64() {
+            result.push_str(&format!(", instance {instance}"));
+        }
+        if let Some(sub_program) = target["sub_program"].as_u64() {
+            result.push_str(&format!(", sub_program {sub_program}"));
+        }
+        result.push(')');
+    }
+    result
+}
+
+/// Compares the sources of two directory entries. Blob files are compared
+/// by contents, not by name. Their names are relative to the respective
+/// entry of DUMP_DIRNAMES.
+fn source_differences(
+    dump_dirnames: [&Path; 2],
+    description: &str,
+    original: &Value,
+    regenerated: &Value,
+    differences: &mut Vec<String>,
+) {
+    match (original, regenerated) {
+        (Value::Object(original_source), Value::Object(regenerated_source))
+            if original_source.contains_key("SecondLevelDirectory")
+                && regenerated_source.contains_key("SecondLevelDirectory") =>
+        {
+            directory_differences(
+                dump_dirnames,
+                &format!("{description} second level"),
+                &original["SecondLevelDirectory"],
+                &regenerated["SecondLevelDirectory"],
+                differences,
+            );
+        }
+        (Value::Object(original_source), Value::Object(regenerated_source))
+            if original_source.contains_key("BhdDirectory")
+                && regenerated_source.contains_key("BhdDirectory") =>
+        {
+            directory_differences(
+                dump_dirnames,
+                &format!("{description} BHD directory"),
+                &original["BhdDirectory"],
+                &regenerated["BhdDirectory"],
+                differences,
+            );
+        }
+        (Value::Object(original_source), Value::Object(regenerated_source))
+            if original_source.contains_key("ImageSlotHeader")
+                && regenerated_source.contains_key("ImageSlotHeader") =>
+        {
+            let original_slot = &original["ImageSlotHeader"];
+            let regenerated_slot = &regenerated["ImageSlotHeader"];
+            let header = |slot: &Value| {
+                let mut slot = slot.clone();
+                if let Some(fields) = slot.as_object_mut() {
+                    fields.remove("directory");
+                }
+                slot
+            };
+            if header(original_slot) != header(regenerated_slot) {
+                differences
+                    .push(format!("{description}: image slot header differs"));
+            }
+            directory_differences(
+                dump_dirnames,
+                &format!("{description} image slot"),
+                &original_slot["directory"],
+                &regenerated_slot["directory"],
+                differences,
+            );
+        }
+        (Value::Object(original_source), Value::Object(regenerated_source))
+            if original_source.contains_key("BlobFile")
+                && regenerated_source.contains_key("BlobFile") =>
+        {
+            let read = |dump_dirname: &Path, value: &Value| {
+                value["BlobFile"]
+                    .as_str()
+                    .and_then(|name| fs::read(dump_dirname.join(name)).ok())
+            };
+            match (
+                read(dump_dirnames[0], original),
+                read(dump_dirnames[1], regenerated),
+            ) {
+                (Some(a), Some(b)) if a == b => {}
+                (Some(a), Some(b)) => differences.push(format!(
+                    "{description}: blob contents differ ({} bytes vs {} bytes)",
+                    a.len(),
+                    b.len()
+                )),
+                _ => differences.push(format!(
+                    "{description}: could not read dumped blob"
+                )),
+            }
+        }
+        _ => {
+            if original != regenerated {
+                differences.push(format!("{description}: source differs"));
+            }
+        }
+    }
+}
+
+/// Compares two dumped directories (the value inside `PspDirectory`,
+/// `BhdDirectory` or `SecondLevelDirectory`, or an image slot's
+/// `directory`) entry by entry.
+fn directory_differences(
+    dump_dirnames: [&Path; 2],
+    path: &str,
+    original: &Value,
+    regenerated: &Value,
+    differences: &mut Vec<String>,
+) {
+    let empty = Vec::new();
+    let original_entries = original["entries"].as_array().unwrap_or(&empty);
+    let regenerated_entries =
+        regenerated["entries"].as_array().unwrap_or(&empty);
+    if original_entries.len() != regenerated_entries.len() {
+        differences.push(format!(
+            "{path}: {} entries vs {} entries",
+            original_entries.len(),
+            regenerated_entries.len()
+        ));
+    }
+    for (index, (original_entry, regenerated_entry)) in
+        original_entries.iter().zip(regenerated_entries.iter()).enumerate()
+    {
+        let description = entry_description(path, index, original_entry);
+        if original_entry["target"] != regenerated_entry["target"] {
+            differences.push(format!(
+                "{description}: target differs: {} vs {}",
+                original_entry["target"], regenerated_entry["target"]
+            ));
+        }
+        source_differences(
+            dump_dirnames,
+            &description,
+            &original_entry["source"],
+            &regenerated_entry["source"],
+            differences,
+        );
+    }
+}
+
+/// Compares two configs written by `dump` (into the respective entry of
+/// DUMP_DIRNAMES) and returns a list of human-readable differences.
+fn config_differences(
+    dump_dirnames: [&Path; 2],
+    original: &Value,
+    regenerated: &Value,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    let empty = serde_json::Map::new();
+    let original_fields = original.as_object().unwrap_or(&empty);
+    let regenerated_fields = regenerated.as_object().unwrap_or(&empty);
+    for (key, original_value) in original_fields {
+        let regenerated_value = &regenerated[key.as_str()];
+        match key.as_str() {
+            "psp" => directory_differences(
+                dump_dirnames,
+                "psp",
+                &original_value["PspDirectory"],
+                &regenerated_value["PspDirectory"],
+                &mut differences,
+            ),
+            "bhd" => directory_differences(
+                dump_dirnames,
+                "bhd",
+                &original_value["BhdDirectory"],
+                &regenerated_value["BhdDirectory"],
+                &mut differences,
+            ),
+            _ => {
+                if original_value != regenerated_value {
+                    differences.push(format!(
+                        "{key}: {original_value} vs {regenerated_value}"
+                    ));
+                }
+            }
+        }
+    }
+    for key in regenerated_fields.keys() {
+        if !original_fields.contains_key(key) {
+            differences.push(format!("{key}: only in regenerated image"));
+        }
+    }
+    differences
+}
+
+/// Generates an image from EFS_CONFIGURATION_FILENAME, dumps it, generates
+/// an image from the dump and checks that both images are identical.
+/// All intermediate files are kept in WORK_DIRNAME.
+/// CONTENT_ADDRESSED is passed on to `dump`.
+pub(crate) fn round_trip(
+    efs_configuration_filename: &Path,
+    image_size: u32,
+    reset_image_filename: &Option<PathBuf>,
+    blobdirs: Vec<PathBuf>,
+    work_dirname: &Path,
+    content_addressed: bool,
+    verbose: bool,
+) -> std::io::Result<()> {
+    fs::create_dir_all(work_dirname)?;
+
+    let original_filename = work_dirname.join("original.img");
+    crate::generate(
+        &original_filename,
+        image_size,
+        efs_configuration_filename,
+        reset_image_filename,
+        &None,
+        &None,
+        None,
+        &None,
+        blobdirs,
+        verbose,
+    )?;
+    let original_dump_dirname = work_dirname.join("original");
+    crate::dump(
+        &original_filename,
+        Some(original_dump_dirname.clone()),
+        content_addressed,
+    )?;
+    let original_config_filename =
+        original_dump_dirname.join("config.efs.json5");
+
+    // The reset image is part of the dump now.
+    let regenerated_filename = work_dirname.join("regenerated.img");
+    crate::generate(
+        &regenerated_filename,
+        image_size,
+        &original_config_filename,
+        &None,
+        &None,
+        &None,
+        None,
+        &None,
+        vec![original_dump_dirname.clone()],
+        verbose,
+    )?;
+
+    if fs::read(&original_filename)? == fs::read(&regenerated_filename)? {
+        return Ok(());
+    }
+
+    let regenerated_dump_dirname = work_dirname.join("regenerated");
+    crate::dump(
+        &regenerated_filename,
+        Some(regenerated_dump_dirname.clone()),
+        content_addressed,
+    )?;
+    let differences = config_differences(
+        [&original_dump_dirname, &regenerated_dump_dirname],
+        &load_dumped_config(&original_config_filename)?,
+        &load_dumped_config(
+            &regenerated_dump_dirname.join("config.efs.json5"),
+        )?,
+    );
+    Err(std::io::Error::other(format!(
+        "Image regenerated from dump of {efs_configuration_filename:?} differs from {original_filename:?}:\n{}",
+        if differences.is_empty() {
+            "(no entry-level differences; layout differs)".to_string()
+        } else {
+            differences.join("\n")
+        }
+    )))
+}
+
+#[test]
+fn test_config_differences() {
+    let original: Value = json5::from_str(
+        r#"{
+            processor_generation: "Milan",
+            psp: { PspDirectory: { entries: [
+                { source: { Value: 1 }, target: { type: "PspSoftFuseChain" } },
+            ] } },
+            bhd: { BhdDirectory: { entries: [
+                { source: { ApcbJson: { a: 1 } },
+                  target: { type: "Apcb", instance: 0, sub_program: 1 } },
+            ] } },
+        }"#,
+    )
+    .unwrap();
+    let dirnames = [Path::new("original"), Path::new("regenerated")];
+    assert!(config_differences(dirnames, &original, &original).is_empty());
+
+    let mut regenerated = original.clone();
+    regenerated["bhd"]["BhdDirectory"]["entries"][0]["source"]["ApcbJson"]["a"] =
+        Value::from(2);
+    assert_eq!(
+        config_differences(dirnames, &original, &regenerated),
+        vec!["bhd[0] (Apcb, instance 0, sub_program 1): source differs"]
+    );
+}
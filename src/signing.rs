@@ -0,0 +1,269 @@
+//! Signing of the BIOS reset image for Platform Secure Boot (PSB), and
+//! checking of signatures in AMD format.
+//!
+//! The PSP checks the reset image against the signature entry of the BHD
+//! directory, using the key in the OemPublicKey entry of the same
+//! directory. The signature is made here from a local RSA private key.
+//!
+//! The OemPublicKey entry is the public key token of that key as AMD issues
+//! it: certified by the AMD root key (the AmdPublicKey entry), which the
+//! PSP checks it against once PSB is enabled in the fuses. A token cannot
+//! be made here, so it is taken as it is (and only checked against the
+//! private key).
+//!
+//! Like all of AMD's keys and signatures, those are stored little-endian.
+
+use amd_efs::{AddressMode, BhdDirectoryEntry, BhdDirectoryEntryType};
+use amd_host_image_builder_config::{
+    Result, SerdeBhdDirectoryEntryAttrs, raw_entry,
+};
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::SeedableRng;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{RandomizedSigner, SignatureEncoding, Verifier};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha384};
+use std::path::Path;
+
+/// BHD directory entry type whose payload is the signature of the reset
+/// image. It's matched by number since amd-efs doesn't know it (by name).
+pub(crate) const BIOS_SIGNATURE_ENTRY_TYPE: u8 = 0x07;
+
+/// Version of the format of public key tokens.
+const PUBLIC_KEY_TOKEN_VERSION: u32 = 1;
+
+/// Key usage (in a public key token) of a key that signs the BIOS.
+pub(crate) const KEY_USAGE_OEM_BIOS_SIGNING: u32 = 8;
+
+/// Size of the header of a public key token, which is followed by the
+/// exponent, the modulus and the signature of the token.
+const PUBLIC_KEY_TOKEN_HEADER_SIZE: usize = 0x40;
+
+/// A public key token (the payload of AmdPublicKey, AblPublicKey and
+/// OemPublicKey entries).
+pub(crate) struct PublicKeyToken {
+    pub(crate) key_id: [u8; 16],
+    /// Id of the key that signed this token
+    pub(crate) certifying_key_id: [u8; 16],
+    /// What the key is for (for example KEY_USAGE_OEM_BIOS_SIGNING)
+    pub(crate) key_usage: u32,
+    pub(crate) key: RsaPublicKey,
+    /// The part of the token that the signature is over
+    pub(crate) signed_part: Vec<u8>,
+    /// Signature by the certifying key (empty if the token has none)
+    pub(crate) signature: Vec<u8>,
+}
+
+impl PublicKeyToken {
+    /// Parses the public key token DATA.
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        let u32_at = |offset: usize| {
+            Some(u32::from_le_bytes(
+                data.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        if u32_at(0)? != PUBLIC_KEY_TOKEN_VERSION {
+            return None;
+        }
+        let exponent_size = usize::try_from(u32_at(0x38)? / 8).ok()?;
+        let modulus_size = usize::try_from(u32_at(0x3c)? / 8).ok()?;
+        let exponent_end = PUBLIC_KEY_TOKEN_HEADER_SIZE + exponent_size;
+        let modulus_end = exponent_end + modulus_size;
+        let exponent = BigUint::from_bytes_le(
+            data.get(PUBLIC_KEY_TOKEN_HEADER_SIZE..exponent_end)?,
+        );
+        let modulus =
+            BigUint::from_bytes_le(data.get(exponent_end..modulus_end)?);
+        Some(Self {
+            key_id: data[0x04..0x14].try_into().ok()?,
+            certifying_key_id: data[0x14..0x24].try_into().ok()?,
+            key_usage: u32_at(0x24)?,
+            key: RsaPublicKey::new(modulus, exponent).ok()?,
+            signed_part: data[..modulus_end].to_vec(),
+            signature: data
+                .get(modulus_end..modulus_end + modulus_size)
+                .unwrap_or_default()
+                .to_vec(),
+        })
+    }
+
+    /// Returns whether SIGNATURE (in AMD format) is a signature of DATA by
+    /// this key.
+    pub(crate) fn verifies(&self, data: &[u8], signature: &[u8]) -> bool {
+        let mut signature = signature.to_vec();
+        signature.reverse();
+        let Ok(signature) = rsa::pss::Signature::try_from(signature.as_slice())
+        else {
+            return false;
+        };
+        let key = self.key.clone();
+        if key.size() == 256 {
+            rsa::pss::VerifyingKey::<Sha256>::new(key)
+                .verify(data, &signature)
+                .is_ok()
+        } else {
+            rsa::pss::VerifyingKey::<Sha384>::new(key)
+                .verify(data, &signature)
+                .is_ok()
+        }
+    }
+}
+
+/// Returns what is wrong with TOKEN as public key token of KEY for signing
+/// the reset image, if anything.
+fn token_problem(key: &RsaPrivateKey, token: &[u8]) -> Option<&'static str> {
+    let Some(token) = PublicKeyToken::parse(token) else {
+        return Some("is not a public key token");
+    };
+    if token.key != RsaPublicKey::from(key) {
+        Some("is not the token of the key")
+    } else if token.key_usage != KEY_USAGE_OEM_BIOS_SIGNING {
+        Some("is not the token of a key for signing the BIOS")
+    } else if token.certifying_key_id == token.key_id {
+        Some(
+            "is self-signed (the PSP only accepts a token that is certified by the AMD root key)",
+        )
+    } else {
+        None
+    }
+}
+
+pub(crate) struct OemSigningKey {
+    key: RsaPrivateKey,
+    /// Public key token of KEY, certified by AMD
+    token: Vec<u8>,
+}
+
+impl OemSigningKey {
+    /// Loads the RSA private key from FILENAME (PEM; PKCS #8 or PKCS #1)
+    /// and its public key token (certified by AMD) from TOKEN_FILENAME.
+    pub(crate) fn load(
+        filename: &Path,
+        token_filename: &Path,
+    ) -> std::io::Result<Self> {
+        let pem = std::fs::read_to_string(filename)?;
+        let key = RsaPrivateKey::from_pkcs8_pem(&pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+            .map_err(|e| {
+                std::io::Error::other(format!(
+                    "{filename:?} is not an RSA private key in PEM format: {e}"
+                ))
+            })?;
+        if !matches!(key.size(), 256 | 512) {
+            return Err(std::io::Error::other(format!(
+                "{filename:?} has a {}-bit RSA key; only 2048 and 4096 bits are supported",
+                key.size() * 8
+            )));
+        }
+        let token = std::fs::read(token_filename)?;
+        if let Some(problem) = token_problem(&key, &token) {
+            return Err(std::io::Error::other(format!(
+                "{token_filename:?} {problem} in {filename:?}"
+            )));
+        }
+        Ok(Self { key, token })
+    }
+
+    /// Returns the signature of DATA in AMD format: RSASSA-PSS with SHA-256
+    /// (2048-bit keys) or SHA-384 (4096-bit keys), little-endian.
+    pub(crate) fn sign(&self, data: &[u8]) -> Vec<u8> {
+        // The salt is derived from DATA so that generating the same image
+        // twice gives the same result.
+        let mut rng = ChaCha20Rng::from_seed(Sha256::digest(data).into());
+        let key = self.key.clone();
+        let mut signature = if key.size() == 256 {
+            rsa::pss::SigningKey::<Sha256>::new(key)
+                .sign_with_rng(&mut rng, data)
+                .to_vec()
+        } else {
+            rsa::pss::SigningKey::<Sha384>::new(key)
+                .sign_with_rng(&mut rng, data)
+                .to_vec()
+        };
+        signature.reverse();
+        signature
+    }
+
+    /// Returns the entries (and their payloads) that have to be next to the
+    /// reset image RESET_IMAGE_BODY in each BHD directory: the OemPublicKey
+    /// and the signature of the reset image.
+    pub(crate) fn reset_image_entries(
+        &self,
+        reset_image_body: &[u8],
+    ) -> Result<Vec<(BhdDirectoryEntry, Vec<u8>)>> {
+        let size = |body: &[u8]| {
+            u32::try_from(body.len())
+                .map_err(|_| amd_efs::Error::DirectoryPayloadRangeCheck)
+        };
+        let signature = self.sign(reset_image_body);
+        let key_entry = BhdDirectoryEntry::new_payload(
+            AddressMode::EfsRelativeOffset,
+            BhdDirectoryEntryType::OemPublicKey,
+            Some(size(&self.token)?),
+            None,
+            None,
+        )?;
+        let mut signature_entry = raw_entry::bhd_directory_entry(
+            BIOS_SIGNATURE_ENTRY_TYPE,
+            &SerdeBhdDirectoryEntryAttrs::builder(),
+            None,
+        )?;
+        signature_entry.set_size(Some(size(&signature)?));
+        Ok(vec![(key_entry, self.token.clone()), (signature_entry, signature)])
+    }
+}
+
+#[test]
+fn test_sign() {
+    let data_dirname = Path::new("tests").join("data").join("test");
+    let key = OemSigningKey::load(
+        &data_dirname.join("oem-signing-key.pem"),
+        &data_dirname.join("oem-public-key-token.bin"),
+    )
+    .unwrap();
+    let amd_root_key = PublicKeyToken::parse(
+        &std::fs::read(data_dirname.join("amd-root-key.bin")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(amd_root_key.key_id, amd_root_key.certifying_key_id);
+
+    // The token as AMD lays it out: header, exponent, modulus, signature.
+    let token = PublicKeyToken::parse(&key.token).unwrap();
+    assert_eq!(key.token.len(), PUBLIC_KEY_TOKEN_HEADER_SIZE + 3 * 256);
+    assert_eq!(&key.token[0x38..0x40], &[0, 8, 0, 0, 0, 8, 0, 0]);
+    assert_eq!(token.key_usage, KEY_USAGE_OEM_BIOS_SIGNING);
+    assert_eq!(token.certifying_key_id, amd_root_key.key_id);
+    assert_eq!(token.key, RsaPublicKey::from(&key.key));
+    assert!(amd_root_key.verifies(&token.signed_part, &token.signature));
+
+    let data = b"reset image";
+    let signature = key.sign(data);
+    assert_eq!(signature, key.sign(data));
+    assert!(token.verifies(data, &signature));
+    assert!(!token.verifies(b"other image", &signature));
+}
+
+#[test]
+fn test_token_problem() {
+    let data_dirname = Path::new("tests").join("data").join("test");
+    let key = OemSigningKey::load(
+        &data_dirname.join("oem-signing-key.pem"),
+        &data_dirname.join("oem-public-key-token.bin"),
+    )
+    .unwrap();
+    assert_eq!(token_problem(&key.key, &key.token), None);
+    let mut self_signed = key.token.clone();
+    self_signed.copy_within(0x04..0x14, 0x14);
+    assert!(token_problem(&key.key, &self_signed).unwrap().contains("self"));
+    let amd_root_key = std::fs::read(data_dirname.join("amd-root-key.bin"));
+    assert_eq!(
+        token_problem(&key.key, &amd_root_key.unwrap()),
+        Some("is not the token of the key")
+    );
+    assert_eq!(
+        token_problem(&key.key, &key.token[..0x40]),
+        Some("is not a public key token")
+    );
+}
@@ -0,0 +1,124 @@
+//! Explains the PSP soft fuse chains of an existing flash image: which named
+//! fields are set, which bits amd-efs has no name for, and which fields are
+//! known to be missing on the processor generation of the image.
+
+use amd_efs::{
+    Efs, ProcessorGeneration, PspDirectory, PspDirectoryEntry,
+    PspDirectoryEntryType,
+};
+use amd_host_image_builder_config::SerdePspSoftFuseChain;
+use std::path::Path;
+
+use crate::images::FlashImage;
+use crate::{DirectoryVisitor, DirectoryWalker};
+
+/// Returns CHAIN in the form it has as the source value of a config entry
+/// (only with the fields that are set).
+pub(crate) fn config_source_value(chain: &SerdePspSoftFuseChain) -> String {
+    let mut fields = chain
+        .set_fields()
+        .into_iter()
+        .map(|(field, value)| format!("{}: {value}", field.name))
+        .collect::<Vec<_>>();
+    if chain.unknown_bits != 0 {
+        fields.push(format!("unknown_bits: {:#x}", chain.unknown_bits));
+    }
+    if fields.is_empty() {
+        "{ PspSoftFuseChain: {} }".to_string()
+    } else {
+        format!("{{ PspSoftFuseChain: {{ {} }} }}", fields.join(", "))
+    }
+}
+
+fn bits_description(bits: &[u32]) -> String {
+    match bits {
+        [bit] => format!("bit {bit}"),
+        [first, .., last] => format!("bits {first}-{last}"),
+        [] => "no bits".to_string(),
+    }
+}
+
+/// Prints what the soft fuse chain VALUE of the entry DESCRIPTION means.
+fn explain(
+    description: &str,
+    value: u64,
+    processor_generation: ProcessorGeneration,
+) {
+    let chain = SerdePspSoftFuseChain::from(value);
+    println!("{description}: {value:#x}");
+    for (field, value) in chain.set_fields() {
+        println!(
+            "    {} = {value} ({})",
+            field.name,
+            bits_description(&field.bits)
+        );
+    }
+    if chain.unknown_bits != 0 {
+        println!("    unknown_bits = {:#x}", chain.unknown_bits);
+    }
+    for name in chain.invalid_fields(processor_generation) {
+        println!(
+            "    WARNING: {name} does not exist on {processor_generation:?}"
+        );
+    }
+    println!("    config: {}", config_source_value(&chain));
+}
+
+/// Explains each soft fuse chain of a flash image.
+struct SoftFuseExplainer {
+    processor_generation: ProcessorGeneration,
+    count: usize,
+}
+
+impl DirectoryVisitor for SoftFuseExplainer {
+    fn psp_entry(
+        &mut self,
+        _storage: &FlashImage,
+        _directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        name: &str,
+    ) {
+        if matches!(
+            entry.typ_or_err(),
+            Ok(PspDirectoryEntryType::PspSoftFuseChain)
+        ) && let Ok(value) = entry.value()
+        {
+            explain(
+                &format!(
+                    "{name}: PspSoftFuseChain (instance {})",
+                    entry.instance()
+                ),
+                value,
+                self.processor_generation,
+            );
+            self.count += 1;
+        }
+    }
+}
+
+/// Explains the soft fuse chains of the flash image INPUT_FILENAME.
+pub(crate) fn explain_soft_fuses(input_filename: &Path) -> std::io::Result<()> {
+    let efs_to_io_error = |e| {
+        std::io::Error::other(format!(
+            "EFS error: {e:?} in file {input_filename:?}"
+        ))
+    };
+    let storage = FlashImage::load(input_filename)?;
+    let amd_physical_mode_mmio_size =
+        crate::amd_physical_mode_mmio_size(&storage)?;
+    let efs = Efs::load(&storage, None, amd_physical_mode_mmio_size)
+        .map_err(efs_to_io_error)?;
+    let mut explainer = SoftFuseExplainer {
+        processor_generation: crate::efs_processor_generation(&efs),
+        count: 0,
+    };
+    DirectoryWalker::new(&storage, amd_physical_mode_mmio_size)
+        .walk_efs(&efs, &mut explainer)
+        .map_err(efs_to_io_error)?;
+    if explainer.count == 0 {
+        return Err(std::io::Error::other(format!(
+            "{input_filename:?} has no PspSoftFuseChain entry"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! Security patch levels (SPL). Each PSP blob says in its header which SPL
+//! it has, and the SPL table (the payload of the BootloaderSplTable entry)
+//! says which SPL the blobs of each entry type must at least have. The PSP
+//! refuses to load blobs below that (anti-rollback).
+
+use amd_efs::{PspDirectory, PspDirectoryEntry, PspDirectoryEntryType};
+use amd_host_image_builder_config::SerdePspDirectoryEntryType;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::DirectoryVisitor;
+use crate::image_slot;
+use crate::images::FlashImage;
+use crate::psp_blob::PspBlobHeader;
+
+/// The SPL table: the minimum SPL for each (raw) PSP directory entry type.
+struct SplTable {
+    minimums: Vec<(u8, u32)>,
+}
+
+impl SplTable {
+    /// Parses the SPL table BODY. After the PSP blob header, it has the
+    /// number of entries and then, for each entry, the PSP directory entry
+    /// type and the minimum SPL (all u32), maybe followed by zeros.
+    /// Note: AMD does not publish that layout, and no SPL table that AMD
+    /// ships is available to check it against. So whatever does not fit it
+    /// exactly is refused rather than taken for minimums.
+    fn parse(body: &[u8]) -> Option<Self> {
+        let contents = PspBlobHeader::parse(body)?.contents(body)?;
+        let (count, rest) = contents.split_first_chunk::<4>()?;
+        let count = usize::try_from(u32::from_le_bytes(*count)).ok()?;
+        let (entries, padding) =
+            rest.split_at_checked(count.checked_mul(8)?)?;
+        if padding.iter().any(|&x| x != 0) {
+            return None;
+        }
+        let minimums = entries
+            .chunks_exact(8)
+            .map(|entry| {
+                let (typ, minimum) = entry.split_at(4);
+                let typ = u32::from_le_bytes(typ.try_into().unwrap());
+                let minimum = u32::from_le_bytes(minimum.try_into().unwrap());
+                Some((u8::try_from(typ).ok()?, minimum))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { minimums })
+    }
+
+    fn minimum(&self, typ: u8) -> Option<u32> {
+        self.minimums.iter().find(|(x, _)| *x == typ).map(|(_, spl)| *spl)
+    }
+}
+
+/// A PSP blob that has an SPL.
+struct Blob {
+    description: String,
+    typ: SerdePspDirectoryEntryType,
+    raw_type: u8,
+    spl: u32,
+}
+
+/// The PSP blobs (with SPL) and the SPL tables of a flash image.
+#[derive(Default)]
+pub(crate) struct ImageBlobs {
+    blobs: Vec<Blob>,
+    spl_tables: Vec<(String, SplTable)>,
+}
+
+impl DirectoryVisitor for ImageBlobs {
+    fn psp_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        name: &str,
+    ) {
+        let Some(body) = crate::read_payload(
+            storage,
+            directory.payload_beginning(entry),
+            entry.size(),
+        ) else {
+            return;
+        };
+        let typ = SerdePspDirectoryEntryType::from(entry);
+        let description =
+            format!("{name}: {typ} (instance {})", entry.instance());
+        if typ == PspDirectoryEntryType::BootloaderSplTable {
+            match SplTable::parse(&body) {
+                Some(table) => self.spl_tables.push((description, table)),
+                None => eprintln!(
+                    "WARNING: {description}: SPL table has an unknown layout, so blobs are not checked against it"
+                ),
+            }
+        } else if let Some(header) = PspBlobHeader::parse(&body) {
+            self.blobs.push(Blob {
+                description,
+                typ,
+                raw_type: image_slot::psp_entry_type(entry),
+                spl: header.security_patch_level(),
+            });
+        }
+    }
+}
+
+/// Returns the PSP blobs (with SPL) and the SPL tables of the flash image
+/// INPUT_FILENAME.
+fn image_blobs(input_filename: &Path) -> std::io::Result<ImageBlobs> {
+    let mut result = ImageBlobs::default();
+    crate::walk_image_directories(input_filename, &mut result)?;
+    Ok(result)
+}
+
+/// Returns the SPL of each entry type (by name) in BLOBS. If there are
+/// multiple blobs of one type, that's the lowest SPL.
+fn spls_by_type(blobs: &[Blob]) -> BTreeMap<String, u32> {
+    let mut result = BTreeMap::<String, u32>::new();
+    for blob in blobs {
+        result
+            .entry(blob.typ.to_string())
+            .and_modify(|x| *x = (*x).min(blob.spl))
+            .or_insert(blob.spl);
+    }
+    result
+}
+
+/// Returns the SPL of each entry type in PREVIOUS_FILENAME: either a flash
+/// image or an SPL manifest (a JSON5 object from entry type to SPL, for
+/// example `{ PspBootloader: 3 }`).
+fn previous_spls(
+    previous_filename: &Path,
+) -> std::io::Result<BTreeMap<String, u32>> {
+    let data = std::fs::read(previous_filename)?;
+    match std::str::from_utf8(&data) {
+        Ok(text) if text.trim_start().starts_with('{') => json5::from_str(text)
+            .map_err(|e| {
+                std::io::Error::other(format!(
+                    "{previous_filename:?} is not an SPL manifest: {e}"
+                ))
+            }),
+        _ => Ok(spls_by_type(&image_blobs(previous_filename)?.blobs)),
+    }
+}
+
+impl ImageBlobs {
+    /// Returns the description and the SPL of each PSP blob (that has an
+    /// SPL).
+    pub(crate) fn spls(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.blobs.iter().map(|blob| (blob.description.as_str(), blob.spl))
+    }
+
+    /// Checks the SPLs of the blobs against the SPL table(s) and returns
+    /// the blobs below the minimum. If VERBOSE, prints the SPL of each
+    /// blob. If PREVIOUS_FILENAME is given, warns about entry types whose
+    /// SPL is lower than there.
+    pub(crate) fn problems(
+        &self,
+        previous_filename: Option<&Path>,
+        verbose: bool,
+    ) -> std::io::Result<Vec<String>> {
+        let mut problems = Vec::new();
+        for blob in &self.blobs {
+            if verbose {
+                println!("{}: SPL {:#x}", blob.description, blob.spl);
+            }
+            for (table_description, table) in &self.spl_tables {
+                if let Some(minimum) = table.minimum(blob.raw_type)
+                    && blob.spl < minimum
+                {
+                    problems.push(format!(
+                        "{}: SPL {:#x} is below the minimum SPL {minimum:#x} of {table_description}",
+                        blob.description, blob.spl
+                    ));
+                }
+            }
+        }
+        if let Some(previous_filename) = previous_filename {
+            let previous = previous_spls(previous_filename)?;
+            for (typ, spl) in spls_by_type(&self.blobs) {
+                if let Some(&previous_spl) = previous.get(&typ)
+                    && spl < previous_spl
+                {
+                    eprintln!(
+                        "WARNING: {typ} would roll back from SPL {previous_spl:#x} (in {previous_filename:?}) to SPL {spl:#x}"
+                    );
+                }
+            }
+        }
+        Ok(problems)
+    }
+}
+
+#[test]
+fn test_spl_table() {
+    let body = std::fs::read("tests/data/spl/spl-table.sbin").unwrap();
+    let table = SplTable::parse(&body).unwrap();
+    assert_eq!(table.minimums, vec![(1, 3)]);
+    assert_eq!(table.minimum(1), Some(3));
+    assert_eq!(table.minimum(2), None);
+
+    // The signed part (see PspBlobHeader::contents) of BODY changed to
+    // CONTENTS.
+    let with_contents = |contents: &[u8]| {
+        let mut result = body[..0x100].to_vec();
+        result[0x14..0x18]
+            .copy_from_slice(&(contents.len() as u32).to_le_bytes());
+        result.extend_from_slice(contents);
+        result
+    };
+    let padded = with_contents(&[1, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 0, 0]);
+    assert_eq!(SplTable::parse(&padded).unwrap().minimums, vec![(1, 3)]);
+    let truncated = with_contents(&[2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0]);
+    assert!(SplTable::parse(&truncated).is_none());
+    let trailing = with_contents(&[1, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 1]);
+    assert!(SplTable::parse(&trailing).is_none());
+    let wide_type = with_contents(&[1, 0, 0, 0, 0, 1, 0, 0, 3, 0, 0, 0]);
+    assert!(SplTable::parse(&wide_type).is_none());
+    assert!(SplTable::parse(&body[0x100..]).is_none());
+}
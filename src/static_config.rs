@@ -73,3 +73,34 @@ pub(crate) const fn EFH_BEGINNING(
         ProcessorGeneration::Genoa | ProcessorGeneration::Turin => 0x2_0000,
     }
 }
+
+/// Default DRAM location that the ABL writes the APOB to (that's the
+/// ram_destination_address of the Apob entry). The Turin configs in etc/
+/// that have an Apob entry have it there, too.
+/// Note: Like EFH_BEGINNING, this is per generation on purpose, even though
+/// all generations so far have the same value.
+#[allow(non_snake_case)]
+pub(crate) const fn APOB_RAM_DESTINATION_ADDRESS(
+    processor_generation: ProcessorGeneration,
+) -> u64 {
+    match processor_generation {
+        ProcessorGeneration::Naples
+        | ProcessorGeneration::Rome
+        | ProcessorGeneration::Milan => 0x400_0000,
+        ProcessorGeneration::Genoa | ProcessorGeneration::Turin => 0x400_0000,
+    }
+}
+
+/// Default DRAM space to leave free for the APOB. The Apob entry doesn't
+/// say how much the ABL writes.
+#[allow(non_snake_case)]
+pub(crate) const fn APOB_SIZE(
+    processor_generation: ProcessorGeneration,
+) -> u64 {
+    match processor_generation {
+        ProcessorGeneration::Naples
+        | ProcessorGeneration::Rome
+        | ProcessorGeneration::Milan => 0x10_0000,
+        ProcessorGeneration::Genoa | ProcessorGeneration::Turin => 0x10_0000,
+    }
+}
@@ -0,0 +1,515 @@
+//! Verification of the signatures in an existing flash image (or in the
+//! blobs of a config): each signed PSP blob against the AmdPublicKey,
+//! AblPublicKey and OemPublicKey entries, each public key against the key
+//! that certified it (an OemPublicKey must not certify itself), and the
+//! reset image against its signature entry and the OemPublicKey of the same
+//! directory.
+
+use amd_efs::{
+    BhdDirectory, BhdDirectoryEntry, BhdDirectoryEntryType, PspDirectory,
+    PspDirectoryEntry, PspDirectoryEntryType,
+};
+use amd_host_image_builder_config::{
+    SerdeBhdDirectory, SerdeBhdDirectoryEntryType, SerdeBhdDirectoryVariant,
+    SerdeBhdSource, SerdeConfig, SerdePspDirectory, SerdePspDirectoryEntryType,
+    SerdePspDirectoryVariant, SerdePspEntrySource,
+};
+use std::path::{Path, PathBuf};
+
+use crate::DirectoryVisitor;
+use crate::images::FlashImage;
+use crate::psp_blob::PspBlobHeader;
+use crate::signing::{BIOS_SIGNATURE_ENTRY_TYPE, PublicKeyToken};
+
+/// What a payload is, as far as signatures are concerned.
+#[derive(Clone, Copy, PartialEq)]
+enum PayloadKind {
+    PublicKey,
+    OemPublicKey,
+    ResetImage,
+    ResetImageSignature,
+    Other,
+}
+
+struct Payload {
+    /// Name of the directory the payload is in
+    directory: String,
+    description: String,
+    kind: PayloadKind,
+    body: Vec<u8>,
+}
+
+fn psp_payload_kind(typ: SerdePspDirectoryEntryType) -> PayloadKind {
+    if typ == PspDirectoryEntryType::AmdPublicKey
+        || typ == PspDirectoryEntryType::AblPublicKey
+    {
+        PayloadKind::PublicKey
+    } else {
+        PayloadKind::Other
+    }
+}
+
+fn bhd_payload_kind(
+    typ: SerdeBhdDirectoryEntryType,
+    reset_image: bool,
+) -> PayloadKind {
+    if typ == BhdDirectoryEntryType::OemPublicKey {
+        PayloadKind::OemPublicKey
+    } else if typ == BhdDirectoryEntryType::Bios && reset_image {
+        PayloadKind::ResetImage
+    } else if typ
+        == SerdeBhdDirectoryEntryType::Unknown(BIOS_SIGNATURE_ENTRY_TYPE)
+    {
+        PayloadKind::ResetImageSignature
+    } else {
+        PayloadKind::Other
+    }
+}
+
+// Flash images
+
+/// The payloads of a flash image.
+#[derive(Default)]
+pub(crate) struct ImagePayloads {
+    payloads: Vec<Payload>,
+}
+
+impl DirectoryVisitor for ImagePayloads {
+    fn psp_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &PspDirectory,
+        entry: &PspDirectoryEntry,
+        name: &str,
+    ) {
+        let typ = SerdePspDirectoryEntryType::from(entry);
+        if let Some(body) = crate::read_payload(
+            storage,
+            directory.payload_beginning(entry),
+            entry.size(),
+        ) {
+            self.payloads.push(Payload {
+                directory: name.to_string(),
+                description: format!("{typ} (instance {})", entry.instance()),
+                kind: psp_payload_kind(typ),
+                body,
+            });
+        }
+    }
+
+    fn bhd_entry(
+        &mut self,
+        storage: &FlashImage,
+        directory: &BhdDirectory,
+        entry: &BhdDirectoryEntry,
+        name: &str,
+    ) {
+        let typ = SerdeBhdDirectoryEntryType::from(entry);
+        if let Some(body) = crate::read_payload(
+            storage,
+            directory.payload_beginning(entry),
+            entry.size(),
+        ) {
+            self.payloads.push(Payload {
+                directory: name.to_string(),
+                description: format!(
+                    "{typ} (instance {}, sub_program {})",
+                    entry.instance(),
+                    entry.sub_program()
+                ),
+                kind: bhd_payload_kind(typ, entry.reset_image()),
+                body,
+            });
+        }
+    }
+}
+
+fn image_payloads(input_filename: &Path) -> std::io::Result<Vec<Payload>> {
+    let mut result = ImagePayloads::default();
+    crate::walk_image_directories(input_filename, &mut result)?;
+    Ok(result.payloads)
+}
+
+// Configs
+
+struct ConfigPayloads<'a> {
+    blobdirs: &'a [PathBuf],
+    payloads: Vec<Payload>,
+}
+
+impl ConfigPayloads<'_> {
+    fn read(&self, blob_filename: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(crate::resolve_blob(
+            self.blobdirs,
+            blob_filename.into(),
+            false,
+        )?)
+    }
+
+    fn add_psp_directory(
+        &mut self,
+        directory: &SerdePspDirectory<'_>,
+        name: &str,
+    ) -> std::io::Result<()> {
+        for entry in directory.entries.iter() {
+            let attrs = &entry.target.attrs;
+            let description =
+                format!("{} (instance {})", attrs.type_, attrs.instance);
+            match &entry.source {
+                SerdePspEntrySource::BlobFile(blob_filename) => {
+                    let body = self.read(blob_filename)?;
+                    self.payloads.push(Payload {
+                        directory: name.to_string(),
+                        description,
+                        kind: psp_payload_kind(attrs.type_),
+                        body,
+                    });
+                }
+                SerdePspEntrySource::SecondLevelDirectory(d) => self
+                    .add_psp_directory(
+                        d,
+                        &format!("{name} > second level PSP directory"),
+                    )?,
+                SerdePspEntrySource::ImageSlotHeader(slot) => self
+                    .add_psp_directory(
+                        &slot.directory,
+                        &format!("{name} > {description}"),
+                    )?,
+                SerdePspEntrySource::BhdDirectory(d) => self
+                    .add_bhd_directory(
+                        d,
+                        &format!("{name} > level 2 BHD directory"),
+                    )?,
+                SerdePspEntrySource::Value(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_bhd_directory(
+        &mut self,
+        directory: &SerdeBhdDirectory<'_>,
+        name: &str,
+    ) -> std::io::Result<()> {
+        for entry in directory.entries.iter() {
+            let attrs = &entry.target.attrs;
+            match &entry.source {
+                SerdeBhdSource::BlobFile(blob_filename) => {
+                    let body = self.read(blob_filename)?;
+                    self.payloads.push(Payload {
+                        directory: name.to_string(),
+                        description: format!(
+                            "{} (instance {}, sub_program {})",
+                            attrs.type_, attrs.instance, attrs.sub_program
+                        ),
+                        kind: bhd_payload_kind(attrs.type_, attrs.reset_image),
+                        body,
+                    });
+                }
+                SerdeBhdSource::SecondLevelDirectory(d) => self
+                    .add_bhd_directory(
+                        d,
+                        &format!("{name} > second level BHD directory"),
+                    )?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn config_payloads(
+    efs_configuration_filename: &Path,
+    blobdirs: &[PathBuf],
+) -> std::io::Result<Vec<Payload>> {
+    let data = std::fs::read_to_string(efs_configuration_filename)?;
+    let config: SerdeConfig<'_> =
+        crate::parse_config(&data, efs_configuration_filename)?;
+    let mut result = ConfigPayloads { blobdirs, payloads: Vec::new() };
+    match &config.psp {
+        SerdePspDirectoryVariant::PspDirectory(d) => {
+            result.add_psp_directory(d, "PSP directory")?
+        }
+        _ => eprintln!("WARNING: PSP combo directories are not supported"),
+    }
+    match &config.bhd {
+        SerdeBhdDirectoryVariant::BhdDirectory(d) => {
+            result.add_bhd_directory(d, "BHD directory")?
+        }
+        _ => eprintln!("WARNING: BHD combo directories are not supported"),
+    }
+    Ok(result.payloads)
+}
+
+// Verification
+
+fn key_id_string(key_id: &[u8; 16]) -> String {
+    key_id.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+struct Verifier {
+    /// Description, kind and token of each public key
+    keys: Vec<(String, PayloadKind, PublicKeyToken)>,
+    /// Whether a reset image without signature is a problem even if there
+    /// is no OemPublicKey
+    require_signed_reset_image: bool,
+    /// Whether to print the results
+    verbose: bool,
+    problems: Vec<String>,
+}
+
+impl Verifier {
+    fn new(require_signed_reset_image: bool, verbose: bool) -> Self {
+        Self {
+            keys: Vec::new(),
+            require_signed_reset_image,
+            verbose,
+            problems: Vec::new(),
+        }
+    }
+
+    fn report(&mut self, description: &str, result: &str, problem: bool) {
+        if self.verbose {
+            println!("{description}: {result}");
+        }
+        if problem {
+            self.problems.push(format!("{description}: {result}"));
+        }
+    }
+
+    fn find_key(&self, key_id: &[u8; 16]) -> Option<&PublicKeyToken> {
+        self.keys
+            .iter()
+            .map(|(_, _, key)| key)
+            .find(|key| key.key_id == *key_id)
+    }
+
+    fn verify_keys(&mut self) {
+        for i in 0..self.keys.len() {
+            let (description, kind, key) = &self.keys[i];
+            let description = description.clone();
+            let result = match self.find_key(&key.certifying_key_id) {
+                // The PSP only accepts an OemPublicKey that AMD certified.
+                Some(_)
+                    if *kind == PayloadKind::OemPublicKey
+                        && key.certifying_key_id == key.key_id =>
+                {
+                    Err("NOT CERTIFIED: self-signed OemPublicKey".to_string())
+                }
+                None => Err(format!(
+                    "KEY MISMATCH: certifying key {} is not in the image",
+                    key_id_string(&key.certifying_key_id)
+                )),
+                Some(certifying_key)
+                    if certifying_key
+                        .verifies(&key.signed_part, &key.signature) =>
+                {
+                    Ok(if key.certifying_key_id == key.key_id {
+                        "ok (self-signed)".to_string()
+                    } else {
+                        format!(
+                            "ok (certified by {})",
+                            key_id_string(&key.certifying_key_id)
+                        )
+                    })
+                }
+                Some(_) => Err(format!(
+                    "BAD SIGNATURE by certifying key {}",
+                    key_id_string(&key.certifying_key_id)
+                )),
+            };
+            match result {
+                Ok(result) => self.report(&description, &result, false),
+                Err(result) => self.report(&description, &result, true),
+            }
+        }
+    }
+
+    /// Verifies the PSP blob BODY (if it has a header).
+    fn verify_psp_blob(&mut self, description: &str, body: &[u8]) {
+        let Some(header) = PspBlobHeader::parse(body) else {
+            return;
+        };
+        if !header.is_signed() {
+            self.report(description, "UNSIGNED", true);
+            return;
+        }
+        let key_id = header.signing_key_id();
+        let Some(key) = self.find_key(&key_id) else {
+            let result = format!(
+                "KEY MISMATCH: signing key {} is not in the image",
+                key_id_string(&key_id)
+            );
+            self.report(description, &result, true);
+            return;
+        };
+        let signed_end = header.signed_end();
+        let verified = match (
+            body.get(..signed_end),
+            body.get(signed_end..signed_end + key.key.size()),
+        ) {
+            (Some(signed_part), Some(signature)) => {
+                key.verifies(signed_part, signature)
+            }
+            _ => false,
+        };
+        let result = if verified {
+            format!("ok (signed by {})", key_id_string(&key_id))
+        } else {
+            format!("BAD SIGNATURE by key {}", key_id_string(&key_id))
+        };
+        self.report(description, &result, !verified);
+    }
+
+    /// Verifies the reset images of DIRECTORY against the signature in it,
+    /// using the OemPublicKey in it (like the PSP does).
+    fn verify_reset_images(&mut self, payloads: &[Payload], directory: &str) {
+        let in_directory = move |kind| {
+            payloads
+                .iter()
+                .filter(move |x| x.directory == directory && x.kind == kind)
+        };
+        let signatures = in_directory(PayloadKind::ResetImageSignature)
+            .map(|x| x.body.as_slice())
+            .collect::<Vec<_>>();
+        let keys = in_directory(PayloadKind::OemPublicKey)
+            .filter_map(|x| {
+                let key = PublicKeyToken::parse(&x.body)?;
+                Some((format!("{directory}: {}", x.description), key))
+            })
+            .collect::<Vec<_>>();
+        for reset_image in in_directory(PayloadKind::ResetImage) {
+            let description =
+                format!("{directory}: {}", reset_image.description);
+            if signatures.is_empty() {
+                // Without an OemPublicKey, the directory doesn't use
+                // Platform Secure Boot--so that's fine (unless required).
+                if self.require_signed_reset_image
+                    || in_directory(PayloadKind::OemPublicKey).next().is_some()
+                {
+                    self.report(&description, "UNSIGNED", true);
+                } else {
+                    self.report(
+                        &description,
+                        "unsigned (there is no OemPublicKey)",
+                        false,
+                    );
+                }
+                continue;
+            }
+            if keys.is_empty() {
+                self.report(
+                    &description,
+                    "KEY MISMATCH: there is no OemPublicKey in the directory",
+                    true,
+                );
+                continue;
+            }
+            let signer = keys
+                .iter()
+                .find(|(_, key)| {
+                    signatures.iter().any(|signature| {
+                        key.verifies(&reset_image.body, signature)
+                    })
+                })
+                .map(|(key_description, _)| key_description.clone());
+            match signer {
+                Some(key_description) => {
+                    let result = format!("ok (signed by {key_description})");
+                    self.report(&description, &result, false)
+                }
+                None => self.report(
+                    &description,
+                    "BAD SIGNATURE (by none of the OemPublicKeys in the directory)",
+                    true,
+                ),
+            }
+        }
+    }
+
+    fn verify(&mut self, payloads: &[Payload]) {
+        for payload in payloads {
+            let description =
+                format!("{}: {}", payload.directory, payload.description);
+            if matches!(
+                payload.kind,
+                PayloadKind::PublicKey | PayloadKind::OemPublicKey
+            ) {
+                match PublicKeyToken::parse(&payload.body) {
+                    Some(key) => {
+                        self.keys.push((description, payload.kind, key))
+                    }
+                    None => self.report(
+                        &description,
+                        "INVALID public key token",
+                        true,
+                    ),
+                }
+            }
+        }
+        self.verify_keys();
+        for payload in payloads {
+            if payload.kind == PayloadKind::Other {
+                let description =
+                    format!("{}: {}", payload.directory, payload.description);
+                self.verify_psp_blob(&description, &payload.body);
+            }
+        }
+        let mut directories = Vec::<&str>::new();
+        for payload in payloads {
+            if !directories.contains(&payload.directory.as_str()) {
+                directories.push(&payload.directory);
+            }
+        }
+        for directory in directories {
+            self.verify_reset_images(payloads, directory);
+        }
+    }
+}
+
+/// Verifies the signatures in the flash image INPUT_FILENAME or (if given
+/// instead) in the blobs of the config EFS_CONFIGURATION_FILENAME. Fails if
+/// there are unsigned, badly signed or key-mismatched payloads.
+pub(crate) fn verify_signatures(
+    input_filename: Option<&Path>,
+    efs_configuration_filename: Option<&Path>,
+    blobdirs: &[PathBuf],
+) -> std::io::Result<()> {
+    let (payloads, source) = match (input_filename, efs_configuration_filename)
+    {
+        (Some(input_filename), None) => {
+            (image_payloads(input_filename)?, input_filename)
+        }
+        (None, Some(efs_configuration_filename)) => (
+            config_payloads(efs_configuration_filename, blobdirs)?,
+            efs_configuration_filename,
+        ),
+        _ => {
+            return Err(std::io::Error::other(
+                "Please specify exactly one of --config and --existing-file",
+            ));
+        }
+    };
+    let mut verifier = Verifier::new(false, true);
+    verifier.verify(&payloads);
+    if verifier.problems.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{} problem(s) with signatures found in {source:?}",
+            verifier.problems.len()
+        )))
+    }
+}
+
+/// Returns the problems with the signatures of PAYLOADS (of a flash image),
+/// without printing anything. If REQUIRE_SIGNED_RESET_IMAGE, a reset image
+/// without signature is a problem, too.
+pub(crate) fn image_signature_problems(
+    payloads: &ImagePayloads,
+    require_signed_reset_image: bool,
+) -> Vec<String> {
+    let mut verifier = Verifier::new(require_signed_reset_image, false);
+    verifier.verify(&payloads.payloads);
+    verifier.problems
+}
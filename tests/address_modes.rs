@@ -0,0 +1,134 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DIRECTORY_HEADER_SIZE: usize = 16;
+const PSP_DIRECTORY_ENTRY_SIZE: usize = 16;
+const ADDRESS_MODE_SHIFT: u32 = 62;
+const DIRECTORY_RELATIVE_OFFSET: u64 = 2;
+const OTHER_DIRECTORY_RELATIVE_OFFSET: u64 = 3;
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Fletcher-32 over the little-endian 16-bit words in DATA, like the PSP
+/// checks directories.
+fn fletcher32(data: &[u8]) -> u32 {
+    let mut c0: u32 = 0xffff;
+    let mut c1: u32 = 0xffff;
+    for block in data.chunks(2 * 359) {
+        for word in block.chunks_exact(2) {
+            c0 += u32::from(u16::from_le_bytes([word[0], word[1]]));
+            c1 += c0;
+        }
+        c0 = (c0 & 0xffff) + (c0 >> 16);
+        c1 = (c1 & 0xffff) + (c1 >> 16);
+    }
+    c0 = (c0 & 0xffff) + (c0 >> 16);
+    c1 = (c1 & 0xffff) + (c1 >> 16);
+    (c1 << 16) | c0
+}
+
+/// Rewrites the second-level PSP directory in IMAGE so that the directory
+/// says that its entries have their own address modes, and so that all its
+/// entries use address mode 3 (relative to the directory).
+fn use_address_mode_3(image: &mut [u8]) {
+    let beginning = image
+        .windows(4)
+        .position(|x| x == b"$PL2")
+        .expect("no second-level PSP directory");
+    let total_entries = u32_at(image, beginning + 8) as usize;
+    assert!(total_entries > 0);
+    let mut additional_info = u32_at(image, beginning + 12);
+    additional_info &= !(3 << 29);
+    additional_info |= (DIRECTORY_RELATIVE_OFFSET as u32) << 29;
+    image[beginning + 12..beginning + 16]
+        .copy_from_slice(&additional_info.to_le_bytes());
+    for i in 0..total_entries {
+        let location_offset = beginning
+            + DIRECTORY_HEADER_SIZE
+            + i * PSP_DIRECTORY_ENTRY_SIZE
+            + 8;
+        // The entries are EFS relative, and the EFS is at the beginning of
+        // the image.
+        let location =
+            u64_at(image, location_offset) & ((1 << ADDRESS_MODE_SHIFT) - 1);
+        let offset = location - beginning as u64;
+        let location =
+            (OTHER_DIRECTORY_RELATIVE_OFFSET << ADDRESS_MODE_SHIFT) | offset;
+        image[location_offset..location_offset + 8]
+            .copy_from_slice(&location.to_le_bytes());
+    }
+    let end = beginning
+        + DIRECTORY_HEADER_SIZE
+        + total_entries * PSP_DIRECTORY_ENTRY_SIZE;
+    let checksum = fletcher32(&image[beginning + 8..end]);
+    image[beginning + 4..beginning + 8]
+        .copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Returns the files in DIRNAME (recursively).
+fn files(dirname: &Path) -> Vec<PathBuf> {
+    let mut result = Vec::new();
+    for entry in fs::read_dir(dirname).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            result.append(&mut files(&path));
+        } else {
+            result.push(path);
+        }
+    }
+    result
+}
+
+#[test]
+fn test_dump_second_level_address_mode_3() {
+    let work_dirname = common::work_dirname("address-modes");
+    let image_filename = work_dirname.join("image.img");
+    let output = common::generate(
+        &data_filename("address-modes", "Milan.efs.json5"),
+        &image_filename,
+    )
+    .arg("-B")
+    .arg(data_dirname("address-modes"))
+    .output()
+    .unwrap();
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut image = fs::read(&image_filename).unwrap();
+    use_address_mode_3(&mut image);
+    fs::write(&image_filename, &image).unwrap();
+
+    let dump_dirname = work_dirname.join("dump");
+    let output = common::command()
+        .arg("dump")
+        .arg("-i")
+        .arg(&image_filename)
+        .arg("-b")
+        .arg(&dump_dirname)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "dump failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let expected =
+        fs::read(data_filename("address-modes", "second-level.blob")).unwrap();
+    assert!(
+        files(&dump_dirname)
+            .iter()
+            .any(|path| fs::read(path).unwrap() == expected),
+        "the payload of the second-level entry was not dumped correctly"
+    );
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::data_filename;
+
+#[test]
+fn test_configured_apob() {
+    let work_dirname = common::work_dirname("apob");
+    let configuration_filename = common::edited_configuration(
+        &work_dirname,
+        "round-trip",
+        "Milan.efs.json5",
+        &[(
+            "processor_generation: \"Milan\",",
+            "processor_generation: \"Milan\",\n    apob: { ram_destination_address: 0x5000000, size: 0x20000 },",
+        )],
+    );
+    let output_filename = work_dirname.join("image.img");
+    let output = common::generate(&configuration_filename, &output_filename)
+        .arg("-v")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains(
+            "BHD directory: Apob (instance 0, sub_program 0): RAM 0x5000000..0x5020000"
+        ),
+        "{stdout}"
+    );
+
+    let output = common::command()
+        .arg("dump")
+        .arg("-i")
+        .arg(&output_filename)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // dump never writes the apob setting, only the Apob entries.
+    assert!(!stdout.contains("apob:"), "{stdout}");
+    let apob = &stdout[stdout.find("\"Apob\"").expect("no Apob in dump")..];
+    assert!(apob.contains("ram_destination_address: 0x5000000"), "{stdout}");
+}
+
+#[test]
+fn test_generate_refuses_different_apobs_with_reset_image() {
+    let output_filename =
+        common::work_dirname("apob-second-level").join("image.img");
+    let output = common::generate(
+        &data_filename("apob", "SecondLevel.efs.json5"),
+        &output_filename,
+    )
+    .arg("-r")
+    .arg(data_filename("check-boot", "reset.bin"))
+    .output()
+    .unwrap();
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("but 0x5000000 and 0x6000000 were requested"),
+        "{stderr}"
+    );
+}
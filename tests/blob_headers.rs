@@ -0,0 +1,112 @@
+mod common;
+
+use common::data_filename;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+fn work_dirname(name: &str) -> PathBuf {
+    common::work_dirname(&format!("blob-headers-{name}"))
+}
+
+/// Returns a PSP blob with CONTENTS_SIZE Bytes of contents (of which only
+/// the first CONTENTS_LEN are there) and the header fields FIRMWARE_TYPE,
+/// SIGNED and COMPRESSED.
+fn blob(
+    contents_size: u32,
+    contents_len: usize,
+    firmware_type: u8,
+    signed: bool,
+    compressed: bool,
+) -> Vec<u8> {
+    let mut result = vec![0u8; 0x100 + contents_len];
+    result[0x10..0x14].copy_from_slice(b"$PS1");
+    result[0x14..0x18].copy_from_slice(&contents_size.to_le_bytes());
+    result[0x30..0x34].copy_from_slice(&u32::from(signed).to_le_bytes());
+    result[0x48..0x4c].copy_from_slice(&u32::from(compressed).to_le_bytes());
+    result[0x5c] = firmware_type;
+    result
+}
+
+fn generate(work_dirname: &Path, bootloader: &[u8], pmu: &[u8]) -> Output {
+    fs::write(work_dirname.join("bootloader.sbin"), bootloader).unwrap();
+    fs::write(work_dirname.join("pmu.sbin"), pmu).unwrap();
+    common::generate(
+        &data_filename("blob-headers", "Milan.efs.json5"),
+        &work_dirname.join("image.img"),
+    )
+    .arg("-B")
+    .arg(work_dirname)
+    .output()
+    .unwrap()
+}
+
+#[test]
+fn test_generate_accepts_matching_blobs() {
+    let work_dirname = work_dirname("matching");
+    let output = generate(
+        &work_dirname,
+        &blob(0x10, 0x10, 0x01, false, false),
+        &blob(0x10, 0x10, 0, true, false),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "generate failed: {stderr}");
+}
+
+#[test]
+fn test_generate_does_not_compare_firmware_type_of_bhd_blobs() {
+    let work_dirname = work_dirname("bhd-firmware-type");
+    let output = generate(
+        &work_dirname,
+        &blob(0x10, 0x10, 0x01, false, false),
+        &blob(0x10, 0x10, 0x01, true, false),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "generate failed: {stderr}");
+}
+
+#[test]
+fn test_generate_rejects_swapped_blob() {
+    let work_dirname = work_dirname("swapped");
+    let output = generate(
+        &work_dirname,
+        &blob(0x10, 0x10, 0x08, false, false),
+        &blob(0x10, 0x10, 0, false, false),
+    );
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Blob header says it is firmware of type 0x8, but the entry has type 0x1"),
+        "{stderr}"
+    );
+    assert!(!work_dirname.join("image.img").exists());
+}
+
+#[test]
+fn test_generate_rejects_truncated_blob() {
+    let work_dirname = work_dirname("truncated");
+    let output = generate(
+        &work_dirname,
+        &blob(0x10, 0x8, 0x01, false, false),
+        &blob(0x10, 0x10, 0, false, false),
+    );
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Blob is truncated"), "{stderr}");
+}
+
+#[test]
+fn test_generate_rejects_compression_mismatch() {
+    let work_dirname = work_dirname("compressed");
+    let output = generate(
+        &work_dirname,
+        &blob(0x10, 0x10, 0x01, false, false),
+        &blob(0x10, 0x10, 0, true, true),
+    );
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Entry has compressed: false, but the blob header says the blob is compressed"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// Generates an image from CONFIGURATION_FILENAME into a new work directory
+/// NAME and returns the name of the image.
+fn generate(name: &str, configuration_filename: &Path) -> PathBuf {
+    let output_filename =
+        common::work_dirname(&format!("check-boot-{name}")).join("image.img");
+    let output = common::generate(configuration_filename, &output_filename)
+        .arg("-B")
+        .arg(data_dirname("check-boot"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output_filename
+}
+
+fn check_boot(image_filename: &Path) -> Output {
+    common::command()
+        .arg("check-boot")
+        .arg("-i")
+        .arg(image_filename)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_check_boot_bootable_image() {
+    let image_filename =
+        generate("bootable", &data_filename("check-boot", "Milan.efs.json5"));
+    let output = check_boot(&image_filename);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "check-boot failed: {stdout}");
+    assert!(stdout.contains("looks bootable on Milan"), "{stdout}");
+}
+
+#[test]
+fn test_check_boot_missing_entries() {
+    let image_filename =
+        generate("missing", &data_filename("round-trip", "Milan.efs.json5"));
+    let output = check_boot(&image_filename);
+    assert!(!output.status.success(), "check-boot passed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "PspBootloader entry is missing (the PSP of Milan needs it)"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "Apcb or ApcbBackup entry is missing (the PSP of Milan needs it)"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "does not contain the reset vector at the top of a 64 KiB segment"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_check_boot_directory_checksum() {
+    let image_filename =
+        generate("checksum", &data_filename("check-boot", "Milan.efs.json5"));
+    let mut image = fs::read(&image_filename).unwrap();
+    let beginning =
+        image.windows(4).position(|x| x == b"$PSP").expect("PSP directory");
+    // sub_program of the first entry
+    image[beginning + 0x11] ^= 1;
+    fs::write(&image_filename, image).unwrap();
+    let output = check_boot(&image_filename);
+    assert!(!output.status.success(), "check-boot passed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // amd-efs might already refuse to load the directory.
+    assert!(stdout.contains("PSP directory"), "{stdout}");
+}
+
+#[test]
+fn test_check_boot_no_efh() {
+    let image_filename =
+        common::work_dirname("check-boot-no-efh").join("image.img");
+    fs::write(&image_filename, vec![0xffu8; 0x100_0000]).unwrap();
+    let output = check_boot(&image_filename);
+    assert!(!output.status.success(), "check-boot passed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("No EFH found, so the processor generation is unknown"),
+        "{stdout}"
+    );
+}
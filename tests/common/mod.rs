@@ -0,0 +1,72 @@
+//! Helpers that the integration tests share.
+
+// Each test crate only uses some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Returns the (new, empty) work directory NAME of a test.
+pub fn work_dirname(name: &str) -> PathBuf {
+    let work_dirname = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&work_dirname);
+    fs::create_dir_all(&work_dirname).unwrap();
+    work_dirname
+}
+
+/// Returns the name of the directory tests/data/TOPIC.
+pub fn data_dirname(topic: &str) -> PathBuf {
+    Path::new("tests").join("data").join(topic)
+}
+
+/// Returns the name of the file FILENAME in tests/data/TOPIC.
+pub fn data_filename(topic: &str, filename: &str) -> PathBuf {
+    data_dirname(topic).join(filename)
+}
+
+/// Returns a command that runs amd-host-image-builder.
+pub fn command() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_amd-host-image-builder"))
+}
+
+/// Returns a command that generates a 16 MiB image OUTPUT_FILENAME from
+/// CONFIGURATION_FILENAME, with the blobs in tests/data/test (more blob
+/// directories and other arguments can be added).
+pub fn generate(
+    configuration_filename: &Path,
+    output_filename: &Path,
+) -> Command {
+    let mut command = command();
+    command
+        .arg("generate")
+        .arg("-s")
+        .arg("16 MiB")
+        .arg("-c")
+        .arg(configuration_filename)
+        .arg("-B")
+        .arg(data_dirname("test"))
+        .arg("-o")
+        .arg(output_filename);
+    command
+}
+
+/// Writes the config tests/data/TOPIC/FILENAME to WORK_DIRNAME, with each
+/// (old, new) of REPLACEMENTS applied to its text, and returns the name of
+/// the copy.  That way, tests share the configs that only differ in
+/// details.
+pub fn edited_configuration(
+    work_dirname: &Path,
+    topic: &str,
+    filename: &str,
+    replacements: &[(&str, &str)],
+) -> PathBuf {
+    let mut text = fs::read_to_string(data_filename(topic, filename)).unwrap();
+    for (old, new) in replacements {
+        assert!(text.contains(old), "{old:?} not in {topic}/{filename}");
+        text = text.replacen(old, new, 1);
+    }
+    let result = work_dirname.join(filename);
+    fs::write(&result, text).unwrap();
+    result
+}
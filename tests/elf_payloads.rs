@@ -0,0 +1,84 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+use std::fs;
+
+#[test]
+fn test_elf_file_segments_become_copy_entries() {
+    let work_dirname = common::work_dirname("elf-payloads");
+    let output_filename = work_dirname.join("image.img");
+    let output = common::generate(
+        &data_filename("elf-payloads", "Milan.efs.json5"),
+        &output_filename,
+    )
+    .arg("-B")
+    .arg(data_dirname("elf-payloads"))
+    .arg("-v")
+    .output()
+    .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // The .bss of the first segment is not stored.
+    assert!(
+        stdout.contains(
+            "BHD directory: Bios (instance 1, sub_program 0): RAM 0x1000000..0x1000020"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "BHD directory: Bios (instance 2, sub_program 0): RAM 0x2000000..0x2000010"
+        ),
+        "{stdout}"
+    );
+
+    let dump_dirname = work_dirname.join("dump");
+    let output = common::command()
+        .arg("dump")
+        .arg("-i")
+        .arg(&output_filename)
+        .arg("-b")
+        .arg(&dump_dirname)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let config =
+        fs::read_to_string(dump_dirname.join("config.efs.json5")).unwrap();
+    assert!(config.contains("ram_destination_address: 0x2000000"), "{config}");
+}
+
+#[test]
+fn test_elf_file_segments_must_not_be_other_entries() {
+    let work_dirname = common::work_dirname("elf-payloads-collision");
+    let elf_entry = "                {\n                    source: {\n                        ElfFile: \"payload.elf\"";
+    let configuration_filename = common::edited_configuration(
+        &work_dirname,
+        "elf-payloads",
+        "Milan.efs.json5",
+        &[(
+            elf_entry,
+            &format!(
+                "{{ source: {{ BlobFile: \"test.blob\" }}, target: {{ type: \"Bios\", instance: 2, copy_image: true, ram_destination_address: 0x3000000 }} }},\n{elf_entry}"
+            ),
+        )],
+    );
+    let output = common::generate(
+        &configuration_filename,
+        &work_dirname.join("image.img"),
+    )
+    .arg("-B")
+    .arg(data_dirname("elf-payloads"))
+    .output()
+    .unwrap();
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Segment 1 of")
+            && stderr.contains("would be Bios (instance 2, sub_program 0"),
+        "{stderr}"
+    );
+}
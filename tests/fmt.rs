@@ -0,0 +1,68 @@
+mod common;
+
+use std::path::Path;
+
+fn fmt(configuration_filename: &Path, check: bool) -> std::process::Output {
+    let mut command = common::command();
+    command.arg("fmt");
+    if check {
+        command.arg("--check");
+    }
+    command.arg(configuration_filename).output().unwrap()
+}
+
+#[test]
+fn test_fmt() {
+    let configuration_filename = common::edited_configuration(
+        &common::work_dirname("fmt"),
+        "round-trip",
+        "Milan.efs.json5",
+        &[],
+    );
+
+    let output = fmt(&configuration_filename, true);
+    assert!(!output.status.success(), "not canonical, but check passed");
+
+    let output = fmt(&configuration_filename, false);
+    assert!(
+        output.status.success(),
+        "fmt failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let canonical = std::fs::read_to_string(&configuration_filename).unwrap();
+
+    let output = fmt(&configuration_filename, true);
+    assert!(
+        output.status.success(),
+        "canonical, but check failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = fmt(&configuration_filename, false);
+    assert!(output.status.success());
+    assert_eq!(
+        std::fs::read_to_string(&configuration_filename).unwrap(),
+        canonical
+    );
+}
+
+#[test]
+fn test_fmt_refuses_comments() {
+    let configuration_filename = common::edited_configuration(
+        &common::work_dirname("fmt-commented"),
+        "round-trip",
+        "Milan.efs.json5",
+        &[("{\n", "{\n    // A comment\n")],
+    );
+    let original = std::fs::read_to_string(&configuration_filename).unwrap();
+
+    for check in [false, true] {
+        let output = fmt(&configuration_filename, check);
+        assert!(!output.status.success(), "commented config was accepted");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("has comments"), "{stderr}");
+    }
+    assert_eq!(
+        std::fs::read_to_string(&configuration_filename).unwrap(),
+        original
+    );
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use common::data_filename;
+
+#[test]
+fn test_generate_reports_level_2_bhd_directory_without_reset_image() {
+    let configuration_filename =
+        data_filename("image-slots", "Turin.efs.json5");
+    let output = common::generate(
+        &configuration_filename,
+        &common::work_dirname("image-slots").join("image.img"),
+    )
+    .output()
+    .unwrap();
+    assert!(
+        !output.status.success(),
+        "generate of {configuration_filename:?} passed"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "generate panicked: {stderr}");
+    for expected in [
+        "Without a Bios Reset entry in the level 2 BHD directory".to_string(),
+        format!("--> {}:37:37", configuration_filename.display()),
+    ] {
+        assert!(stderr.contains(&expected), "{expected:?} not in {stderr}");
+    }
+}
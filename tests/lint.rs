@@ -0,0 +1,92 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+use std::path::Path;
+use std::process::Output;
+
+/// Runs `lint` on CONFIGURATION_FILENAME, with the blobs in tests/data/test.
+fn lint(configuration_filename: &Path) -> Output {
+    common::command()
+        .arg("lint")
+        .arg("-c")
+        .arg(configuration_filename)
+        .arg("-B")
+        .arg(data_dirname("test"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_lint_reports_all_findings() {
+    let configuration_filename = data_filename("lint", "Milan.efs.json5");
+    let output = lint(&configuration_filename);
+    assert!(
+        !output.status.success(),
+        "lint of {configuration_filename:?} passed"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        "duplicate entry",
+        "flash_location 0x20800 is not aligned",
+        "flash ranges overlap (0x20800..0x21000)",
+        "Implied source is only supported for Apob",
+        "Missing mandatory PSP entry PspBootloader",
+        "Missing mandatory BHD entry PmuFirmwareData",
+        "Missing mandatory BHD entry Apcb or ApcbBackup",
+        "Missing Bios entry with reset_image",
+    ] {
+        assert!(stderr.contains(expected), "{expected:?} not in {stderr}");
+    }
+}
+
+#[test]
+fn test_lint_points_at_entries() {
+    let configuration_filename = data_filename("lint", "Milan.efs.json5");
+    let output = lint(&configuration_filename);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        format!("--> {}:21:17", configuration_filename.display()),
+        format!("--> {}:37:17", configuration_filename.display()),
+        "40 |                         type: \"PmuFirmwareInstructions\","
+            .to_string(),
+    ] {
+        assert!(stderr.contains(&expected), "{expected:?} not in {stderr}");
+    }
+}
+
+#[test]
+fn test_lint_soft_fuse_chains() {
+    let configuration_filename = data_filename("lint", "Genoa.efs.json5");
+    let output = lint(&configuration_filename);
+    assert!(
+        !output.status.success(),
+        "lint of {configuration_filename:?} passed"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        "soft fuse spi_decoding does not exist on Genoa",
+        "soft fuse chain is given as the number 0x1; write it as { PspSoftFuseChain: {",
+    ] {
+        assert!(stderr.contains(expected), "{expected:?} not in {stderr}");
+    }
+}
+
+#[test]
+fn test_lint_points_at_json5_errors() {
+    let configuration_filename =
+        common::work_dirname("lint-json5-error").join("config.efs.json5");
+    std::fs::write(
+        &configuration_filename,
+        "{\n    processor_generation: \"Milano\"\n}\n",
+    )
+    .unwrap();
+    let output = lint(&configuration_filename);
+    assert!(!output.status.success(), "lint passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for expected in [
+        format!("--> {}:2:", configuration_filename.display()),
+        "2 |     processor_generation: \"Milano\"".to_string(),
+    ] {
+        assert!(stderr.contains(&expected), "{expected:?} not in {stderr}");
+    }
+}
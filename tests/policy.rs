@@ -0,0 +1,105 @@
+mod common;
+
+use common::data_filename;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+fn work_dirname(name: &str) -> PathBuf {
+    common::work_dirname(&format!("policy-{name}"))
+}
+
+fn generate(
+    configuration_filename: &Path,
+    output_filename: &Path,
+    extra_args: &[&str],
+) -> Output {
+    common::generate(configuration_filename, output_filename)
+        .args(extra_args)
+        .output()
+        .unwrap()
+}
+
+/// Generates an image (without policy) that has a raw, unsigned reset image
+/// and a soft fuse chain that allows debug unlock.
+fn generate_development_image(name: &str) -> PathBuf {
+    let work_dirname = work_dirname(name);
+    let reset_image_filename = work_dirname.join("reset.bin");
+    fs::write(&reset_image_filename, [0x90u8; 0x1000]).unwrap();
+    let output_filename = work_dirname.join("development.img");
+    let output = generate(
+        &data_filename("signing", "Milan.efs.json5"),
+        &output_filename,
+        &["-r", reset_image_filename.to_str().unwrap()],
+    );
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output_filename
+}
+
+fn verify(image_filename: &Path, policy: &str) -> Output {
+    common::command()
+        .arg("verify")
+        .arg("-i")
+        .arg(image_filename)
+        .arg("--policy")
+        .arg(policy)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_verify_development_image() {
+    let image_filename = generate_development_image("verify");
+    let output = verify(&image_filename, "development");
+    assert!(
+        output.status.success(),
+        "verify --policy development failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = verify(&image_filename, "production");
+    assert!(!output.status.success(), "verify --policy production passed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for expected in [
+        "PspSoftFuseChain (instance 0): policy Production requires secure_debug_unlock to be false",
+        "Bios (instance 0, sub_program 0): UNSIGNED",
+    ] {
+        assert!(stdout.contains(expected), "{expected:?} not in {stdout}");
+    }
+}
+
+#[test]
+fn test_generate_enforces_config_policy() {
+    let work_dirname = work_dirname("config");
+    let configuration_filename = common::edited_configuration(
+        &work_dirname,
+        "signing",
+        "Milan.efs.json5",
+        &[(
+            "processor_generation: \"Milan\",",
+            "processor_generation: \"Milan\",\n    policy: { profile: \"Production\" },",
+        )],
+    );
+    let output_filename = work_dirname.join("production.img");
+    let output = generate(&configuration_filename, &output_filename, &[]);
+    assert!(!output.status.success(), "generate passed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("requires secure_debug_unlock to be false"),
+        "{stdout}"
+    );
+    assert!(!output_filename.exists());
+
+    let output = generate(
+        &configuration_filename,
+        &output_filename,
+        &["--policy", "development"],
+    );
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("contradicts the policy Production"), "{stderr}");
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::data_filename;
+use std::path::Path;
+use std::process::Output;
+
+fn generate(configuration_filename: &Path, output_filename: &Path) -> Output {
+    common::generate(configuration_filename, output_filename)
+        .arg("-v")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_generate_reports_ram_destinations() {
+    let output_filename =
+        common::work_dirname("ram-layout-ok").join("image.img");
+    let output = generate(
+        &data_filename("round-trip", "Milan.efs.json5"),
+        &output_filename,
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "generate failed: {stderr}");
+    assert!(
+        stdout.contains(
+            "BHD directory: Bios (instance 0, sub_program 0): RAM 0x76000000..0x7600000c"
+        ),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(
+            "BHD directory: Apob (instance 0, sub_program 0): RAM 0x4000000..0x4100000"
+        ),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_generate_rejects_overlapping_ram_destinations() {
+    let work_dirname = common::work_dirname("ram-layout-overlap");
+    // The reset image overlaps the APOB (at 0x4000000..0x4100000).
+    let configuration_filename = common::edited_configuration(
+        &work_dirname,
+        "round-trip",
+        "Milan.efs.json5",
+        &[(
+            "ram_destination_address: 0x76000000",
+            "ram_destination_address: 0x4080000",
+        )],
+    );
+    let output_filename = work_dirname.join("image.img");
+    let output = generate(&configuration_filename, &output_filename);
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("overlaps"), "{stderr}");
+    assert!(stderr.contains("1 overlap(s) of RAM destinations"), "{stderr}");
+    assert!(!output_filename.exists());
+}
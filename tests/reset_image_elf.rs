@@ -0,0 +1,135 @@
+mod common;
+
+use common::data_filename;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// Generates an image from CONFIGURATION_FILENAME with a reset image that
+/// has a long run of zeros in the middle into a new work directory NAME.
+/// Returns the work directory and the name of the image file.
+fn generate(name: &str, configuration_filename: &Path) -> (PathBuf, PathBuf) {
+    let work_dirname = common::work_dirname(&format!("reset-image-elf-{name}"));
+    let mut reset_image = vec![0u8; 0x2020];
+    reset_image[..0x10].fill(0x90);
+    reset_image[0x2010..].fill(0xf4);
+    let reset_image_filename = work_dirname.join("reset.bin");
+    fs::write(&reset_image_filename, &reset_image).unwrap();
+    let output_filename = work_dirname.join("image.img");
+    let output = common::generate(configuration_filename, &output_filename)
+        .arg("-r")
+        .arg(&reset_image_filename)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (work_dirname, output_filename)
+}
+
+fn dump(image_filename: &Path, args: &[&Path]) -> Output {
+    common::command()
+        .arg("dump")
+        .arg("-i")
+        .arg(image_filename)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// Checks that the reset image ELF file ELF_FILENAME has the reset image of
+/// generate in it.
+fn check_reset_image_elf(elf_filename: &Path) {
+    let elf = fs::read(elf_filename).unwrap();
+    assert_eq!(&elf[..4], b"\x7fELF");
+    // e_entry is the reset vector
+    assert_eq!(u64_at(&elf, 24), 0x7fff_fff0);
+    // The zeros in the middle are a hole between two segments.
+    let phoff = u64_at(&elf, 32) as usize;
+    assert_eq!(u16_at(&elf, 56), 2);
+    let segments = (0..2)
+        .map(|i| {
+            let header = &elf[phoff + i * 56..];
+            // p_paddr, p_filesz
+            (u64_at(header, 24), u64_at(header, 32))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(segments, vec![(0x7fff_dfe0, 0x10), (0x7fff_fff0, 0x10)]);
+}
+
+#[test]
+fn test_dump_reset_image_elf() {
+    let (work_dirname, image_filename) =
+        generate("elf", &data_filename("signing", "Milan.efs.json5"));
+    let elf_filename = work_dirname.join("reset.elf");
+    let output =
+        dump(&image_filename, &[Path::new("--reset-image-elf"), &elf_filename]);
+    assert!(
+        output.status.success(),
+        "dump failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    check_reset_image_elf(&elf_filename);
+}
+
+#[test]
+fn test_dump_reset_image_elf_from_image_slots() {
+    // The first reset image is the one in the level 2 BHD directory of the
+    // first image slot.
+    let (work_dirname, image_filename) = generate(
+        "image-slots",
+        &data_filename("signing", "TurinImageSlots.efs.json5"),
+    );
+    let elf_filename = work_dirname.join("reset.elf");
+    let output =
+        dump(&image_filename, &[Path::new("--reset-image-elf"), &elf_filename]);
+    assert!(
+        output.status.success(),
+        "dump failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    check_reset_image_elf(&elf_filename);
+
+    let output = dump(
+        &image_filename,
+        &[Path::new("-r"), &work_dirname.join("reset.bin")],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "dump failed: {stderr}");
+    assert!(stderr.contains("matches"), "{stderr}");
+}
+
+#[test]
+fn test_dump_compares_reset_image() {
+    let (work_dirname, image_filename) =
+        generate("compare", &data_filename("signing", "Milan.efs.json5"));
+    let output = dump(
+        &image_filename,
+        &[Path::new("-r"), &work_dirname.join("reset.bin")],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "dump failed: {stderr}");
+    assert!(stderr.contains("matches"), "{stderr}");
+
+    let mut other_reset_image =
+        fs::read(work_dirname.join("reset.bin")).unwrap();
+    other_reset_image[0x2018] = 0xcc;
+    let other_reset_image_filename = work_dirname.join("other.bin");
+    fs::write(&other_reset_image_filename, &other_reset_image).unwrap();
+    let output =
+        dump(&image_filename, &[Path::new("-r"), &other_reset_image_filename]);
+    assert!(!output.status.success(), "dump passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("differs from"), "{stderr}");
+    assert!(stderr.contains("at address 0x7ffffff8"), "{stderr}");
+}
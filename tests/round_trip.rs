@@ -0,0 +1,104 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+
+/// Generates an image of size SIZE from
+/// tests/data/round-trip/GENERATION.efs.json5, dumps it (with
+/// content-addressed blobs if CONTENT_ADDRESSED is set), regenerates it from
+/// the dump and checks that the result is byte-identical.
+fn round_trip_with_options(
+    generation: &str,
+    size: &str,
+    content_addressed: bool,
+) {
+    let configuration_filename =
+        data_filename("round-trip", &format!("{generation}.efs.json5"));
+    let work_dirname = common::work_dirname(&format!(
+        "round-trip-{generation}-{}{}",
+        size.replace(' ', ""),
+        if content_addressed { "-content-addressed" } else { "" }
+    ));
+    let mut command = common::command();
+    command.arg("round-trip");
+    if content_addressed {
+        command.arg("--content-addressed");
+    }
+    let output = command
+        .arg("-s")
+        .arg(size)
+        .arg("-c")
+        .arg(&configuration_filename)
+        .arg("-B")
+        .arg(data_dirname("test"))
+        .arg("-w")
+        .arg(&work_dirname)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "round trip of {configuration_filename:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn round_trip_with_size(generation: &str, size: &str) {
+    round_trip_with_options(generation, size, false);
+}
+
+fn round_trip(generation: &str) {
+    round_trip_with_size(generation, "16 MiB");
+}
+
+#[test]
+fn test_round_trip_naples() {
+    round_trip("Naples");
+}
+
+#[test]
+fn test_round_trip_rome() {
+    round_trip("Rome");
+}
+
+#[test]
+fn test_round_trip_milan() {
+    round_trip("Milan");
+}
+
+/// Entries of types that amd-efs doesn't know survive dump and generate.
+#[test]
+fn test_round_trip_milan_unknown_entries() {
+    round_trip("MilanUnknownEntries");
+}
+
+/// Entries that differ only in rom_id get different dump files.
+#[test]
+fn test_round_trip_milan_rom_ids() {
+    round_trip("MilanRomIds");
+}
+
+/// Most entries have the same payload (test.blob), so it's stored once.
+#[test]
+fn test_round_trip_milan_rom_ids_content_addressed() {
+    round_trip_with_options("MilanRomIds", "16 MiB", true);
+}
+
+#[test]
+fn test_round_trip_genoa() {
+    round_trip("Genoa");
+}
+
+#[test]
+fn test_round_trip_turin() {
+    round_trip("Turin");
+}
+
+#[test]
+fn test_round_trip_turin_image_slots() {
+    round_trip("TurinImageSlots");
+}
+
+/// Only the first 16 MiB of bigger images are in the physical MMIO window.
+#[test]
+fn test_round_trip_turin_32_mib() {
+    round_trip_with_size("Turin", "32 MiB");
+}
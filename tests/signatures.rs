@@ -0,0 +1,179 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// Runs `generate` with the config tests/data/signing/CONFIGURATION and a
+/// reset image that is signed by the test key, with the public key token
+/// TOKEN_FILENAME.  Returns the reset image, the name of the image file and
+/// the output of `generate`.
+fn generate_signed(
+    name: &str,
+    configuration: &str,
+    token_filename: &Path,
+) -> (Vec<u8>, PathBuf, Output) {
+    let work_dirname = common::work_dirname(&format!("signatures-{name}"));
+    let reset_image =
+        (0..0x1000u32).map(|i| (i * 7 + 3) as u8).collect::<Vec<_>>();
+    let reset_image_filename = work_dirname.join("reset.bin");
+    fs::write(&reset_image_filename, &reset_image).unwrap();
+    let output_filename = work_dirname.join("signed.img");
+    let output = common::generate(
+        &data_filename("signing", configuration),
+        &output_filename,
+    )
+    .arg("-r")
+    .arg(&reset_image_filename)
+    .arg("--oem-signing-key")
+    .arg(data_filename("test", "oem-signing-key.pem"))
+    .arg("--oem-public-key-token")
+    .arg(token_filename)
+    .output()
+    .unwrap();
+    (reset_image, output_filename, output)
+}
+
+/// Generates an image with a reset image that is signed by the test key.
+/// Returns the reset image and the name of the image file.
+fn generate_signed_image(name: &str) -> (Vec<u8>, PathBuf) {
+    let (reset_image, output_filename, output) = generate_signed(
+        name,
+        "Milan.efs.json5",
+        &data_filename("test", "oem-public-key-token.bin"),
+    );
+    assert!(
+        output.status.success(),
+        "generate failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (reset_image, output_filename)
+}
+
+fn verify_signatures(image_filename: &Path) -> Output {
+    common::command()
+        .arg("verify-signatures")
+        .arg("-i")
+        .arg(image_filename)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_verify_signed_image() {
+    let (_, image_filename) = generate_signed_image("signed");
+    let output = verify_signatures(&image_filename);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "verify-signatures failed: {stdout}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for expected in [
+        "PSP directory: AmdPublicKey (instance 0): ok (self-signed)",
+        "BHD directory: OemPublicKey (instance 0, sub_program 0): ok (certified by 03808608917af08dd97d9d6e75169269)",
+        "BHD directory: Bios (instance 0, sub_program 0): ok (signed by BHD directory: OemPublicKey",
+    ] {
+        assert!(stdout.contains(expected), "{expected:?} not in {stdout}");
+    }
+}
+
+#[test]
+fn test_verify_tampered_reset_image() {
+    let (reset_image, image_filename) = generate_signed_image("tampered");
+    let mut image = fs::read(&image_filename).unwrap();
+    let position = image
+        .windows(reset_image.len())
+        .position(|x| x == reset_image)
+        .expect("reset image in image");
+    image[position + 0x10] ^= 1;
+    fs::write(&image_filename, &image).unwrap();
+    let output = verify_signatures(&image_filename);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "verify-signatures passed: {stdout}");
+    assert!(stdout.contains("Bios (instance 0, sub_program 0): BAD SIGNATURE"));
+}
+
+#[test]
+fn test_generate_signed_image_entries() {
+    let (_, image_filename) = generate_signed_image("entries");
+    let dump_dirname = image_filename.with_file_name("dump");
+    let output = common::command()
+        .arg("dump")
+        .arg("-i")
+        .arg(&image_filename)
+        .arg("-b")
+        .arg(&dump_dirname)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "dump failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bhd_dirname = dump_dirname.join("bhd-default");
+    // A public key token of a 2048 bit key, and a signature by that key.
+    let token = fs::read(bhd_dirname.join("OemPublicKey-i00-s00.bin")).unwrap();
+    assert_eq!(token.len(), 0x40 + 3 * 256);
+    assert_eq!(&token[0x38..0x40], &[0, 8, 0, 0, 0, 8, 0, 0]);
+    let signature = fs::read(bhd_dirname.join("0x07-i00-s00.bin")).unwrap();
+    assert_eq!(signature.len(), 256);
+}
+
+#[test]
+fn test_generate_refuses_oem_public_key_in_level_2_directory() {
+    let (_, _, output) = generate_signed(
+        "level-2-key",
+        "TurinImageSlots.efs.json5",
+        &data_filename("test", "oem-public-key-token.bin"),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "generate passed: {stderr}");
+    assert!(!stderr.contains("panicked"), "generate panicked: {stderr}");
+    assert!(
+        stderr.contains("already has an OemPublicKey entry"),
+        "unexpected error: {stderr}"
+    );
+}
+
+#[test]
+fn test_generate_refuses_self_signed_token() {
+    // That's a token of the test key that is signed by itself.
+    let (_, _, output) = generate_signed(
+        "self-signed-token",
+        "Milan.efs.json5",
+        &data_filename("signing", "amd-public-key.bin"),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "generate passed: {stderr}");
+    assert!(stderr.contains("is self-signed"), "unexpected error: {stderr}");
+}
+
+#[test]
+fn test_verify_psp_blobs() {
+    let output = common::command()
+        .arg("verify-signatures")
+        .arg("-c")
+        .arg(data_filename("signing", "PspBlobs.efs.json5"))
+        .arg("-B")
+        .arg(data_dirname("signing"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "verify-signatures passed: {stdout}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("3 problem(s) with signatures found"),
+        "unexpected problems: {stdout}"
+    );
+    for expected in [
+        "PSP directory: AmdPublicKey (instance 0): ok (self-signed)",
+        "PSP directory: PspBootloader (instance 0): ok (signed by ",
+        "PSP directory: PspRecoveryBootloader (instance 0): UNSIGNED",
+        "PSP directory: SmuOffChipFirmware8 (instance 0): BAD SIGNATURE by key ",
+        "PSP directory: Abl0 (instance 0): KEY MISMATCH: signing key ",
+    ] {
+        assert!(stdout.contains(expected), "{expected:?} not in {stdout}");
+    }
+}
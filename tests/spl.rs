@@ -0,0 +1,86 @@
+mod common;
+
+use common::{data_dirname, data_filename};
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+fn generate(
+    configuration_name: &str,
+    output_filename: &Path,
+    extra_args: &[&str],
+) -> Output {
+    common::generate(&data_filename("spl", configuration_name), output_filename)
+        .arg("-B")
+        .arg(data_dirname("spl"))
+        .args(extra_args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_generate_rejects_blob_below_spl_table() {
+    let output_filename = common::work_dirname("spl-below").join("image.img");
+    let output = generate("Milan.efs.json5", &output_filename, &[]);
+    assert!(!output.status.success(), "generate passed");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("PSP directory: PspBootloader (instance 0): SPL 0x2 is below the minimum SPL 0x3 of PSP directory: BootloaderSplTable (instance 0)"),
+        "{stderr}"
+    );
+    assert!(!output_filename.exists());
+}
+
+#[test]
+fn test_generate_reports_spl_and_rollback() {
+    let work_dirname = common::work_dirname("spl-rollback");
+    let manifest_filename = work_dirname.join("spl.json5");
+    fs::write(&manifest_filename, "{ PspBootloader: 3 }").unwrap();
+    let output_filename = work_dirname.join("image.img");
+    let output = generate(
+        "Milan-no-table.efs.json5",
+        &output_filename,
+        &["--previous-spl", manifest_filename.to_str().unwrap(), "-v"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "generate failed: {stderr}");
+    assert!(
+        stdout.contains("PSP directory: PspBootloader (instance 0): SPL 0x2"),
+        "{stdout}"
+    );
+    assert!(
+        stderr.contains("WARNING: PspBootloader would roll back from SPL 0x3"),
+        "{stderr}"
+    );
+
+    // The image itself can be the previous one, too.
+    let output = generate(
+        "Milan-no-table.efs.json5",
+        &work_dirname.join("image2.img"),
+        &["--previous-spl", output_filename.to_str().unwrap()],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "generate failed: {stderr}");
+    assert!(!stderr.contains("roll back"), "{stderr}");
+    // Only with -v
+    assert!(!stdout.contains("SPL 0x2"), "{stdout}");
+
+    let output = common::command()
+        .arg("dump")
+        .arg("-i")
+        .arg(&output_filename)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("SPL 0x2"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Info: PSP directory: PspBootloader (instance 0): SPL 0x2"
+        ),
+        "{stderr}"
+    );
+}
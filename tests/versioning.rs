@@ -19,6 +19,7 @@ fn test_token_versioning() {
                 match entry.source {
                     SerdeBhdSource::Implied => {}
                     SerdeBhdSource::BlobFile(_) => {}
+                    SerdeBhdSource::ElfFile(_) => {}
                     SerdeBhdSource::SecondLevelDirectory(_) => {}
                     SerdeBhdSource::ApcbJson(apcb) => {
                         found_match = true;
@@ -48,6 +49,7 @@ fn test_token_versioning_failure() {
                 match entry.source {
                     SerdeBhdSource::Implied => {}
                     SerdeBhdSource::BlobFile(_) => {}
+                    SerdeBhdSource::ElfFile(_) => {}
                     SerdeBhdSource::SecondLevelDirectory(_) => {}
                     SerdeBhdSource::ApcbJson(apcb) => {
                         found_match = true;
@@ -88,6 +90,7 @@ fn test_token_versioning_both_new() {
                 match entry.source {
                     SerdeBhdSource::Implied => {}
                     SerdeBhdSource::BlobFile(_) => {}
+                    SerdeBhdSource::ElfFile(_) => {}
                     SerdeBhdSource::SecondLevelDirectory(_) => {}
                     SerdeBhdSource::ApcbJson(apcb) => {
                         found_match = true;
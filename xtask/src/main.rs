@@ -77,6 +77,7 @@ enum Command {
         #[clap(long)]
         blob_dir: String,
     },
+    /// Generates the JSON schemas for the config
     Schema,
     /// Runs unit tests
     Test {
@@ -290,14 +291,15 @@ fn dump<P: AsRef<Path> + ?Sized>(image: &P, blob_dir: &P) {
     cmd(cargo(), args.split_whitespace()).run().expect("dump successful");
 }
 
-/// Generates a JSON schema for our config.
+/// Generates the JSON schemas for our config: One for all processor
+/// generations and one for each processor generation.
 fn schema() {
-    let out = std::fs::File::create("out/efs.schema.json")
-        .expect("created schema file");
-    cmd(cargo(), ["run", "--manifest-path", "ahib-schema/Cargo.toml", "--"])
-        .stdout_file(out)
-        .run()
-        .expect("generated schema");
+    cmd(
+        cargo(),
+        ["run", "--manifest-path", "ahib-schema/Cargo.toml", "--", "out"],
+    )
+    .run()
+    .expect("generated schema");
 }
 
 /// Runs unit tests.